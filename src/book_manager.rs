@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    builder_supervisor::BuilderSupervisor,
+    metrics,
+    model::{asset_pair::AssetPair, book_validation::ValidationPolicy, order_book::OrderBook},
+    orderbook_builder::TriggerMode,
+    pool_info_provider::pool_info_provider_handle::PoolInfoProviderHandle,
+};
+
+/// Map of managed asset pair to its latest book, `None` until its first book is built
+type LatestBooks = Arc<Mutex<HashMap<AssetPair, Option<OrderBook>>>>;
+
+/// Runs a supervised order book builder per pool and merges their books into a single stream
+/// tagged by asset pair. Pools can be added and removed while running.
+pub struct BookManager {
+    /// Handle used to subscribe and unsubscribe pools
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    /// Owns the builders of every pool
+    supervisor: BuilderSupervisor,
+    latest_books: LatestBooks,
+    /// Sender of the merged stream, dropped on shutdown so the stream ends once drained
+    book_tx: Option<mpsc::UnboundedSender<(AssetPair, OrderBook)>>,
+    book_rx: mpsc::UnboundedReceiver<(AssetPair, OrderBook)>,
+    shutdown: CancellationToken,
+}
+
+impl BookManager {
+    pub fn new(
+        pool_info_provider_handle: PoolInfoProviderHandle,
+        shutdown: CancellationToken,
+    ) -> Self {
+        let (book_tx, book_rx) = mpsc::unbounded_channel();
+
+        BookManager {
+            supervisor: BuilderSupervisor::new(pool_info_provider_handle.clone(), shutdown.clone()),
+            pool_info_provider_handle,
+            latest_books: Arc::new(Mutex::new(HashMap::new())),
+            book_tx: Some(book_tx),
+            book_rx,
+            shutdown,
+        }
+    }
+
+    /// Apply `policy` to books whose node data fails validation
+    pub fn with_validation(mut self, policy: ValidationPolicy) -> Self {
+        self.supervisor = self.supervisor.with_validation(policy);
+        self
+    }
+
+    /// Subscribe to `asset_pair` and start building its books, false if it is already managed or
+    /// the manager has shut down
+    pub fn add_pool(
+        &mut self,
+        asset_pair: &AssetPair,
+        poll_duration: Duration,
+        trigger: TriggerMode,
+        min_rebuild_interval: Duration,
+    ) -> bool {
+        let Some(book_tx) = self.book_tx.clone() else {
+            return false;
+        };
+        if self.shutdown.is_cancelled() {
+            return false;
+        }
+        {
+            let mut latest_books = self.latest_books.lock().unwrap();
+            if latest_books.contains_key(asset_pair) {
+                return false;
+            }
+            latest_books.insert(asset_pair.clone(), None);
+        }
+
+        tracing::info!(pair = %asset_pair, "adding pool");
+        self.pool_info_provider_handle
+            .subscribe_pool_price_updates(asset_pair);
+        let mut orderbook_rx =
+            self.supervisor
+                .supervise(asset_pair, poll_duration, trigger, min_rebuild_interval);
+
+        let asset_pair = asset_pair.clone();
+        let latest_books = self.latest_books.clone();
+        tokio::spawn(async move {
+            while let Some(ob) = orderbook_rx.recv().await {
+                // books still queued once the pool is removed are dropped
+                match latest_books.lock().unwrap().get_mut(&asset_pair) {
+                    Some(latest) => *latest = Some(ob.clone()),
+                    None => break,
+                }
+
+                if book_tx.send((asset_pair.clone(), ob)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        true
+    }
+
+    /// Stop building books for `asset_pair` and release its subscription, which other clients of the
+    /// provider may share. False if it isn't managed.
+    pub fn remove_pool(&mut self, asset_pair: &AssetPair) -> bool {
+        if self
+            .latest_books
+            .lock()
+            .unwrap()
+            .remove(asset_pair)
+            .is_none()
+        {
+            return false;
+        }
+
+        tracing::info!(pair = %asset_pair, "removing pool");
+        self.supervisor.stop(asset_pair);
+        self.pool_info_provider_handle
+            .unsubscribe_pool_price_updates(asset_pair);
+
+        true
+    }
+
+    /// Asset pairs currently managed
+    pub fn pools(&self) -> Vec<AssetPair> {
+        let mut pools: Vec<_> = self.latest_books.lock().unwrap().keys().cloned().collect();
+        pools.sort_by_key(|asset_pair| asset_pair.to_string());
+
+        pools
+    }
+
+    /// Latest book built for `asset_pair`, `None` if it isn't managed or no book has been built yet
+    pub fn latest_book(&self, asset_pair: &AssetPair) -> Option<OrderBook> {
+        self.latest_books
+            .lock()
+            .unwrap()
+            .get(asset_pair)
+            .cloned()
+            .flatten()
+    }
+
+    /// Number of times the builder for `asset_pair` has been restarted
+    pub fn restart_count(&self, asset_pair: &AssetPair) -> u64 {
+        self.supervisor.restart_count(asset_pair)
+    }
+
+    /// Next book built for any pool, `None` once shut down and every book already built has been
+    /// received
+    pub async fn next_book(&mut self) -> Option<(AssetPair, OrderBook)> {
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled(), if self.book_tx.is_some() => {
+                    self.book_tx = None;
+                },
+                book = self.book_rx.recv() => {
+                    metrics::set_queue_depth("orderbooks", self.book_rx.len());
+
+                    return book;
+                },
+            }
+        }
+    }
+}
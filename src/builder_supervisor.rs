@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::mpsc, time::sleep};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    metrics,
+    model::{asset_pair::AssetPair, book_validation::ValidationPolicy, order_book::OrderBook},
+    orderbook_builder::{OrderBookBuilder, TriggerMode},
+    pool_info_provider::pool_info_provider_handle::PoolInfoProviderHandle,
+};
+
+const MIN_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// A builder running for this long before failing is restarted after the minimum backoff again
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Delay before restarting a failed builder, doubling on every consecutive failure
+#[derive(Clone, Copy, Debug)]
+struct RestartBackoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl RestartBackoff {
+    fn new(min: Duration, max: Duration) -> Self {
+        RestartBackoff {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    /// Delay before the next restart
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
+
+/// Owns the order book builders of every pool, restarting them with backoff whenever they panic or
+/// exit. Each pool's receiver stays open across restarts and only closes on shutdown.
+pub struct BuilderSupervisor {
+    /// Handle the builders fetch prices and liquidity from
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    /// Bounds of the delay before restarting a failed builder
+    restart_backoff: RestartBackoff,
+    /// What the builders do with books whose node data fails validation
+    validation: ValidationPolicy,
+    /// Number of restarts per pool
+    restarts: Arc<Mutex<HashMap<AssetPair, u64>>>,
+    /// Stops the builder of a single pool
+    stop_tokens: Mutex<HashMap<AssetPair, CancellationToken>>,
+    /// Stops every builder
+    shutdown: CancellationToken,
+}
+
+impl BuilderSupervisor {
+    pub fn new(
+        pool_info_provider_handle: PoolInfoProviderHandle,
+        shutdown: CancellationToken,
+    ) -> Self {
+        BuilderSupervisor {
+            pool_info_provider_handle,
+            restart_backoff: RestartBackoff::new(MIN_RESTART_BACKOFF, MAX_RESTART_BACKOFF),
+            validation: ValidationPolicy::default(),
+            restarts: Arc::new(Mutex::new(HashMap::new())),
+            stop_tokens: Mutex::new(HashMap::new()),
+            shutdown,
+        }
+    }
+
+    /// Restart failed builders after `min`, doubling up to `max` on consecutive failures
+    pub fn with_restart_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.restart_backoff = RestartBackoff::new(min, max);
+        self
+    }
+
+    /// Apply `policy` to books whose node data fails validation
+    pub fn with_validation(mut self, policy: ValidationPolicy) -> Self {
+        self.validation = policy;
+        self
+    }
+
+    /// Start a supervised builder for `asset_pair` and return the channel its books are published on,
+    /// replacing any builder already supervised for it. The channel closes once the builder is
+    /// stopped or on shutdown.
+    pub fn supervise(
+        &self,
+        asset_pair: &AssetPair,
+        poll_duration: Duration,
+        trigger: TriggerMode,
+        min_rebuild_interval: Duration,
+    ) -> mpsc::UnboundedReceiver<OrderBook> {
+        let (book_tx, book_rx) = mpsc::unbounded_channel();
+        self.restarts.lock().unwrap().insert(asset_pair.clone(), 0);
+        let stop = self.shutdown.child_token();
+        if let Some(previous) = self
+            .stop_tokens
+            .lock()
+            .unwrap()
+            .insert(asset_pair.clone(), stop.clone())
+        {
+            previous.cancel();
+        }
+
+        let asset_pair = asset_pair.clone();
+        let pool_info_provider_handle = self.pool_info_provider_handle.clone();
+        let mut restart_backoff = self.restart_backoff;
+        let validation = self.validation;
+        let restarts = self.restarts.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let orderbook_builder = OrderBookBuilder::new(
+                    asset_pair.clone(),
+                    pool_info_provider_handle.clone(),
+                    poll_duration,
+                    trigger,
+                    min_rebuild_interval,
+                    book_tx.clone(),
+                )
+                .with_validation(validation);
+                let started_at = Instant::now();
+                let builder_stop = stop.clone();
+                let result = tokio::spawn(
+                    async move {
+                        orderbook_builder.run(builder_stop).await;
+                    }
+                    .instrument(tracing::info_span!("order_book_builder", pair = %asset_pair)),
+                )
+                .await;
+
+                if stop.is_cancelled() || book_tx.is_closed() {
+                    break;
+                }
+
+                match result {
+                    Err(e) if e.is_panic() => {
+                        tracing::error!(pair = %asset_pair, "order book builder panicked")
+                    }
+                    _ => tracing::warn!(pair = %asset_pair, "order book builder exited"),
+                }
+
+                if started_at.elapsed() >= STABLE_AFTER {
+                    restart_backoff.reset();
+                }
+                let delay = restart_backoff.next_delay();
+                tracing::info!(
+                    pair = %asset_pair,
+                    delay = ?delay,
+                    "restarting order book builder"
+                );
+
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = sleep(delay) => {},
+                }
+
+                *restarts
+                    .lock()
+                    .unwrap()
+                    .entry(asset_pair.clone())
+                    .or_default() += 1;
+                metrics::inc_builder_restarts(&asset_pair);
+            }
+        });
+
+        book_rx
+    }
+
+    /// Stop the builder for `asset_pair` for good, false if it isn't supervised
+    pub fn stop(&self, asset_pair: &AssetPair) -> bool {
+        let Some(stop) = self.stop_tokens.lock().unwrap().remove(asset_pair) else {
+            return false;
+        };
+        stop.cancel();
+        self.restarts.lock().unwrap().remove(asset_pair);
+        self.pool_info_provider_handle
+            .health_monitor()
+            .unregister_builder(asset_pair);
+
+        true
+    }
+
+    /// Number of times the builder for `asset_pair` has been restarted
+    pub fn restart_count(&self, asset_pair: &AssetPair) -> u64 {
+        self.restarts
+            .lock()
+            .unwrap()
+            .get(asset_pair)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Number of restarts of every supervised builder
+    pub fn restart_counts(&self) -> HashMap<AssetPair, u64> {
+        self.restarts.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RestartBackoff;
+
+    #[test]
+    fn test_restart_backoff() {
+        let mut backoff = RestartBackoff::new(Duration::from_secs(1), Duration::from_secs(5));
+
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 5, 5], delays);
+
+        backoff.reset();
+        assert_eq!(Duration::from_secs(1), backoff.next_delay());
+    }
+}
@@ -0,0 +1,623 @@
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
+
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use tokio::{sync::mpsc, time::interval};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    model::{
+        asset_pair::AssetPair, common::Tick, json_rpc::JsonRpcResponse, pool_price::PoolPrice,
+        price_update::PriceUpdate, timestamp::Timestamp,
+    },
+    pool_info_provider::pool_info_provider_handle::PoolInfoProviderHandle,
+    transport::journal::{JournalEntry, JournalEvent},
+    util::pool_price,
+};
+
+/// How often candles whose interval has ended are closed without waiting for the next update
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Length of a candle, a whole number of seconds. Candles start at multiples of it since the unix
+/// epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CandleInterval(Duration);
+
+impl CandleInterval {
+    /// `None` unless `duration` is a whole number of seconds, at least one, and its microseconds
+    /// fit in a u64
+    pub fn new(duration: Duration) -> Option<Self> {
+        (duration.subsec_nanos() == 0
+            && duration.as_secs() > 0
+            && duration.as_micros() <= u64::MAX as u128)
+            .then_some(CandleInterval(duration))
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+
+    fn micros(&self) -> u64 {
+        self.0.as_micros() as u64
+    }
+
+    /// Start of the candle `unix_us` falls in
+    fn start_of(&self, unix_us: u64) -> u64 {
+        unix_us - unix_us % self.micros()
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = String;
+
+    /// Parse a number of seconds, minutes or hours, ie. "1s", "5m" or "1h"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid candle interval {:?}, expected ie. 1s, 5m or 1h", s);
+        let s = s.trim();
+        let (count, unit) = s.split_at(s.len().saturating_sub(1));
+        let unit_secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            _ => return Err(invalid()),
+        };
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        let secs = count.checked_mul(unit_secs).ok_or_else(invalid)?;
+
+        CandleInterval::new(Duration::from_secs(secs)).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for CandleInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        if secs.is_multiple_of(3600) {
+            write!(f, "{}h", secs / 3600)
+        } else if secs.is_multiple_of(60) {
+            write!(f, "{}m", secs / 60)
+        } else {
+            write!(f, "{}s", secs)
+        }
+    }
+}
+
+impl Serialize for CandleInterval {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Open, high, low and close pool price over an interval, in whole units of the quote asset, with
+/// the ticks they were at
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Candle {
+    pub asset_pair: AssetPair,
+    pub interval: CandleInterval,
+    /// Start of the candle in microseconds since the unix epoch
+    pub open_time_us: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub open_tick: Tick,
+    pub high_tick: Tick,
+    pub low_tick: Tick,
+    pub close_tick: Tick,
+    /// Ticks the pool moved by within the candle, summed over every update, including the move
+    /// from the update before the candle
+    pub ticks_moved: u64,
+    /// Price updates within the candle
+    pub updates: u64,
+}
+
+impl Candle {
+    fn new(
+        asset_pair: &AssetPair,
+        interval: CandleInterval,
+        open_time_us: u64,
+        tick: Tick,
+        price: f64,
+        ticks_moved: u64,
+    ) -> Self {
+        Candle {
+            asset_pair: asset_pair.clone(),
+            interval,
+            open_time_us,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            open_tick: tick,
+            high_tick: tick,
+            low_tick: tick,
+            close_tick: tick,
+            ticks_moved,
+            updates: 1,
+        }
+    }
+
+    /// End of the candle in microseconds since the unix epoch, exclusive
+    pub fn close_time_us(&self) -> u64 {
+        self.open_time_us.saturating_add(self.interval.micros())
+    }
+
+    fn apply(&mut self, tick: Tick, price: f64, ticks_moved: u64) {
+        if price > self.high {
+            self.high = price;
+            self.high_tick = tick;
+        }
+        if price < self.low {
+            self.low = price;
+            self.low_tick = tick;
+        }
+        self.close = price;
+        self.close_tick = tick;
+        self.ticks_moved += ticks_moved;
+        self.updates += 1;
+    }
+}
+
+/// The latest candle of a pool and interval
+struct LatestCandle {
+    candle: Candle,
+    /// Whether the candle has been closed and published
+    closed: bool,
+}
+
+/// Candles of every pool for each interval, built from price updates. A candle is closed once an
+/// update arrives for a later candle or its interval has ended. Intervals without updates have no
+/// candle and updates for a candle already closed are dropped.
+pub struct CandleAggregator {
+    intervals: Vec<CandleInterval>,
+    latest: HashMap<(AssetPair, CandleInterval), LatestCandle>,
+    /// Tick of the last update of each pool
+    last_tick: HashMap<AssetPair, Tick>,
+}
+
+impl CandleAggregator {
+    pub fn new(intervals: Vec<CandleInterval>) -> Self {
+        CandleAggregator {
+            intervals,
+            latest: HashMap::new(),
+            last_tick: HashMap::new(),
+        }
+    }
+
+    /// Record a price of `asset_pair` at `unix_us` microseconds since the unix epoch and return
+    /// the candles it closes
+    pub fn observe(
+        &mut self,
+        asset_pair: &AssetPair,
+        tick: Tick,
+        price: f64,
+        unix_us: u64,
+    ) -> Vec<Candle> {
+        let ticks_moved = self
+            .last_tick
+            .insert(asset_pair.clone(), tick)
+            .map_or(0, |last| last.abs_diff(tick) as u64);
+
+        let mut closed = Vec::new();
+        for interval in self.intervals.iter() {
+            let open_time_us = interval.start_of(unix_us);
+            let key = (asset_pair.clone(), *interval);
+            match self.latest.get_mut(&key) {
+                Some(latest) if latest.candle.open_time_us == open_time_us => {
+                    if !latest.closed {
+                        latest.candle.apply(tick, price, ticks_moved);
+                    }
+                }
+                Some(latest) if latest.candle.open_time_us > open_time_us => {}
+                _ => {
+                    let candle = Candle::new(
+                        asset_pair,
+                        *interval,
+                        open_time_us,
+                        tick,
+                        price,
+                        ticks_moved,
+                    );
+                    let previous = self.latest.insert(
+                        key,
+                        LatestCandle {
+                            candle,
+                            closed: false,
+                        },
+                    );
+                    if let Some(previous) = previous.filter(|previous| !previous.closed) {
+                        closed.push(previous.candle);
+                    }
+                }
+            }
+        }
+
+        closed
+    }
+
+    /// Record the price of `update` and return the candles it closes. Updates republished as
+    /// stale and updates without a price are ignored.
+    pub fn update(&mut self, update: &PriceUpdate) -> Vec<Candle> {
+        if update.stale {
+            return Vec::new();
+        }
+        let Some(price) = pool_price(&update.sqrt_price, update.tick, &update.asset_pair) else {
+            return Vec::new();
+        };
+
+        self.observe(
+            &update.asset_pair,
+            update.tick,
+            price,
+            update.received_at.unix_micros(),
+        )
+    }
+
+    /// Close every candle whose interval has ended by `unix_us`
+    pub fn close_due(&mut self, unix_us: u64) -> Vec<Candle> {
+        let mut closed: Vec<Candle> = self
+            .latest
+            .values_mut()
+            .filter(|latest| !latest.closed && latest.candle.close_time_us() <= unix_us)
+            .map(|latest| {
+                latest.closed = true;
+                latest.candle.clone()
+            })
+            .collect();
+        closed.sort_by_key(|candle| (candle.open_time_us, candle.interval));
+
+        closed
+    }
+
+    /// Record the price updates received in a journal recorded by a `RecordingTransport` and
+    /// return the candles they close. Entries without a wall clock time are skipped.
+    pub fn backfill(&mut self, entries: &[JournalEntry]) -> Vec<Candle> {
+        let mut closed = Vec::new();
+        for (asset_pair, tick, price, unix_us) in journal_prices(entries) {
+            closed.extend(self.observe(&asset_pair, tick, price, unix_us));
+        }
+
+        closed
+    }
+}
+
+/// Pool prices received in a journal with the time they were received at. Updates are attributed
+/// to pools by the subscription id the node answered each subscription request with.
+fn journal_prices(entries: &[JournalEntry]) -> Vec<(AssetPair, Tick, f64, u64)> {
+    let mut requests: HashMap<String, AssetPair> = HashMap::new();
+    let mut subscriptions: HashMap<String, AssetPair> = HashMap::new();
+    let mut prices = Vec::new();
+    let mut untimed = 0;
+
+    for entry in entries.iter() {
+        match &entry.event {
+            JournalEvent::WsSent { message } => {
+                let Ok(request) = serde_json::from_str::<Value>(message) else {
+                    continue;
+                };
+                if request["method"] != "cf_subscribe_pool_price" {
+                    continue;
+                }
+                let params = &request["params"];
+                if let (Some(id), Some(from), Some(to)) = (
+                    request["id"].as_str(),
+                    params["from_asset"].as_str(),
+                    params["to_asset"].as_str(),
+                ) {
+                    requests.insert(
+                        id.to_string(),
+                        AssetPair::new(from.to_string(), to.to_string()),
+                    );
+                }
+            }
+            JournalEvent::WsReceived { message } => {
+                if let Ok(pool_price_update) = serde_json::from_str::<PoolPrice>(message) {
+                    let result = pool_price_update.params.result;
+                    let Some(asset_pair) =
+                        subscriptions.get(&pool_price_update.params.subscription)
+                    else {
+                        continue;
+                    };
+                    let Some(unix_us) = entry.unix_us else {
+                        untimed += 1;
+
+                        continue;
+                    };
+                    if let Some(price) = pool_price(&result.sqrt_price, result.tick, asset_pair) {
+                        prices.push((asset_pair.clone(), result.tick, price, unix_us));
+                    }
+                } else if let Ok(response) = serde_json::from_str::<JsonRpcResponse>(message) {
+                    if let Some(asset_pair) = requests.get(&response.id) {
+                        subscriptions.insert(response.result, asset_pair.clone());
+                    }
+                }
+            }
+            JournalEvent::Rest { .. } => {}
+        }
+    }
+
+    if untimed > 0 {
+        tracing::warn!(
+            updates = untimed,
+            "skipped price updates recorded without a wall clock time"
+        );
+    }
+
+    prices
+}
+
+/// An enduring thread which builds candles from the price updates of every pool and sends each
+/// candle downstream once it is closed
+pub struct CandleBuilder {
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    pools: Vec<AssetPair>,
+    aggregator: CandleAggregator,
+    candle_tx: mpsc::UnboundedSender<Candle>,
+}
+
+/// Create and start a candle builder continuing from `aggregator`, which may have been backfilled,
+/// and return the channel it publishes closed candles on. The channel closes once `shutdown` is
+/// cancelled.
+pub fn create_and_start_candle_builder(
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    pools: Vec<AssetPair>,
+    aggregator: CandleAggregator,
+    shutdown: CancellationToken,
+) -> mpsc::UnboundedReceiver<Candle> {
+    let (candle_tx, candle_rx) = mpsc::unbounded_channel();
+    let mut builder = CandleBuilder::new(pool_info_provider_handle, pools, aggregator, candle_tx);
+
+    tokio::spawn(async move {
+        builder.run(shutdown).await;
+    });
+
+    candle_rx
+}
+
+impl CandleBuilder {
+    pub fn new(
+        pool_info_provider_handle: PoolInfoProviderHandle,
+        pools: Vec<AssetPair>,
+        aggregator: CandleAggregator,
+        candle_tx: mpsc::UnboundedSender<Candle>,
+    ) -> Self {
+        CandleBuilder {
+            pool_info_provider_handle,
+            pools,
+            aggregator,
+            candle_tx,
+        }
+    }
+
+    fn publish(&self, candles: Vec<Candle>) -> bool {
+        candles
+            .into_iter()
+            .all(|candle| self.candle_tx.send(candle).is_ok())
+    }
+
+    /// Enduring loop, aggregating every price update until `shutdown` is cancelled
+    pub async fn run(&mut self, shutdown: CancellationToken) {
+        let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+        self.pool_info_provider_handle
+            .forward_pool_price_updates(&self.pools, update_tx)
+            .await;
+        let mut close_check = interval(CLOSE_CHECK_INTERVAL);
+
+        loop {
+            let closed = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    tracing::info!("stopping candle builder");
+
+                    break;
+                },
+                _ = close_check.tick() => {
+                    self.aggregator.close_due(Timestamp::now().unix_micros())
+                },
+                update = update_rx.recv() => {
+                    let Some((_, update)) = update else {
+                        tracing::warn!("price updates of every pool have stopped");
+
+                        break;
+                    };
+
+                    match update {
+                        Some(update) => self.aggregator.update(&update),
+                        None => Vec::new(),
+                    }
+                }
+            };
+
+            if !self.publish(closed) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::{
+        model::asset_pair::AssetPair,
+        transport::journal::{JournalEntry, JournalEvent},
+    };
+
+    use super::{CandleAggregator, CandleInterval};
+
+    fn btc_usdc() -> AssetPair {
+        AssetPair::new("BTC".to_string(), "USDC".to_string())
+    }
+
+    #[test]
+    fn test_parse_candle_interval() {
+        for (s, secs) in [
+            ("1s", 1),
+            ("1m", 60),
+            ("5m", 300),
+            ("1h", 3600),
+            ("90s", 90),
+        ] {
+            let interval: CandleInterval = s.parse().unwrap();
+            assert_eq!(Duration::from_secs(secs), interval.duration());
+        }
+        assert_eq!("90s", "90s".parse::<CandleInterval>().unwrap().to_string());
+        assert_eq!("2h", "120m".parse::<CandleInterval>().unwrap().to_string());
+
+        for s in ["", "0s", "1d", "m", "-1m", "1.5m"] {
+            assert!(s.parse::<CandleInterval>().is_err(), "{:?}", s);
+        }
+
+        // too many seconds, or microseconds, for a u64
+        for s in [
+            "5124095576030432h",
+            "288230376151711744s",
+            "18446744073710s",
+        ] {
+            assert!(s.parse::<CandleInterval>().is_err(), "{:?}", s);
+        }
+
+        // the longest interval still buckets prices
+        let longest: CandleInterval = "18446744073709s".parse().unwrap();
+        let mut aggregator = CandleAggregator::new(vec![longest]);
+        let btc_usdc = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        assert!(aggregator
+            .observe(&btc_usdc, 57040, 30_000.0, 1_700_000_000_000_000)
+            .is_empty());
+        assert_eq!(1, aggregator.close_due(u64::MAX).len());
+    }
+
+    #[test]
+    fn test_candles() {
+        let minute: CandleInterval = "1m".parse().unwrap();
+        let mut aggregator = CandleAggregator::new(vec!["1s".parse().unwrap(), minute]);
+        let at = |secs: f64| (secs * 1_000_000.0) as u64;
+
+        assert!(aggregator
+            .observe(&btc_usdc(), 100, 1.0, at(60.1))
+            .is_empty());
+        assert!(aggregator
+            .observe(&btc_usdc(), 110, 1.5, at(60.2))
+            .is_empty());
+        assert!(aggregator
+            .observe(&btc_usdc(), 90, 0.5, at(60.3))
+            .is_empty());
+
+        // the next second closes the 1s candle only
+        let closed = aggregator.observe(&btc_usdc(), 95, 0.8, at(61.5));
+        assert_eq!(1, closed.len());
+        let candle = &closed[0];
+        assert_eq!(at(60.0), candle.open_time_us);
+        assert_eq!(
+            (1.0, 1.5, 0.5, 0.5),
+            (candle.open, candle.high, candle.low, candle.close)
+        );
+        assert_eq!(
+            (100, 110, 90, 90),
+            (
+                candle.open_tick,
+                candle.high_tick,
+                candle.low_tick,
+                candle.close_tick
+            )
+        );
+        assert_eq!(30, candle.ticks_moved);
+        assert_eq!(3, candle.updates);
+
+        // candles are closed once their interval has ended without another update
+        let closed = aggregator.close_due(at(120.0));
+        assert_eq!(2, closed.len());
+        let candle = closed
+            .iter()
+            .find(|candle| candle.interval == minute)
+            .unwrap();
+        assert_eq!(
+            (1.0, 1.5, 0.5, 0.8),
+            (candle.open, candle.high, candle.low, candle.close)
+        );
+        assert_eq!(35, candle.ticks_moved);
+        assert_eq!(4, candle.updates);
+
+        // a late update gets a 1s candle of its own but doesn't reopen the closed minute
+        assert!(aggregator
+            .observe(&btc_usdc(), 96, 0.9, at(119.0))
+            .is_empty());
+        let closed = aggregator.close_due(at(200.0));
+        assert_eq!(1, closed.len());
+        assert_eq!((at(119.0), 1), (closed[0].open_time_us, closed[0].updates));
+        assert_ne!(minute, closed[0].interval);
+
+        // an interval without updates has no candle
+        let closed = aggregator.observe(&btc_usdc(), 100, 1.0, at(240.0));
+        assert!(closed.is_empty());
+        let closed = aggregator.close_due(at(300.0));
+        assert_eq!(
+            vec![at(240.0), at(240.0)],
+            closed.iter().map(|c| c.open_time_us).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_backfill_from_journal() {
+        let entry = |unix_us: Option<u64>, event: JournalEvent| JournalEntry {
+            elapsed_us: 0,
+            unix_us,
+            event,
+        };
+        let price = |tick: i32| JournalEvent::WsReceived {
+            message: json!({
+                "jsonrpc": "2.0",
+                "method": "cf_subscribe_pool_price",
+                "params": {
+                    "subscription": "sub-1",
+                    "result": {"price": "0x0", "sqrt_price": "0x0", "tick": tick},
+                },
+            })
+            .to_string(),
+        };
+        let entries = vec![
+            entry(
+                Some(0),
+                JournalEvent::WsSent {
+                    message: json!({
+                        "jsonrpc": "2.0",
+                        "id": "7",
+                        "method": "cf_subscribe_pool_price",
+                        "params": {"from_asset": "BTC", "to_asset": "USDC"},
+                    })
+                    .to_string(),
+                },
+            ),
+            entry(
+                Some(1),
+                JournalEvent::WsReceived {
+                    message: r#"{"jsonrpc":"2.0","id":"7","result":"sub-1"}"#.to_string(),
+                },
+            ),
+            entry(Some(1_000_000), price(57040)),
+            entry(None, price(57000)),
+            entry(Some(1_500_000), price(57050)),
+            entry(Some(2_000_000), price(57045)),
+        ];
+
+        let mut aggregator = CandleAggregator::new(vec!["1s".parse().unwrap()]);
+        let closed = aggregator.backfill(&entries);
+        assert_eq!(1, closed.len());
+        assert_eq!(btc_usdc(), closed[0].asset_pair);
+        assert_eq!(1_000_000, closed[0].open_time_us);
+        assert_eq!(
+            (57040, 57050, 57040, 57050),
+            (
+                closed[0].open_tick,
+                closed[0].high_tick,
+                closed[0].low_tick,
+                closed[0].close_tick
+            )
+        );
+        assert_eq!(2, closed[0].updates);
+
+        // the last candle stays open for live updates to continue
+        assert_eq!(1, aggregator.close_due(3_000_000).len());
+    }
+}
@@ -0,0 +1,605 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use primitive_types::U256;
+use serde::Serialize;
+use tokio::{sync::mpsc, time::interval};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    metrics,
+    model::{
+        asset_pair::AssetPair, common::Tick, liquidity::Liquidity, order_book::Side,
+        price_update::PriceUpdate, timestamp::Timestamp,
+    },
+    pool_info_provider::pool_info_provider_handle::PoolInfoProviderHandle,
+};
+
+/// What nodes disagree about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DivergenceKind {
+    /// Some nodes sent no price update for a block the others did
+    MissingUpdate,
+    Tick,
+    SqrtPrice,
+    /// Range order liquidity differs at a tick
+    RangeLiquidity,
+    /// Limit order amounts differ at a tick
+    LimitLiquidity,
+}
+
+impl DivergenceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DivergenceKind::MissingUpdate => "missing_update",
+            DivergenceKind::Tick => "tick",
+            DivergenceKind::SqrtPrice => "sqrt_price",
+            DivergenceKind::RangeLiquidity => "range_liquidity",
+            DivergenceKind::LimitLiquidity => "limit_liquidity",
+        }
+    }
+}
+
+/// The value one node reported, `None` if it reported nothing
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NodeValue {
+    pub node: String,
+    pub value: Option<String>,
+}
+
+/// Nodes disagreeing about a pool at a block
+#[derive(Clone, Debug, Serialize)]
+pub struct DivergenceReport {
+    pub asset_pair: AssetPair,
+    pub block_number: u64,
+    pub block_hash: Option<String>,
+    pub kind: DivergenceKind,
+    /// Side of the limit orders which differ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub side: Option<Side>,
+    /// First tick at which liquidity differs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick: Option<Tick>,
+    /// Number of ticks at which liquidity differs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mismatched_ticks: Option<usize>,
+    pub values: Vec<NodeValue>,
+    pub detected_at: Timestamp,
+}
+
+impl DivergenceReport {
+    fn new(
+        asset_pair: &AssetPair,
+        block_number: u64,
+        block_hash: &Option<String>,
+        kind: DivergenceKind,
+        values: Vec<NodeValue>,
+    ) -> Self {
+        DivergenceReport {
+            asset_pair: asset_pair.clone(),
+            block_number,
+            block_hash: block_hash.clone(),
+            kind,
+            side: None,
+            tick: None,
+            mismatched_ticks: None,
+            values,
+            detected_at: Timestamp::now(),
+        }
+    }
+}
+
+/// Hex amounts compared by value, so "0x0" and "0x00" agree. Unparseable amounts compare as written.
+fn normalise_amount(amount: &str) -> String {
+    match U256::from_str_radix(amount.trim_start_matches("0x"), 16) {
+        Ok(amount) => format!("{:#x}", amount),
+        Err(_) => amount.to_string(),
+    }
+}
+
+/// Whether every value is the same
+fn all_equal<T: PartialEq>(values: &[T]) -> bool {
+    values.windows(2).all(|w| w[0] == w[1])
+}
+
+/// Compare the price updates each node sent for a block, `None` for nodes which sent none
+pub fn compare_price_updates(
+    asset_pair: &AssetPair,
+    block_number: u64,
+    updates: &[(String, Option<PriceUpdate>)],
+) -> Vec<DivergenceReport> {
+    let mut reports = Vec::new();
+    let block_hash = updates
+        .iter()
+        .find_map(|(_, update)| update.as_ref()?.block_hash.clone());
+
+    let values = |value: fn(&PriceUpdate) -> String| -> Vec<NodeValue> {
+        updates
+            .iter()
+            .map(|(node, update)| NodeValue {
+                node: node.clone(),
+                value: update.as_ref().map(value),
+            })
+            .collect()
+    };
+
+    if updates.iter().any(|(_, update)| update.is_none()) {
+        reports.push(DivergenceReport::new(
+            asset_pair,
+            block_number,
+            &block_hash,
+            DivergenceKind::MissingUpdate,
+            values(|update| update.tick.to_string()),
+        ));
+    }
+
+    let present: Vec<_> = updates
+        .iter()
+        .filter_map(|(_, update)| update.as_ref())
+        .collect();
+    let ticks: Vec<_> = present.iter().map(|update| update.tick).collect();
+    if !all_equal(&ticks) {
+        reports.push(DivergenceReport::new(
+            asset_pair,
+            block_number,
+            &block_hash,
+            DivergenceKind::Tick,
+            values(|update| update.tick.to_string()),
+        ));
+    }
+    let sqrt_prices: Vec<_> = present
+        .iter()
+        .map(|update| normalise_amount(&update.sqrt_price))
+        .collect();
+    if !all_equal(&sqrt_prices) {
+        reports.push(DivergenceReport::new(
+            asset_pair,
+            block_number,
+            &block_hash,
+            DivergenceKind::SqrtPrice,
+            values(|update| update.sqrt_price.clone()),
+        ));
+    }
+
+    reports
+}
+
+/// Report the ticks at which the nodes' amounts differ, a tick missing from a node's book counts as zero
+fn compare_ticks(
+    nodes: &[&String],
+    amounts: &[BTreeMap<Tick, String>],
+    report: impl FnOnce(Tick, usize, Vec<NodeValue>) -> DivergenceReport,
+) -> Option<DivergenceReport> {
+    let zero = normalise_amount("0x0");
+    let ticks: BTreeSet<Tick> = amounts.iter().flat_map(|a| a.keys().copied()).collect();
+
+    let amounts_at = |tick: &Tick| -> Vec<&String> {
+        amounts
+            .iter()
+            .map(|a| a.get(tick).unwrap_or(&zero))
+            .collect()
+    };
+    let mismatched: Vec<Tick> = ticks
+        .iter()
+        .filter(|tick| !all_equal(&amounts_at(tick)))
+        .copied()
+        .collect();
+
+    let first = *mismatched.first()?;
+    let values = nodes
+        .iter()
+        .zip(amounts_at(&first))
+        .map(|(node, amount)| NodeValue {
+            node: node.to_string(),
+            value: Some(amount.clone()),
+        })
+        .collect();
+
+    Some(report(first, mismatched.len(), values))
+}
+
+/// Compare the `cf_pool_liquidity` results each node returned at a block, nodes whose request failed
+/// are left out
+pub fn compare_liquidity(
+    asset_pair: &AssetPair,
+    block_number: u64,
+    block_hash: &Option<String>,
+    results: &[(String, Option<Liquidity>)],
+) -> Vec<DivergenceReport> {
+    let results: Vec<_> = results
+        .iter()
+        .filter_map(|(node, liquidity)| Some((node, liquidity.as_ref()?)))
+        .collect();
+    if results.len() < 2 {
+        return Vec::new();
+    }
+    let nodes: Vec<_> = results.iter().map(|(node, _)| *node).collect();
+
+    let report = |kind: DivergenceKind, side: Option<Side>| {
+        move |tick, mismatched_ticks, values| DivergenceReport {
+            side,
+            tick: Some(tick),
+            mismatched_ticks: Some(mismatched_ticks),
+            ..DivergenceReport::new(asset_pair, block_number, block_hash, kind, values)
+        }
+    };
+
+    let mut reports = Vec::new();
+
+    let range: Vec<BTreeMap<Tick, String>> = results
+        .iter()
+        .map(|(_, liquidity)| {
+            liquidity
+                .result
+                .range_orders
+                .iter()
+                .map(|order| (order.tick, normalise_amount(&order.liquidity)))
+                .collect()
+        })
+        .collect();
+    reports.extend(compare_ticks(
+        &nodes,
+        &range,
+        report(DivergenceKind::RangeLiquidity, None),
+    ));
+
+    for side in [Side::Buy, Side::Sell] {
+        let limit: Vec<BTreeMap<Tick, String>> = results
+            .iter()
+            .map(|(_, liquidity)| {
+                let orders = match side {
+                    Side::Buy => &liquidity.result.limit_orders.bids,
+                    Side::Sell => &liquidity.result.limit_orders.asks,
+                };
+                orders
+                    .iter()
+                    .map(|order| (order.tick, normalise_amount(&order.amount)))
+                    .collect()
+            })
+            .collect();
+        reports.extend(compare_ticks(
+            &nodes,
+            &limit,
+            report(DivergenceKind::LimitLiquidity, Some(side)),
+        ));
+    }
+
+    reports
+}
+
+/// Settings for a `ConsistencyChecker`
+#[derive(Clone, Debug)]
+pub struct ConsistencyOptions {
+    /// Time to wait for every node's update for a block before reporting missing updates
+    pub block_timeout: Duration,
+    /// Minimum time between liquidity comparisons per pool
+    pub liquidity_interval: Duration,
+}
+
+/// Price updates for a block, as they arrive from each node
+struct PendingBlock {
+    first_seen: Instant,
+    updates: Vec<Option<PriceUpdate>>,
+}
+
+/// An enduring thread which compares what several nodes report for the same pools and sends a
+/// `DivergenceReport` downstream whenever they disagree.
+///
+/// Price updates are matched by block number, updates without one can't be compared and are ignored.
+/// Liquidity is compared at the hash of a block every node has sent an update for, at most once per
+/// `liquidity_interval` per pool.
+pub struct ConsistencyChecker {
+    /// A provider connected to each node, with the node's name
+    nodes: Vec<(String, PoolInfoProviderHandle)>,
+    pools: Vec<AssetPair>,
+    options: ConsistencyOptions,
+    /// Blocks not yet seen from every node
+    pending: HashMap<(AssetPair, u64), PendingBlock>,
+    /// Highest block compared per pool, later arrivals for earlier blocks are ignored
+    compared: HashMap<AssetPair, u64>,
+    /// Time of the last liquidity comparison per pool
+    liquidity_checked_at: HashMap<AssetPair, Instant>,
+    report_tx: mpsc::UnboundedSender<DivergenceReport>,
+}
+
+impl ConsistencyChecker {
+    pub fn new(
+        nodes: Vec<(String, PoolInfoProviderHandle)>,
+        pools: Vec<AssetPair>,
+        options: ConsistencyOptions,
+        report_tx: mpsc::UnboundedSender<DivergenceReport>,
+    ) -> Self {
+        ConsistencyChecker {
+            nodes,
+            pools,
+            options,
+            pending: HashMap::new(),
+            compared: HashMap::new(),
+            liquidity_checked_at: HashMap::new(),
+            report_tx,
+        }
+    }
+
+    /// Forward every node's price updates for every pool, tagged with the node's index
+    async fn forward_price_updates(&self, update_tx: mpsc::UnboundedSender<(usize, PriceUpdate)>) {
+        for (index, (name, handle)) in self.nodes.iter().enumerate() {
+            for asset_pair in self.pools.iter() {
+                handle.subscribe_pool_price_updates(asset_pair);
+                let Some(mut price_update_rx) =
+                    handle.get_pool_price_update_stream(asset_pair).await
+                else {
+                    tracing::error!(pair = %asset_pair, node = %name, "no price updates");
+
+                    continue;
+                };
+
+                let update_tx = update_tx.clone();
+                tokio::spawn(async move {
+                    while let Some(update) = price_update_rx.recv().await {
+                        if update_tx.send((index, update)).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    fn send_reports(&self, reports: Vec<DivergenceReport>) {
+        for report in reports {
+            metrics::inc_node_divergences(&report.asset_pair, report.kind.as_str());
+            let _ = self.report_tx.send(report);
+        }
+    }
+
+    /// Compare a block's price updates, and liquidity if it is time to
+    fn compare_block(&mut self, asset_pair: AssetPair, block_number: u64, block: PendingBlock) {
+        let compared = self.compared.entry(asset_pair.clone()).or_default();
+        *compared = (*compared).max(block_number);
+
+        let updates: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(name, _)| name.clone())
+            .zip(block.updates)
+            .collect();
+        self.send_reports(compare_price_updates(&asset_pair, block_number, &updates));
+
+        let complete = updates.iter().all(|(_, update)| update.is_some());
+        let block_hash = updates
+            .iter()
+            .find_map(|(_, update)| update.as_ref()?.block_hash.clone());
+        let due = self
+            .liquidity_checked_at
+            .get(&asset_pair)
+            .is_none_or(|at| at.elapsed() >= self.options.liquidity_interval);
+        let Some(block_hash) = block_hash.filter(|_| complete && due) else {
+            return;
+        };
+        self.liquidity_checked_at
+            .insert(asset_pair.clone(), Instant::now());
+
+        let nodes = self.nodes.clone();
+        let report_tx = self.report_tx.clone();
+        tokio::spawn(async move {
+            let results = join_all(nodes.iter().map(|(name, handle)| async {
+                let liquidity = handle.get_pool_liquidity_at(&asset_pair, &block_hash).await;
+                (name.clone(), liquidity)
+            }))
+            .await;
+
+            let reports = compare_liquidity(&asset_pair, block_number, &Some(block_hash), &results);
+            for report in reports {
+                metrics::inc_node_divergences(&report.asset_pair, report.kind.as_str());
+                let _ = report_tx.send(report);
+            }
+        });
+    }
+
+    /// Enduring loop, matching up price updates from every node until `shutdown` is cancelled
+    pub async fn run(&mut self, shutdown: CancellationToken) {
+        let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+        self.forward_price_updates(update_tx).await;
+
+        let mut timeout_check_interval = interval(self.options.block_timeout / 2);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => {
+                    tracing::info!("stopping consistency checker");
+
+                    break;
+                },
+                _ = timeout_check_interval.tick() => {
+                    let timed_out: Vec<_> = self
+                        .pending
+                        .iter()
+                        .filter(|(_, block)| block.first_seen.elapsed() >= self.options.block_timeout)
+                        .map(|(key, _)| key.clone())
+                        .collect();
+
+                    for (asset_pair, block_number) in timed_out {
+                        let block = self.pending.remove(&(asset_pair.clone(), block_number)).unwrap();
+                        self.compare_block(asset_pair, block_number, block);
+                    }
+                },
+                update = update_rx.recv() => {
+                    let Some((index, update)) = update else {
+                        tracing::error!("price updates from every node have stopped");
+
+                        break;
+                    };
+                    let Some(block_number) = update.block_number else {
+                        tracing::debug!(pair = %update.asset_pair, node = %self.nodes[index].0, "price update has no block number, it can't be compared");
+
+                        continue;
+                    };
+                    if self.compared.get(&update.asset_pair).is_some_and(|compared| block_number <= *compared) {
+                        continue;
+                    }
+
+                    let asset_pair = update.asset_pair.clone();
+                    let node_count = self.nodes.len();
+                    let block = self.pending.entry((asset_pair.clone(), block_number)).or_insert_with(|| PendingBlock {
+                        first_seen: Instant::now(),
+                        updates: vec![None; node_count],
+                    });
+                    block.updates[index] = Some(update);
+
+                    if block.updates.iter().all(|update| update.is_some()) {
+                        let block = self.pending.remove(&(asset_pair.clone(), block_number)).unwrap();
+                        self.compare_block(asset_pair, block_number, block);
+                    }
+
+                    if self.report_tx.is_closed() {
+                        break;
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Create and start a `ConsistencyChecker` comparing `pools` across `nodes`, returning the channel
+/// divergence reports are sent on, the channel closes once `shutdown` is cancelled
+pub fn create_and_start_consistency_checker(
+    nodes: Vec<(String, PoolInfoProviderHandle)>,
+    pools: Vec<AssetPair>,
+    options: ConsistencyOptions,
+    shutdown: CancellationToken,
+) -> mpsc::UnboundedReceiver<DivergenceReport> {
+    let (report_tx, report_rx) = mpsc::unbounded_channel();
+    let mut checker = ConsistencyChecker::new(nodes, pools, options, report_tx);
+
+    tokio::spawn(async move {
+        checker.run(shutdown).await;
+    });
+
+    report_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{
+        asset_pair::AssetPair,
+        liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+        order_book::Side,
+        price_update::PriceUpdate,
+        timestamp::Timestamp,
+    };
+
+    use super::{compare_liquidity, compare_price_updates, DivergenceKind};
+
+    fn btc_usdc() -> AssetPair {
+        AssetPair::new("BTC".to_string(), "USDC".to_string())
+    }
+
+    fn price_update(tick: i32, sqrt_price: &str) -> PriceUpdate {
+        PriceUpdate {
+            asset_pair: btc_usdc(),
+            price: "0x1".to_string(),
+            sqrt_price: sqrt_price.to_string(),
+            tick,
+            block_number: Some(10),
+            block_hash: Some("0xabc".to_string()),
+            stale: false,
+            received_at: Timestamp::now(),
+        }
+    }
+
+    fn liquidity(range: &[(i32, &str)], bids: &[(i32, &str)]) -> Liquidity {
+        Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![],
+                    bids: bids
+                        .iter()
+                        .map(|(tick, amount)| LimitOrder {
+                            tick: *tick,
+                            amount: amount.to_string(),
+                        })
+                        .collect(),
+                },
+                range_orders: range
+                    .iter()
+                    .map(|(tick, liquidity)| RangeOrder {
+                        tick: *tick,
+                        liquidity: liquidity.to_string(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_compare_price_updates() {
+        let agreeing = [
+            ("a".to_string(), Some(price_update(100, "0x10"))),
+            ("b".to_string(), Some(price_update(100, "0x010"))),
+        ];
+        assert!(compare_price_updates(&btc_usdc(), 10, &agreeing).is_empty());
+
+        let diverging = [
+            ("a".to_string(), Some(price_update(100, "0x10"))),
+            ("b".to_string(), Some(price_update(101, "0x11"))),
+            ("c".to_string(), None),
+        ];
+        let kinds: Vec<_> = compare_price_updates(&btc_usdc(), 10, &diverging)
+            .iter()
+            .map(|report| report.kind)
+            .collect();
+        assert_eq!(
+            vec![
+                DivergenceKind::MissingUpdate,
+                DivergenceKind::Tick,
+                DivergenceKind::SqrtPrice
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn test_compare_liquidity() {
+        let results = [
+            (
+                "a".to_string(),
+                Some(liquidity(&[(-10, "0x539"), (10, "0x0")], &[(5, "0x1")])),
+            ),
+            // a band missing from the book is the same as zero liquidity
+            (
+                "b".to_string(),
+                Some(liquidity(&[(-10, "0x539")], &[(5, "0x1")])),
+            ),
+            ("c".to_string(), None),
+        ];
+        assert!(compare_liquidity(&btc_usdc(), 10, &None, &results).is_empty());
+
+        let results = [
+            (
+                "a".to_string(),
+                Some(liquidity(&[(-10, "0x539"), (10, "0x1")], &[(5, "0x1")])),
+            ),
+            (
+                "b".to_string(),
+                Some(liquidity(&[(-10, "0x539")], &[(5, "0x2")])),
+            ),
+        ];
+        let reports = compare_liquidity(&btc_usdc(), 10, &None, &results);
+        assert_eq!(2, reports.len());
+
+        assert_eq!(DivergenceKind::RangeLiquidity, reports[0].kind);
+        assert_eq!(Some(10), reports[0].tick);
+        assert_eq!(Some("0x1".to_string()), reports[0].values[0].value);
+        assert_eq!(Some("0x0".to_string()), reports[0].values[1].value);
+
+        assert_eq!(DivergenceKind::LimitLiquidity, reports[1].kind);
+        assert_eq!(Some(Side::Buy), reports[1].side);
+        assert_eq!(Some(1), reports[1].mismatched_ticks);
+    }
+}
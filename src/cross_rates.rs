@@ -0,0 +1,387 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    metrics,
+    model::{asset_pair::AssetPair, price_update::PriceUpdate, timestamp::Timestamp},
+    pool_info_provider::pool_info_provider_handle::PoolInfoProviderHandle,
+    router::HUB_ASSET,
+    util::pool_price,
+};
+
+/// What an implied cross rate is compared with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateSource {
+    /// A pool quoting the pair directly
+    Pool,
+    /// A configured reference price
+    Reference,
+}
+
+impl RateSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateSource::Pool => "pool",
+            RateSource::Reference => "reference",
+        }
+    }
+}
+
+/// A rate observed for a pair compared with the rate implied through the hub asset
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CrossRateDeviation {
+    pub asset_pair: AssetPair,
+    pub source: RateSource,
+    pub observed_rate: f64,
+    pub implied_rate: f64,
+    /// How far `observed_rate` is above `implied_rate`, in basis points
+    pub deviation_bps: f64,
+    pub detected_at: Timestamp,
+}
+
+/// Mid rates between every pair of assets implied by their pools against the hub asset
+#[derive(Debug, Default)]
+pub struct CrossRateMatrix {
+    /// Price of each asset in the hub asset
+    hub_prices: HashMap<String, f64>,
+    /// Price of pools between two non hub assets
+    direct_prices: HashMap<AssetPair, f64>,
+}
+
+impl CrossRateMatrix {
+    pub fn new() -> Self {
+        CrossRateMatrix::default()
+    }
+
+    /// Record the pool price of `update`, ignored if it has no price
+    pub fn update(&mut self, update: &PriceUpdate) {
+        let asset_pair = &update.asset_pair;
+        let Some(price) = pool_price(&update.sqrt_price, update.tick, asset_pair) else {
+            return;
+        };
+
+        if asset_pair.to == HUB_ASSET {
+            self.hub_prices.insert(asset_pair.from.clone(), price);
+        } else if asset_pair.from == HUB_ASSET {
+            self.hub_prices.insert(asset_pair.to.clone(), 1.0 / price);
+        } else {
+            self.direct_prices.insert(asset_pair.clone(), price);
+        }
+    }
+
+    /// Forget the price of a pool which is no longer subscribed
+    pub fn remove(&mut self, asset_pair: &AssetPair) {
+        if asset_pair.to == HUB_ASSET {
+            self.hub_prices.remove(&asset_pair.from);
+        } else if asset_pair.from == HUB_ASSET {
+            self.hub_prices.remove(&asset_pair.to);
+        } else {
+            self.direct_prices.remove(asset_pair);
+        }
+    }
+
+    fn hub_price(&self, asset: &str) -> Option<f64> {
+        if asset == HUB_ASSET {
+            Some(1.0)
+        } else {
+            self.hub_prices.get(asset).copied()
+        }
+    }
+
+    /// Implied mid rate of `to` per `from`, `None` unless both have a price in the hub asset
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        Some(self.hub_price(from)? / self.hub_price(to)?)
+    }
+
+    /// Implied rate of every asset against every other, by `from` then `to`
+    pub fn rates(&self) -> BTreeMap<String, BTreeMap<String, f64>> {
+        let assets: Vec<&str> = self
+            .hub_prices
+            .keys()
+            .map(String::as_str)
+            .chain([HUB_ASSET])
+            .collect();
+
+        assets
+            .iter()
+            .map(|from| {
+                let rates = assets
+                    .iter()
+                    .filter(|to| *to != from)
+                    .filter_map(|to| Some((to.to_string(), self.rate(from, to)?)))
+                    .collect();
+                (from.to_string(), rates)
+            })
+            .collect()
+    }
+
+    /// Compare every directly quoted pool and every reference price with its implied rate, pairs
+    /// without an implied rate are skipped
+    pub fn deviations(&self, reference_prices: &[(AssetPair, f64)]) -> Vec<CrossRateDeviation> {
+        let pools = self
+            .direct_prices
+            .iter()
+            .map(|(asset_pair, price)| (asset_pair, *price, RateSource::Pool));
+        let references = reference_prices
+            .iter()
+            .map(|(asset_pair, price)| (asset_pair, *price, RateSource::Reference));
+
+        pools
+            .chain(references)
+            .filter_map(|(asset_pair, observed_rate, source)| {
+                let implied_rate = self.rate(&asset_pair.from, &asset_pair.to)?;
+
+                Some(CrossRateDeviation {
+                    asset_pair: asset_pair.clone(),
+                    source,
+                    observed_rate,
+                    implied_rate,
+                    deviation_bps: (observed_rate / implied_rate - 1.0) * 10_000.0,
+                    detected_at: Timestamp::now(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The latest `CrossRateMatrix`, cheap to clone, all clones share the same matrix
+#[derive(Clone, Default)]
+pub struct CrossRates {
+    matrix: Arc<Mutex<CrossRateMatrix>>,
+}
+
+impl CrossRates {
+    pub fn new() -> Self {
+        CrossRates::default()
+    }
+
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        self.matrix.lock().unwrap().rate(from, to)
+    }
+
+    pub fn rates(&self) -> BTreeMap<String, BTreeMap<String, f64>> {
+        self.matrix.lock().unwrap().rates()
+    }
+}
+
+/// Settings for a `CrossRateMonitor`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossRateOptions {
+    /// Deviation from the implied rate, in basis points, beyond which a rate is inconsistent
+    pub threshold_bps: f64,
+    /// Rates of pairs to compare with their implied rate, in `to` per `from`
+    pub reference_prices: Vec<(AssetPair, f64)>,
+}
+
+/// An enduring thread which updates `CrossRates` on every price update and sends a
+/// `CrossRateDeviation` downstream whenever a directly quoted pool or a reference price moves
+/// further than the threshold from its implied rate. A pair is reported once until it is back
+/// within the threshold.
+pub struct CrossRateMonitor {
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    pools: Vec<AssetPair>,
+    options: CrossRateOptions,
+    cross_rates: CrossRates,
+    /// Pairs currently beyond the threshold
+    inconsistent: HashSet<(AssetPair, RateSource)>,
+    report_tx: mpsc::UnboundedSender<CrossRateDeviation>,
+}
+
+/// Create and start a cross rate monitor and return the channel it reports inconsistencies on, the
+/// channel closes once `shutdown` is cancelled
+pub fn create_and_start_cross_rate_monitor(
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    pools: Vec<AssetPair>,
+    options: CrossRateOptions,
+    cross_rates: CrossRates,
+    shutdown: CancellationToken,
+) -> mpsc::UnboundedReceiver<CrossRateDeviation> {
+    let (report_tx, report_rx) = mpsc::unbounded_channel();
+    let mut monitor = CrossRateMonitor::new(
+        pool_info_provider_handle,
+        pools,
+        options,
+        cross_rates,
+        report_tx,
+    );
+
+    tokio::spawn(async move {
+        monitor.run(shutdown).await;
+    });
+
+    report_rx
+}
+
+impl CrossRateMonitor {
+    pub fn new(
+        pool_info_provider_handle: PoolInfoProviderHandle,
+        pools: Vec<AssetPair>,
+        options: CrossRateOptions,
+        cross_rates: CrossRates,
+        report_tx: mpsc::UnboundedSender<CrossRateDeviation>,
+    ) -> Self {
+        CrossRateMonitor {
+            pool_info_provider_handle,
+            pools,
+            options,
+            cross_rates,
+            inconsistent: HashSet::new(),
+            report_tx,
+        }
+    }
+
+    /// Compare every rate with its implied rate, reporting those newly beyond the threshold
+    fn check(&mut self) {
+        let deviations = self
+            .cross_rates
+            .matrix
+            .lock()
+            .unwrap()
+            .deviations(&self.options.reference_prices);
+
+        for deviation in deviations {
+            metrics::set_cross_rate_deviation(
+                &deviation.asset_pair,
+                deviation.source.as_str(),
+                deviation.deviation_bps,
+            );
+
+            let key = (deviation.asset_pair.clone(), deviation.source);
+            if deviation.deviation_bps.abs() <= self.options.threshold_bps {
+                if self.inconsistent.remove(&key) {
+                    tracing::info!(
+                        pair = %deviation.asset_pair,
+                        source = deviation.source.as_str(),
+                        deviation_bps = deviation.deviation_bps,
+                        "cross rate consistent again"
+                    );
+                }
+
+                continue;
+            }
+            if self.inconsistent.insert(key) {
+                metrics::inc_cross_rate_inconsistencies(
+                    &deviation.asset_pair,
+                    deviation.source.as_str(),
+                );
+                let _ = self.report_tx.send(deviation);
+            }
+        }
+    }
+
+    /// Enduring loop, updating the matrix on every price update until `shutdown` is cancelled
+    pub async fn run(&mut self, shutdown: CancellationToken) {
+        let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+        self.pool_info_provider_handle
+            .forward_pool_price_updates(&self.pools, update_tx)
+            .await;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => {
+                    tracing::info!("stopping cross rate monitor");
+
+                    break;
+                },
+                update = update_rx.recv() => {
+                    let Some((asset_pair, update)) = update else {
+                        tracing::warn!("price updates of every pool have stopped");
+
+                        break;
+                    };
+
+                    let mut matrix = self.cross_rates.matrix.lock().unwrap();
+                    match update {
+                        Some(update) => matrix.update(&update),
+                        None => matrix.remove(&asset_pair),
+                    }
+                    drop(matrix);
+                    self.check();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{asset_pair::AssetPair, price_update::PriceUpdate, timestamp::Timestamp};
+
+    use super::{CrossRateMatrix, RateSource};
+
+    fn price_update(asset_pair: &str, tick: i32) -> PriceUpdate {
+        PriceUpdate {
+            asset_pair: asset_pair.parse().unwrap(),
+            price: "0x0".to_string(),
+            sqrt_price: "0x0".to_string(),
+            tick,
+            block_number: None,
+            block_hash: None,
+            stale: false,
+            received_at: Timestamp::now(),
+        }
+    }
+
+    #[test]
+    fn test_implied_rates() {
+        let mut matrix = CrossRateMatrix::new();
+        // BTC around 30000 and ETH around 3000 USDC
+        matrix.update(&price_update("BTC-USDC", 57040));
+        matrix.update(&price_update("ETH-USDC", -196_257));
+
+        let btc_eth = matrix.rate("BTC", "ETH").unwrap();
+        assert!((btc_eth - 10.0).abs() < 0.01, "rate {}", btc_eth);
+        assert_eq!(Some(1.0), matrix.rate("ETH", "ETH"));
+        assert!((matrix.rate("ETH", "BTC").unwrap() * btc_eth - 1.0).abs() < 1e-12);
+        assert_eq!(None, matrix.rate("BTC", "DOT"));
+
+        let rates = matrix.rates();
+        assert_eq!(3, rates.len());
+        assert_eq!(2, rates["USDC"].len());
+        assert_eq!(Some(&btc_eth), rates["BTC"].get("ETH"));
+
+        matrix.remove(&"ETH-USDC".parse().unwrap());
+        assert_eq!(None, matrix.rate("BTC", "ETH"));
+    }
+
+    #[test]
+    fn test_deviations_from_pools_and_references() {
+        let mut matrix = CrossRateMatrix::new();
+        matrix.update(&price_update("BTC-USDC", 57040));
+        matrix.update(&price_update("ETH-USDC", -196_257));
+        // a direct pool 100 ticks, about 100 bps, above the tick of the implied rate
+        matrix.update(&price_update("BTC-ETH", 57040 + 196_257 + 100));
+
+        let btc_eth: AssetPair = "BTC-ETH".parse().unwrap();
+        let deviations = matrix.deviations(&[(btc_eth.clone(), 9.9)]);
+        assert_eq!(2, deviations.len());
+
+        let pool = deviations
+            .iter()
+            .find(|d| d.source == RateSource::Pool)
+            .unwrap();
+        assert_eq!(btc_eth, pool.asset_pair);
+        assert!(
+            (pool.deviation_bps - 100.0).abs() < 5.0,
+            "deviation {}",
+            pool.deviation_bps
+        );
+
+        let reference = deviations
+            .iter()
+            .find(|d| d.source == RateSource::Reference)
+            .unwrap();
+        assert!(
+            (reference.deviation_bps + 100.0).abs() < 5.0,
+            "deviation {}",
+            reference.deviation_bps
+        );
+    }
+}
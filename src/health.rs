@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::model::asset_pair::AssetPair;
+
+/// Health of a single pool price subscription or order book builder
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub asset_pair: String,
+    /// Seconds since the last update, `None` if there hasn't been one yet
+    pub seconds_since_update: Option<f64>,
+    pub stale: bool,
+}
+
+/// Point in time health of the feedhandler
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// The provider loop is running
+    pub live: bool,
+    /// Every tracked pool and builder has updated within the staleness threshold
+    pub ready: bool,
+    pub stale_after_secs: f64,
+    pub pools: Vec<ComponentHealth>,
+    pub builders: Vec<ComponentHealth>,
+}
+
+#[derive(Default)]
+struct HealthState {
+    /// Last time the provider loop ran
+    provider_heartbeat: Option<Instant>,
+    /// Map of subscribed asset pair to the time of its last price update
+    pools: HashMap<AssetPair, Option<Instant>>,
+    /// Map of order book builder asset pair to the time of its last published book
+    builders: HashMap<AssetPair, Option<Instant>>,
+}
+
+/// Tracks the last update time of every pool and builder and decides when they have gone stale.
+/// Cheap to clone, all clones share the same state.
+#[derive(Clone)]
+pub struct HealthMonitor {
+    /// Time without updates after which a pool or builder is considered stale
+    stale_after: Duration,
+    state: Arc<Mutex<HealthState>>,
+}
+
+impl HealthMonitor {
+    pub fn new(stale_after: Duration) -> Self {
+        HealthMonitor {
+            stale_after,
+            state: Arc::new(Mutex::new(HealthState::default())),
+        }
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        self.stale_after
+    }
+
+    pub fn record_provider_heartbeat(&self) {
+        self.state.lock().unwrap().provider_heartbeat = Some(Instant::now());
+    }
+
+    /// Start tracking a pool, it is unhealthy until its first price update
+    pub fn register_pool(&self, asset_pair: &AssetPair) {
+        self.state
+            .lock()
+            .unwrap()
+            .pools
+            .entry(asset_pair.clone())
+            .or_insert(None);
+    }
+
+    /// Stop tracking a pool
+    pub fn unregister_pool(&self, asset_pair: &AssetPair) {
+        self.state.lock().unwrap().pools.remove(asset_pair);
+    }
+
+    pub fn record_price_update(&self, asset_pair: &AssetPair) {
+        self.state
+            .lock()
+            .unwrap()
+            .pools
+            .insert(asset_pair.clone(), Some(Instant::now()));
+    }
+
+    /// Start tracking a builder, it is unhealthy until it publishes its first book
+    pub fn register_builder(&self, asset_pair: &AssetPair) {
+        self.state
+            .lock()
+            .unwrap()
+            .builders
+            .entry(asset_pair.clone())
+            .or_insert(None);
+    }
+
+    /// Stop tracking a builder
+    pub fn unregister_builder(&self, asset_pair: &AssetPair) {
+        self.state.lock().unwrap().builders.remove(asset_pair);
+    }
+
+    /// Record a book published by a registered builder, books of unregistered builders are ignored
+    pub fn record_book_built(&self, asset_pair: &AssetPair) {
+        if let Some(last_update) = self.state.lock().unwrap().builders.get_mut(asset_pair) {
+            *last_update = Some(Instant::now());
+        }
+    }
+
+    /// Whether a pool's last price update is older than the staleness threshold. Pools without any
+    /// updates yet are not stale, nothing has been published for them.
+    pub fn is_pool_stale(&self, asset_pair: &AssetPair) -> bool {
+        match self.state.lock().unwrap().pools.get(asset_pair) {
+            Some(Some(last_update)) => last_update.elapsed() > self.stale_after,
+            _ => false,
+        }
+    }
+
+    /// Pools whose last price update is older than the staleness threshold
+    pub fn stale_pools(&self) -> Vec<AssetPair> {
+        self.state
+            .lock()
+            .unwrap()
+            .pools
+            .iter()
+            .filter(|(_, last_update)| last_update.is_some_and(|t| t.elapsed() > self.stale_after))
+            .map(|(asset_pair, _)| asset_pair.clone())
+            .collect()
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let state = self.state.lock().unwrap();
+
+        let component_health = |components: &HashMap<AssetPair, Option<Instant>>| {
+            let mut health: Vec<ComponentHealth> = components
+                .iter()
+                .map(|(asset_pair, last_update)| {
+                    let elapsed = last_update.map(|t| t.elapsed());
+                    ComponentHealth {
+                        asset_pair: asset_pair.to_string(),
+                        seconds_since_update: elapsed.map(|e| e.as_secs_f64()),
+                        stale: elapsed.is_none_or(|e| e > self.stale_after),
+                    }
+                })
+                .collect();
+            health.sort_by(|a, b| a.asset_pair.cmp(&b.asset_pair));
+            health
+        };
+
+        let pools = component_health(&state.pools);
+        let builders = component_health(&state.builders);
+
+        let live = state
+            .provider_heartbeat
+            .is_some_and(|t| t.elapsed() <= self.stale_after);
+        let ready = live && pools.iter().chain(builders.iter()).all(|c| !c.stale);
+
+        HealthReport {
+            live,
+            ready,
+            stale_after_secs: self.stale_after.as_secs_f64(),
+            pools,
+            builders,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::model::asset_pair::AssetPair;
+
+    use super::HealthMonitor;
+
+    #[test]
+    fn test_health_report() {
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let health_monitor = HealthMonitor::new(Duration::from_millis(20));
+
+        health_monitor.record_provider_heartbeat();
+        health_monitor.register_pool(&asset_pair);
+        let report = health_monitor.report();
+        assert!(report.live);
+        assert!(!report.ready);
+        assert!(!health_monitor.is_pool_stale(&asset_pair));
+
+        health_monitor.record_price_update(&asset_pair);
+        assert!(health_monitor.report().ready);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(health_monitor.is_pool_stale(&asset_pair));
+        assert_eq!(vec![asset_pair], health_monitor.stale_pools());
+
+        let report = health_monitor.report();
+        assert!(!report.live);
+        assert!(!report.ready);
+        assert!(report.pools[0].stale);
+    }
+}
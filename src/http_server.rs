@@ -0,0 +1,157 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Largest request head accepted
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// Response produced by a route
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        HttpResponse {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+type RouteHandler = Arc<dyn Fn() -> HttpResponse + Send + Sync>;
+
+/// Minimal HTTP server for operational endpoints (metrics, health). Serves `GET` requests on a fixed
+/// set of paths, one request per connection.
+#[derive(Clone, Default)]
+pub struct HttpServer {
+    /// Map of path to the handler producing its response
+    routes: HashMap<String, RouteHandler>,
+}
+
+impl HttpServer {
+    pub fn new() -> Self {
+        HttpServer::default()
+    }
+
+    /// Serve the response of `handler` on `path`
+    pub fn route(
+        mut self,
+        path: &str,
+        handler: impl Fn() -> HttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.insert(path.to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Enduring loop, accept and serve connections on `listener`
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::error!(error = ?e, "error accepting http connection");
+
+                    continue;
+                }
+            };
+
+            let server = self.clone();
+            tokio::spawn(async move {
+                server.handle_connection(stream).await;
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+
+            if buf.len() > MAX_REQUEST_HEAD {
+                return;
+            }
+        }
+
+        let head = String::from_utf8_lossy(&buf);
+        let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().unwrap_or_default();
+        let path = request_line
+            .next()
+            .unwrap_or_default()
+            .split('?')
+            .next()
+            .unwrap_or_default();
+
+        let response = match (method, self.routes.get(path)) {
+            ("GET", Some(handler)) => handler(),
+            (_, Some(_)) => {
+                HttpResponse::new(405, "text/plain", "method not allowed\n".to_string())
+            }
+            (_, None) => HttpResponse::new(404, "text/plain", "not found\n".to_string()),
+        };
+
+        let http_response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            response.reason(),
+            response.content_type,
+            response.body.len(),
+            response.body
+        );
+
+        if let Err(e) = stream.write_all(http_response.as_bytes()).await {
+            tracing::debug!(error = ?e, "error writing http response");
+        }
+        let _ = stream.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::{HttpResponse, HttpServer};
+
+    #[tokio::test]
+    async fn test_http_server_routes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = HttpServer::new().route("/metrics", || {
+            HttpResponse::new(200, "text/plain", "metric 1\n".to_string())
+        });
+        tokio::spawn(server.serve(listener));
+
+        let resp = reqwest::get(format!("http://{}/metrics?x=1", addr))
+            .await
+            .unwrap();
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!("metric 1\n", resp.text().await.unwrap());
+
+        let resp = reqwest::get(format!("http://{}/missing", addr))
+            .await
+            .unwrap();
+        assert_eq!(404, resp.status().as_u16());
+    }
+}
@@ -0,0 +1,18 @@
+pub mod book_manager;
+pub mod builder_supervisor;
+pub mod candles;
+pub mod config;
+pub mod consistency;
+pub mod cross_rates;
+pub mod health;
+pub mod http_server;
+pub mod logging;
+pub mod metrics;
+pub mod model;
+pub mod orderbook_builder;
+pub mod pool_info_provider;
+pub mod router;
+pub mod sink;
+pub mod tick_math;
+pub mod transport;
+pub mod util;
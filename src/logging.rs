@@ -0,0 +1,133 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::{
+    filter::{EnvFilter, LevelFilter},
+    fmt::{
+        format::{Format, Json, JsonFields},
+        MakeWriter, SubscriberBuilder,
+    },
+};
+
+/// Output format of log lines
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// A json object per line, carrying the fields of the event and of every span it is in
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format {:?}, expected text or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Install the global subscriber writing to stdout at `level`, overridden by `RUST_LOG` if set.
+/// Records of crates still using `log` are forwarded too.
+pub fn init(level: LevelFilter, format: LogFormat) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+
+    match format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => json_subscriber(filter, std::io::stdout).init(),
+    }
+}
+
+/// Builder of a subscriber writing json lines to `writer`, each with the fields of its current span
+/// and of every enclosing span
+fn json_subscriber<W>(
+    filter: EnvFilter,
+    writer: W,
+) -> SubscriberBuilder<JsonFields, Format<Json>, EnvFilter, W>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_env_filter(filter)
+        .with_writer(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::Value;
+    use tracing_subscriber::{filter::EnvFilter, fmt::MakeWriter};
+
+    use super::json_subscriber;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Buffer;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_lines_carry_span_fields() {
+        let buffer = Buffer::default();
+        let subscriber = json_subscriber(EnvFilter::new("debug"), buffer.clone()).finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let book = tracing::info_span!("book_build", pair = "BTC-USDC", block = 10);
+            let _book = book.enter();
+            let request = tracing::info_span!("liquidity_request", request_id = 7);
+            let _request = request.enter();
+            tracing::warn!(latency_ms = 1500.0, "liquidity received");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!("WARN", line["level"]);
+        assert_eq!("liquidity received", line["fields"]["message"]);
+        assert_eq!(1500.0, line["fields"]["latency_ms"]);
+        assert_eq!("liquidity_request", line["span"]["name"]);
+        assert_eq!(7, line["span"]["request_id"]);
+        // the enclosing book build correlates the request with the book
+        assert_eq!("book_build", line["spans"][0]["name"]);
+        assert_eq!("BTC-USDC", line["spans"][0]["pair"]);
+        assert_eq!(10, line["spans"][0]["block"]);
+    }
+}
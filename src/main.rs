@@ -1,107 +1,432 @@
-use std::{env, time::Duration};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    process,
+    time::Duration,
+};
 
-use orderbook_builder::create_and_start_order_book_builder;
-use pool_info_provider::pool_info_provider::PoolInfoProvider;
-use tokio::time::sleep;
+use chainflip_feedhandler_rs::{
+    book_manager::BookManager,
+    candles::{create_and_start_candle_builder, Candle, CandleAggregator, CandleInterval},
+    config::{Cli, Config},
+    consistency::create_and_start_consistency_checker,
+    cross_rates::{create_and_start_cross_rate_monitor, CrossRates},
+    health::HealthMonitor,
+    http_server::{HttpResponse, HttpServer},
+    logging, metrics,
+    model::{asset_pair::AssetPair, book_stats::BookStats, depth::DepthLadder},
+    pool_info_provider::{
+        node_connection::NodeConnector, pool_info_provider::PoolInfoProvider,
+        pool_info_provider_handle::PoolInfoProviderHandle,
+    },
+    transport::{
+        journal::{read_journal, JournalWriter},
+        node_transport::NodeTransport,
+        recording_transport::RecordingTransport,
+        replay_transport::ReplayTransport,
+        websocket_transport::WebsocketTransport,
+    },
+};
+use clap::Parser;
+use tokio::{
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
 
-use crate::model::asset_pair::AssetPair;
-use simple_logger::SimpleLogger;
-mod model;
-mod orderbook_builder;
-mod pool_info_provider;
-mod util;
+/// Time allowed for providers to unsubscribe and close their node connections on shutdown
+const PROVIDER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
-mod constants {
-    use std::time::Duration;
+/// Cancel `shutdown` on SIGINT or SIGTERM
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let mut sigterm = signal(SignalKind::terminate()).expect("error listening for SIGTERM");
 
-    pub const ORDERBOOK_POLL_DURATION: Duration = Duration::from_secs(15);
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => tracing::info!("received SIGINT, shutting down"),
+        _ = sigterm.recv() => tracing::info!("received SIGTERM, shutting down"),
+    }
+    shutdown.cancel();
+}
+
+/// Shut down the providers once nothing uses them anymore, waiting for them to unsubscribe and
+/// close their node connections
+async fn stop_providers(provider_shutdown: CancellationToken, provider_tasks: Vec<JoinHandle<()>>) {
+    provider_shutdown.cancel();
+
+    for task in provider_tasks {
+        match timeout(PROVIDER_SHUTDOWN_TIMEOUT, task).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!(error = ?e, "pool info provider failed"),
+            Err(_) => tracing::warn!("timed out waiting for pool info provider to shut down"),
+        }
+    }
+}
+
+/// Create the pool info provider, replaying a journal if configured or otherwise connecting to live
+/// nodes, rate limited and caching liquidity as configured. Live traffic is recorded if configured.
+async fn create_provider(config: &Config, health_monitor: HealthMonitor) -> PoolInfoProvider {
+    if config.replay.is_some() {
+        return PoolInfoProvider::new(create_transport(config).await, health_monitor)
+            .with_liquidity_cache_capacity(config.cache.liquidity_entries);
+    }
+
+    let mut pool_info_provider = if !config.standby_nodes.is_empty() {
+        tracing::info!(
+            nodes = config.standby_nodes.len() + 1,
+            mode = %config.redundancy.mode,
+            "connecting to nodes"
+        );
+        let connectors = config
+            .node_endpoints()
+            .into_iter()
+            .map(NodeConnector::websocket)
+            .collect();
+
+        PoolInfoProvider::with_nodes(connectors, config.redundancy.mode, health_monitor)
+    } else {
+        PoolInfoProvider::new(create_transport(config).await, health_monitor)
+    }
+    .with_liquidity_cache_capacity(config.cache.liquidity_entries);
+
+    for (node, rate_limit) in config.node_rate_limits().into_iter().enumerate() {
+        if let Some(rate_limit) = rate_limit {
+            pool_info_provider = pool_info_provider.with_rate_limit(node, rate_limit);
+        }
+    }
+
+    pool_info_provider
+}
+
+/// Create the transport to the node, replaying a journal if configured or otherwise connecting to a
+/// live node. Live traffic is recorded if configured.
+async fn create_transport(config: &Config) -> Box<dyn NodeTransport> {
+    if let Some(replay) = &config.replay {
+        let entries = read_journal(&replay.journal).expect("error reading replay journal");
+        tracing::info!(
+            entries = entries.len(),
+            journal = %replay.journal.display(),
+            speed = %config.replay_speed(),
+            "replaying journal"
+        );
+
+        return Box::new(ReplayTransport::new(entries, config.replay_speed()));
+    }
+
+    // validation guarantees an address or urls when not replaying
+    let transport = WebsocketTransport::connect(&config.node.endpoint())
+        .await
+        .expect("error connecting to websocket");
+
+    match &config.record {
+        Some(record) => {
+            tracing::info!(journal = %record.journal.display(), "recording node traffic");
+
+            let journal =
+                JournalWriter::create(&record.journal).expect("error creating record journal");
+            Box::new(RecordingTransport::new(Box::new(transport), journal))
+        }
+        None => Box::new(transport),
+    }
+}
+
+/// Build candles of `pools` at `intervals`, backfilled from the configured journals, and append
+/// every closed candle to the configured file or log it
+fn start_candle_builder(
+    config: &Config,
+    intervals: Vec<CandleInterval>,
+    pool_info_provider_handle: PoolInfoProviderHandle,
+    pools: Vec<AssetPair>,
+    shutdown: CancellationToken,
+) {
+    let candles = config.candles.clone().unwrap_or_default();
+    let mut writer = candles.path.as_ref().map(|path| {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("error opening candles file");
+        BufWriter::new(file)
+    });
+    let mut publish = move |candle: &Candle| {
+        let line = serde_json::to_string(candle).unwrap_or_default();
+        match writer.as_mut() {
+            Some(writer) => {
+                if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+                    tracing::error!(error = ?e, "error writing candle");
+                }
+            }
+            None => tracing::info!(candle = %line, "candle closed"),
+        }
+    };
+
+    let mut aggregator = CandleAggregator::new(intervals);
+    for journal in candles.backfill_journals.iter() {
+        let entries = read_journal(journal).expect("error reading candle backfill journal");
+        let backfilled = aggregator.backfill(&entries);
+        tracing::info!(
+            journal = %journal.display(),
+            candles = backfilled.len(),
+            "backfilled candles"
+        );
+        backfilled.iter().for_each(&mut publish);
+    }
+
+    let mut candle_rx =
+        create_and_start_candle_builder(pool_info_provider_handle, pools, aggregator, shutdown);
+    tokio::spawn(async move {
+        while let Some(candle) = candle_rx.recv().await {
+            publish(&candle);
+        }
+    });
+}
+
+/// Compare every configured node, logging divergence reports and appending them to the report file
+/// if configured, until `shutdown` is cancelled
+async fn run_consistency_checker(
+    config: &Config,
+    health_monitor: HealthMonitor,
+    shutdown: CancellationToken,
+) {
+    let mut report_writer = config.consistency.report_path.as_ref().map(|path| {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("error opening divergence report");
+        BufWriter::new(file)
+    });
+
+    // a provider per node so each node's view is kept separate
+    let provider_shutdown = CancellationToken::new();
+    let mut provider_tasks = Vec::new();
+    let nodes = config
+        .node_endpoints()
+        .into_iter()
+        .zip(config.node_rate_limits())
+        .map(|(endpoint, rate_limit)| {
+            let connector = NodeConnector::websocket(endpoint);
+            let name = connector.name().to_string();
+            let mut pool_info_provider = PoolInfoProvider::with_nodes(
+                vec![connector],
+                config.redundancy.mode,
+                health_monitor.clone(),
+            )
+            .with_liquidity_cache_capacity(config.cache.liquidity_entries);
+            if let Some(rate_limit) = rate_limit {
+                pool_info_provider = pool_info_provider.with_rate_limit(0, rate_limit);
+            }
+            let handle = pool_info_provider.get_handle();
+
+            let provider_shutdown = provider_shutdown.clone();
+            provider_tasks.push(tokio::spawn(async move {
+                pool_info_provider.run(provider_shutdown).await;
+            }));
+
+            (name, handle)
+        })
+        .collect::<Vec<_>>();
+    tracing::info!(nodes = nodes.len(), "checking consistency");
+
+    let pools = config.pools.iter().map(|pool| pool.asset_pair()).collect();
+    let mut report_rx = create_and_start_consistency_checker(
+        nodes,
+        pools,
+        config.consistency_options(),
+        shutdown.clone(),
+    );
+
+    while let Some(report) = report_rx.recv().await {
+        let line = serde_json::to_string(&report).unwrap_or_default();
+        tracing::warn!(report = %line, "nodes diverge");
+
+        if let Some(writer) = report_writer.as_mut() {
+            if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+                tracing::error!(error = ?e, "error writing divergence report");
+            }
+        }
+    }
+
+    if shutdown.is_cancelled() {
+        tracing::info!("consistency checker has stopped");
+    } else {
+        tracing::error!("consistency checker has stopped");
+    }
+    stop_providers(provider_shutdown, provider_tasks).await;
+}
+
+/// Serve Prometheus metrics, health and cross rate endpoints if an http address is configured
+async fn start_http_server(
+    config: &Config,
+    health_monitor: HealthMonitor,
+    cross_rates: CrossRates,
+) {
+    let Some(http_address) = &config.http.address else {
+        return;
+    };
+
+    let listener = TcpListener::bind(http_address)
+        .await
+        .expect("error binding http address");
+    tracing::info!(address = %http_address, "serving metrics and health");
+
+    let health_response = |health_monitor: &HealthMonitor, ready: bool| {
+        let report = health_monitor.report();
+        let healthy = if ready { report.ready } else { report.live };
+        let body = serde_json::to_string_pretty(&report).unwrap_or_default();
+
+        HttpResponse::new(if healthy { 200 } else { 503 }, "application/json", body)
+    };
+
+    let live_health_monitor = health_monitor.clone();
+    let server = HttpServer::new()
+        .route("/metrics", || {
+            HttpResponse::new(200, "text/plain; version=0.0.4", metrics::gather())
+        })
+        .route("/health/live", move || {
+            health_response(&live_health_monitor, false)
+        })
+        .route("/health/ready", move || {
+            health_response(&health_monitor, true)
+        })
+        .route("/cross_rates", move || {
+            let body = serde_json::to_string_pretty(&cross_rates.rates()).unwrap_or_default();
+
+            HttpResponse::new(200, "application/json", body)
+        });
+    tokio::spawn(server.serve(listener));
 }
 
 #[tokio::main]
 async fn main() {
-    SimpleLogger::new().env().init().unwrap();
-
-    let node_address = match env::var("CHAINFLIP_NODE_ADDR") {
-        Ok(addr) => addr,
-        Err(e) => panic!(
-            "No chainflip node address (CHAINFLIP_NODE_ADDR) set: {:?}",
-            e
-        ),
+    let cli = Cli::parse();
+    let config = match Config::from_cli(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
     };
 
-    let pools = vec![
-        AssetPair::new("BTC".to_string(), "USDC".to_string()),
-        AssetPair::new("FLIP".to_string(), "USDC".to_string()),
-        AssetPair::new("DOT".to_string(), "USDC".to_string()),
-        AssetPair::new("ETH".to_string(), "USDC".to_string()),
-    ];
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    logging::init(config.log_level(), config.logging.format);
+
+    let health_monitor = HealthMonitor::new(config.stale_after());
+
+    let cross_rates = CrossRates::new();
+    start_http_server(&config, health_monitor.clone(), cross_rates.clone()).await;
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
 
-    // create and start the pool info provider and subscribe to price updates on each pool
-    let pool_provider_handle = {
-        let mut pool_info_provider = PoolInfoProvider::new(&node_address);
+    if config.consistency.enabled {
+        run_consistency_checker(&config, health_monitor, shutdown).await;
+        return;
+    }
+
+    let mut sinks: Vec<_> = config
+        .sinks
+        .iter()
+        .map(|sink| sink.create().expect("error creating sink"))
+        .collect();
+
+    // create and start the pool info provider and subscribe to price updates on each pool. It is
+    // shut down after the builders so their requests in flight are answered.
+    let provider_shutdown = CancellationToken::new();
+    let (pool_provider_handle, provider_task) = {
+        let mut pool_info_provider = create_provider(&config, health_monitor).await;
         let handle = pool_info_provider.get_handle();
 
-        tokio::spawn(async move {
-            pool_info_provider.run().await;
+        let provider_shutdown = provider_shutdown.clone();
+        let task = tokio::spawn(async move {
+            pool_info_provider.run(provider_shutdown).await;
         });
 
-        handle
+        (handle, task)
     };
 
-    for pool in pools.iter() {
-        pool_provider_handle.subscribe_pool_price_updates(pool);
+    // build books for each pool
+    let mut book_manager = BookManager::new(pool_provider_handle.clone(), shutdown.clone())
+        .with_validation(config.validation.policy);
+    for pool in config.pools.iter() {
+        book_manager.add_pool(
+            &pool.asset_pair(),
+            config.poll_interval(pool),
+            config.trigger(pool),
+            config.min_rebuild_interval(pool),
+        );
     }
 
-    // FIXME: hack to wait for subscriptions to be setup
-    sleep(Duration::from_secs(5)).await;
-
-    // get a price_update handle for FLIP-USDC
-    let mut price_update_rx = pool_provider_handle
-        .get_streaming_pool_price_updates(&pools[1])
-        .await
-        .unwrap();
+    // report rates diverging from those implied through USDC
+    if let Some(cross_rate_options) = config.cross_rate_options() {
+        let mut report_rx = create_and_start_cross_rate_monitor(
+            pool_provider_handle.clone(),
+            book_manager.pools(),
+            cross_rate_options,
+            cross_rates,
+            shutdown.clone(),
+        );
+        tokio::spawn(async move {
+            while let Some(deviation) = report_rx.recv().await {
+                tracing::warn!(
+                    pair = %deviation.asset_pair,
+                    source = deviation.source.as_str(),
+                    observed_rate = deviation.observed_rate,
+                    implied_rate = deviation.implied_rate,
+                    deviation_bps = deviation.deviation_bps,
+                    "cross rate inconsistent"
+                );
+            }
+        });
+    }
 
-    // create and start an order book builder for BTC-USDC
-    let mut btc_orderbook_rx = create_and_start_order_book_builder(
-        &pools[0],
-        pool_provider_handle.clone(),
-        constants::ORDERBOOK_POLL_DURATION,
-    );
+    // candles of every pool's price
+    if let Some(intervals) = config.candle_intervals() {
+        start_candle_builder(
+            &config,
+            intervals,
+            pool_provider_handle.clone(),
+            book_manager.pools(),
+            shutdown.clone(),
+        );
+    }
 
-    // get the latest price for flip
-    let price_update = pool_provider_handle
-        .get_latest_pool_price(&pools[1])
-        .await
-        .unwrap();
-    log::info!("FLIP-USC price update: {:?}", price_update);
-
-    // listen for different types of updates on the channels were interested in
-    loop {
-        tokio::select! {
-            ob = btc_orderbook_rx.recv() => {
-                let ob = match ob {
-                    Some(ob) => ob,
-                    None => {
-                        log::error!("error receiving orderbook");
-
-                        break;
-                    },
-                };
-
-                log::info!("Received orderbook: {:?}", ob);
-            },
-            _ = price_update_rx.changed() => {
-                match price_update_rx.borrow_and_update().as_ref() {
-                    Some(pu) => {
-                        log::info!("Received price update: {:?}", pu);
-                    },
-                    None => {
-                        log::error!("error receiving price update");
-
-                        break;
-                    },
-                }
+    // publish books from every builder to every sink
+    let depth_options = config.depth_options();
+    let stats_options = config.stats_options();
+    while let Some((asset_pair, mut ob)) = book_manager.next_book().await {
+        if let Some(depth_options) = &depth_options {
+            ob.depth = DepthLadder::from_book(&ob, depth_options);
+        }
+        if let Some(stats_options) = &stats_options {
+            ob.stats = BookStats::from_book(&ob, stats_options);
+            if let Some(stats) = &ob.stats {
+                metrics::observe_book_stats(&asset_pair, stats);
+            }
+        }
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.publish(&ob) {
+                tracing::error!(pair = %asset_pair, error = ?e, "error publishing orderbook");
             }
         }
     }
+
+    if shutdown.is_cancelled() {
+        tracing::info!("all order book builders have stopped");
+    } else {
+        tracing::error!("all order book builders have stopped");
+    }
+    for sink in sinks.iter_mut() {
+        if let Err(e) = sink.flush() {
+            tracing::error!(error = ?e, "error flushing sink");
+        }
+    }
+
+    stop_providers(provider_shutdown, vec![provider_task]).await;
+    tracing::info!("shut down");
 }
@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, GaugeVec, HistogramVec, IntCounter, IntCounterVec,
+    IntGaugeVec, TextEncoder,
+};
+
+use crate::{
+    model::{
+        asset_pair::AssetPair, book_stats::BookStats, book_validation::ValidationReport,
+        order_book::OrderBook,
+    },
+    util::u256_to_f64,
+};
+
+lazy_static! {
+    static ref WEBSOCKET_MESSAGES_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_websocket_messages_received_total",
+        "Pool price websocket messages received per pool",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref PRICE_UPDATES: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_price_updates_total",
+        "Price updates published downstream per pool",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref SECONDS_SINCE_LAST_PRICE_UPDATE: GaugeVec = register_gauge_vec!(
+        "feedhandler_seconds_since_last_price_update",
+        "Seconds since the last price update per pool",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref LIQUIDITY_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "feedhandler_liquidity_request_duration_seconds",
+        "Latency of cf_pool_liquidity requests",
+        &["asset_pair"],
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+    )
+    .unwrap();
+    static ref LIQUIDITY_REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_liquidity_request_errors_total",
+        "Failed cf_pool_liquidity requests by failure kind",
+        &["asset_pair", "kind"]
+    )
+    .unwrap();
+    static ref BOOKS_BUILT: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_books_built_total",
+        "Order books built per builder",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref BOOK_AGE_AT_PUBLISH: HistogramVec = register_histogram_vec!(
+        "feedhandler_book_age_at_publish_seconds",
+        "Time from receiving the price update a book was built from to publishing the book",
+        &["asset_pair"],
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap();
+    static ref ORDER_BOOK_TICKS: IntGaugeVec = register_int_gauge_vec!(
+        "feedhandler_order_book_ticks",
+        "Number of range order bands and limit order ticks in the latest book",
+        &["asset_pair", "kind"]
+    )
+    .unwrap();
+    static ref ORDER_BOOK_LIQUIDITY: GaugeVec = register_gauge_vec!(
+        "feedhandler_order_book_liquidity",
+        "Total range order liquidity and limit order amounts in the latest book",
+        &["asset_pair", "kind"]
+    )
+    .unwrap();
+    static ref QUEUE_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        "feedhandler_queue_depth",
+        "Messages waiting in internal channels",
+        &["queue"]
+    )
+    .unwrap();
+    static ref WEBSOCKET_CONNECTS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_websocket_connects_total",
+        "Websocket connections made per node, anything above one is a reconnect",
+        &["node"]
+    )
+    .unwrap();
+    static ref NODE_FAILOVERS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_node_failovers_total",
+        "Switches of the active node, by the node switched to",
+        &["node"]
+    )
+    .unwrap();
+    static ref NODES_CONNECTED: IntGaugeVec = register_int_gauge_vec!(
+        "feedhandler_node_connected",
+        "Whether each node is connected (1) or not (0)",
+        &["node"]
+    )
+    .unwrap();
+    static ref DUPLICATE_PRICE_UPDATES: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_duplicate_price_updates_total",
+        "Price updates dropped as already received from another node",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref NODE_DIVERGENCES: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_node_divergences_total",
+        "Disagreements between nodes found by the consistency checker, by kind",
+        &["asset_pair", "kind"]
+    )
+    .unwrap();
+    static ref BUILDER_RESTARTS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_builder_restarts_total",
+        "Order book builders restarted after panicking or exiting",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref COALESCED_LIQUIDITY_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_coalesced_liquidity_requests_total",
+        "Liquidity requests answered by a request already in flight for the same pool and block",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref RATE_LIMITED_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_rate_limited_requests_total",
+        "REST requests delayed by a node's rate limit",
+        &["node"]
+    )
+    .unwrap();
+    static ref LIQUIDITY_CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_liquidity_cache_lookups_total",
+        "Liquidity cache lookups by pool and whether they hit",
+        &["asset_pair", "result"]
+    )
+    .unwrap();
+    static ref LIQUIDITY_CACHE_EVICTIONS: IntCounter = register_int_counter!(
+        "feedhandler_liquidity_cache_evictions_total",
+        "Liquidity responses evicted from the cache to make room"
+    )
+    .unwrap();
+    static ref BOOK_DEPTH: GaugeVec = register_gauge_vec!(
+        "feedhandler_book_depth",
+        "Quote asset tradable within bps of the mid price per pool and side",
+        &["asset_pair", "bps", "side"]
+    )
+    .unwrap();
+    static ref EFFECTIVE_SPREAD_BPS: GaugeVec = register_gauge_vec!(
+        "feedhandler_effective_spread_bps",
+        "Spread of the average buy and sell prices for the reference notional per pool",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref CROSS_RATE_DEVIATION: GaugeVec = register_gauge_vec!(
+        "feedhandler_cross_rate_deviation_bps",
+        "Deviation of a directly quoted or reference rate from the rate implied through USDC",
+        &["asset_pair", "source"]
+    )
+    .unwrap();
+    static ref CROSS_RATE_INCONSISTENCIES: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_cross_rate_inconsistencies_total",
+        "Times a rate moved beyond the threshold from the rate implied through USDC",
+        &["asset_pair", "source"]
+    )
+    .unwrap();
+    static ref BOOK_VALIDATION_ISSUES: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_book_validation_issues_total",
+        "Problems found validating the node data of books per pool and kind",
+        &["asset_pair", "kind"]
+    )
+    .unwrap();
+    static ref INVALID_BOOKS_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_invalid_books_dropped_total",
+        "Books not published because their node data failed validation per pool",
+        &["asset_pair"]
+    )
+    .unwrap();
+    static ref BUILDS_WITHOUT_LIQUIDITY: IntCounterVec = register_int_counter_vec!(
+        "feedhandler_builds_without_liquidity_total",
+        "Book builds skipped because the pool's liquidity was unavailable per pool",
+        &["asset_pair"]
+    )
+    .unwrap();
+    /// Time of the last price update per pool, used to compute staleness at scrape time
+    static ref LAST_PRICE_UPDATE: Mutex<HashMap<AssetPair, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Kind of failure for a liquidity request
+pub enum LiquidityErrorKind {
+    /// The request could not be made or no response was received
+    Transport,
+    /// The response could not be parsed
+    Parse,
+}
+
+pub fn inc_websocket_messages_received(asset_pair: &AssetPair) {
+    WEBSOCKET_MESSAGES_RECEIVED
+        .with_label_values(&[&asset_pair.to_string()])
+        .inc();
+}
+
+pub fn inc_price_updates(asset_pair: &AssetPair) {
+    PRICE_UPDATES
+        .with_label_values(&[&asset_pair.to_string()])
+        .inc();
+
+    LAST_PRICE_UPDATE
+        .lock()
+        .unwrap()
+        .insert(asset_pair.clone(), Instant::now());
+}
+
+pub fn observe_liquidity_request(asset_pair: &AssetPair, started_at: Instant) {
+    LIQUIDITY_REQUEST_DURATION
+        .with_label_values(&[&asset_pair.to_string()])
+        .observe(started_at.elapsed().as_secs_f64());
+}
+
+pub fn inc_liquidity_request_errors(asset_pair: &AssetPair, kind: LiquidityErrorKind) {
+    let kind = match kind {
+        LiquidityErrorKind::Transport => "transport",
+        LiquidityErrorKind::Parse => "parse",
+    };
+
+    LIQUIDITY_REQUEST_ERRORS
+        .with_label_values(&[&asset_pair.to_string(), kind])
+        .inc();
+}
+
+/// Record a built book and its size
+pub fn observe_order_book(ob: &OrderBook) {
+    let asset_pair = ob.asset_pair.to_string();
+
+    BOOKS_BUILT.with_label_values(&[&asset_pair]).inc();
+
+    let sizes = [
+        ("range", ob.range_orders.len(), ob.range_liquidity()),
+        ("limit_bid", ob.limit_bids.len(), ob.limit_bid_amount()),
+        ("limit_ask", ob.limit_asks.len(), ob.limit_ask_amount()),
+    ];
+    for (kind, ticks, liquidity) in sizes {
+        ORDER_BOOK_TICKS
+            .with_label_values(&[&asset_pair, kind])
+            .set(ticks as i64);
+        ORDER_BOOK_LIQUIDITY
+            .with_label_values(&[&asset_pair, kind])
+            .set(u256_to_f64(liquidity));
+    }
+}
+
+/// Record a book's depth and effective spread, a book too thin for the reference notional has no
+/// spread
+pub fn observe_book_stats(asset_pair: &AssetPair, stats: &BookStats) {
+    let asset_pair = asset_pair.to_string();
+
+    for depth in stats.depth.iter() {
+        let bps = depth.bps.to_string();
+        BOOK_DEPTH
+            .with_label_values(&[&asset_pair, &bps, "bid"])
+            .set(depth.bid_quote);
+        BOOK_DEPTH
+            .with_label_values(&[&asset_pair, &bps, "ask"])
+            .set(depth.ask_quote);
+    }
+    match stats.effective_spread_bps {
+        Some(spread) => EFFECTIVE_SPREAD_BPS
+            .with_label_values(&[&asset_pair])
+            .set(spread),
+        None => {
+            let _ = EFFECTIVE_SPREAD_BPS.remove_label_values(&[&asset_pair]);
+        }
+    }
+}
+
+/// Record the issues found validating a book, bar informational ones, and whether it was dropped
+pub fn observe_validation_report(report: &ValidationReport) {
+    let asset_pair = report.asset_pair.to_string();
+
+    for issue in report
+        .issues
+        .iter()
+        .filter(|issue| !issue.kind.is_informational())
+    {
+        BOOK_VALIDATION_ISSUES
+            .with_label_values(&[&asset_pair, issue.kind.as_str()])
+            .inc();
+    }
+    if !report.published {
+        INVALID_BOOKS_DROPPED
+            .with_label_values(&[&asset_pair])
+            .inc();
+    }
+}
+
+pub fn observe_book_age_at_publish(asset_pair: &AssetPair, age: Duration) {
+    BOOK_AGE_AT_PUBLISH
+        .with_label_values(&[&asset_pair.to_string()])
+        .observe(age.as_secs_f64());
+}
+
+pub fn set_queue_depth(queue: &str, depth: usize) {
+    QUEUE_DEPTH.with_label_values(&[queue]).set(depth as i64);
+}
+
+pub fn inc_websocket_connects(node: &str) {
+    WEBSOCKET_CONNECTS.with_label_values(&[node]).inc();
+}
+
+pub fn inc_node_failovers(node: &str) {
+    NODE_FAILOVERS.with_label_values(&[node]).inc();
+}
+
+pub fn set_node_connected(node: &str, connected: bool) {
+    NODES_CONNECTED
+        .with_label_values(&[node])
+        .set(connected as i64);
+}
+
+pub fn inc_duplicate_price_updates(asset_pair: &AssetPair) {
+    DUPLICATE_PRICE_UPDATES
+        .with_label_values(&[&asset_pair.to_string()])
+        .inc();
+}
+
+pub fn inc_node_divergences(asset_pair: &AssetPair, kind: &str) {
+    NODE_DIVERGENCES
+        .with_label_values(&[&asset_pair.to_string(), kind])
+        .inc();
+}
+
+pub fn set_cross_rate_deviation(asset_pair: &AssetPair, source: &str, deviation_bps: f64) {
+    CROSS_RATE_DEVIATION
+        .with_label_values(&[&asset_pair.to_string(), source])
+        .set(deviation_bps);
+}
+
+pub fn inc_cross_rate_inconsistencies(asset_pair: &AssetPair, source: &str) {
+    CROSS_RATE_INCONSISTENCIES
+        .with_label_values(&[&asset_pair.to_string(), source])
+        .inc();
+}
+
+/// Count a restart of the order book builder of `asset_pair`
+pub fn inc_builder_restarts(asset_pair: &AssetPair) {
+    BUILDER_RESTARTS
+        .with_label_values(&[&asset_pair.to_string()])
+        .inc();
+}
+
+pub fn inc_coalesced_liquidity_requests(asset_pair: &AssetPair) {
+    COALESCED_LIQUIDITY_REQUESTS
+        .with_label_values(&[&asset_pair.to_string()])
+        .inc();
+}
+
+pub fn inc_rate_limited_requests(node: &str) {
+    RATE_LIMITED_REQUESTS.with_label_values(&[node]).inc();
+}
+
+pub fn inc_liquidity_cache_lookups(asset_pair: &AssetPair, hit: bool) {
+    LIQUIDITY_CACHE_LOOKUPS
+        .with_label_values(&[&asset_pair.to_string(), if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+pub fn inc_liquidity_cache_evictions() {
+    LIQUIDITY_CACHE_EVICTIONS.inc();
+}
+
+pub fn inc_builds_without_liquidity(asset_pair: &AssetPair) {
+    BUILDS_WITHOUT_LIQUIDITY
+        .with_label_values(&[&asset_pair.to_string()])
+        .inc();
+}
+
+/// Render all metrics in the Prometheus text exposition format
+pub fn gather() -> String {
+    for (asset_pair, last_update) in LAST_PRICE_UPDATE.lock().unwrap().iter() {
+        SECONDS_SINCE_LAST_PRICE_UPDATE
+            .with_label_values(&[&asset_pair.to_string()])
+            .set(last_update.elapsed().as_secs_f64());
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!(error = ?e, "error encoding metrics");
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -1,7 +1,13 @@
 pub mod asset_pair;
+pub mod book_stats;
+pub mod book_validation;
 pub mod common;
+pub mod depth;
 pub mod json_rpc;
 pub mod liquidity;
 pub mod order_book;
+pub mod pool_info;
 pub mod pool_price;
 pub mod price_update;
+pub mod swap;
+pub mod timestamp;
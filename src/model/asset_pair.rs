@@ -1,4 +1,6 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
+
+use serde::{Serialize, Serializer};
 
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct AssetPair {
@@ -10,6 +12,11 @@ impl AssetPair {
     pub fn new(from: String, to: String) -> AssetPair {
         AssetPair { from, to }
     }
+
+    /// The same pair quoted the other way round, ie. USDC-BTC for BTC-USDC
+    pub fn inverse(&self) -> AssetPair {
+        AssetPair::new(self.to.clone(), self.from.clone())
+    }
 }
 
 impl fmt::Display for AssetPair {
@@ -23,3 +30,23 @@ impl fmt::Debug for AssetPair {
         fmt::Display::fmt(self, f)
     }
 }
+
+impl FromStr for AssetPair {
+    type Err = String;
+
+    /// Parse an asset pair in its display form, ie. "BTC-USDC"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once('-') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+                Ok(AssetPair::new(from.to_uppercase(), to.to_uppercase()))
+            }
+            _ => Err(format!("invalid asset pair {:?}, expected ie. BTC-USDC", s)),
+        }
+    }
+}
+
+impl Serialize for AssetPair {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,239 @@
+use serde::Serialize;
+
+use crate::util::{asset_decimals, tick_to_price, u256_to_f64};
+
+use super::{
+    depth::{range_amounts, DepthGranularity, DepthLadder, DepthLevel, DepthOptions},
+    order_book::OrderBook,
+};
+
+/// Width of the levels walked to fill the reference notional
+const WALK_LEVEL_BPS: f64 = 1.0;
+/// Levels walked each side, the reference notional is filled within 10% of the mid price or not at
+/// all
+const WALK_LEVELS: usize = 1000;
+
+/// Which statistics are computed for each book
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsOptions {
+    /// Distances from the mid price, in basis points, to report the depth within
+    pub depth_bps: Vec<f64>,
+    /// Quote asset amount the effective spread is computed for
+    pub reference_notional: f64,
+}
+
+/// Amounts tradable within `bps` basis points of the mid price, in whole units of each asset
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DepthAtBps {
+    pub bps: f64,
+    /// Base asset which can be sold into bids
+    pub bid_base: f64,
+    /// Quote asset received selling `bid_base`
+    pub bid_quote: f64,
+    /// Base asset which can be bought from asks
+    pub ask_base: f64,
+    /// Quote asset paid buying `ask_base`
+    pub ask_quote: f64,
+}
+
+/// Summary of a book's liquidity, prices in quote asset per base asset and amounts in whole units
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BookStats {
+    pub mid_price: f64,
+    /// Highest limit bid, or the price of the current tick if range liquidity is active at it
+    pub best_bid: Option<f64>,
+    /// Lowest limit ask, or the price of the next tick if range liquidity is active at the current one
+    pub best_ask: Option<f64>,
+    pub depth: Vec<DepthAtBps>,
+    pub reference_notional: f64,
+    /// Average price buying `reference_notional` worth of base, `None` if the book is too thin
+    pub avg_buy_price: Option<f64>,
+    /// Average price selling base for `reference_notional`, `None` if the book is too thin
+    pub avg_sell_price: Option<f64>,
+    /// Difference of the average buy and sell prices relative to the mid price, in basis points
+    pub effective_spread_bps: Option<f64>,
+    /// Range liquidity above the price, in the base asset
+    pub range_base: f64,
+    /// Range liquidity below the price, in the quote asset
+    pub range_quote: f64,
+    /// Sum of limit asks, in the base asset
+    pub limit_base: f64,
+    /// Sum of limit bids, in the quote asset
+    pub limit_quote: f64,
+}
+
+impl BookStats {
+    /// Statistics of `ob`, `None` if the decimals of either asset are unknown
+    pub fn from_book(ob: &OrderBook, options: &StatsOptions) -> Option<Self> {
+        let depth = options
+            .depth_bps
+            .iter()
+            .map(|bps| {
+                let ladder = DepthLadder::from_book(
+                    ob,
+                    &DepthOptions {
+                        granularity: DepthGranularity::Bps(*bps),
+                        levels: 1,
+                    },
+                )?;
+                let bid = ladder.bids.first();
+                let ask = ladder.asks.first();
+
+                Some(DepthAtBps {
+                    bps: *bps,
+                    bid_base: bid.map_or(0.0, |level| level.base_amount),
+                    bid_quote: bid.map_or(0.0, |level| level.quote_amount),
+                    ask_base: ask.map_or(0.0, |level| level.base_amount),
+                    ask_quote: ask.map_or(0.0, |level| level.quote_amount),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let walk = DepthLadder::from_book(
+            ob,
+            &DepthOptions {
+                granularity: DepthGranularity::Bps(WALK_LEVEL_BPS),
+                levels: WALK_LEVELS,
+            },
+        )?;
+        let mid_price = walk.mid_price;
+        let avg_buy_price = average_price(&walk.asks, options.reference_notional);
+        let avg_sell_price = average_price(&walk.bids, options.reference_notional);
+        let effective_spread_bps = match (avg_buy_price, avg_sell_price) {
+            (Some(buy), Some(sell)) => Some((buy - sell) / mid_price * 10_000.0),
+            _ => None,
+        };
+
+        let range_active = ob.range_orders.iter().any(|band| {
+            !band.liquidity.is_zero() && band.start_tick <= ob.tick && ob.tick < band.end_tick
+        });
+        let best_bid = ob
+            .limit_bids
+            .iter()
+            .map(|order| order.tick)
+            .chain(range_active.then_some(ob.tick))
+            .max()
+            .map(|tick| tick_to_price(tick, &ob.asset_pair));
+        let best_ask = ob
+            .limit_asks
+            .iter()
+            .map(|order| order.tick)
+            .chain(range_active.then_some(ob.tick + 1))
+            .min()
+            .map(|tick| tick_to_price(tick, &ob.asset_pair));
+
+        let (range_base, range_quote) = range_amounts(ob, mid_price)?;
+        let base_unit = 10_f64.powi(asset_decimals(&ob.asset_pair.from)? as i32);
+        let quote_unit = 10_f64.powi(asset_decimals(&ob.asset_pair.to)? as i32);
+
+        Some(BookStats {
+            mid_price,
+            best_bid,
+            best_ask,
+            depth,
+            reference_notional: options.reference_notional,
+            avg_buy_price,
+            avg_sell_price,
+            effective_spread_bps,
+            range_base,
+            range_quote,
+            limit_base: u256_to_f64(ob.limit_ask_amount()) / base_unit,
+            limit_quote: u256_to_f64(ob.limit_bid_amount()) / quote_unit,
+        })
+    }
+}
+
+/// Average price trading `notional` of the quote asset through `levels`, nearest level first.
+/// Amounts within a level are assumed to trade at a constant price.
+fn average_price(levels: &[DepthLevel], notional: f64) -> Option<f64> {
+    let mut remaining = notional;
+    let mut base = 0.0;
+
+    for level in levels {
+        if level.quote_amount >= remaining {
+            base += level.base_amount * remaining / level.quote_amount;
+
+            return Some(notional / base);
+        }
+        remaining -= level.quote_amount;
+        base += level.base_amount;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use crate::{
+        model::{
+            asset_pair::AssetPair,
+            liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+            order_book::OrderBook,
+        },
+        util::tick_to_price,
+    };
+
+    use super::{BookStats, StatsOptions};
+
+    #[test]
+    fn test_book_stats() {
+        // a wide band around the price, and limit orders 10 ticks either side of it
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![LimitOrder {
+                        tick: 57050,
+                        amount: format!("{:#x}", 100_000_000),
+                    }],
+                    bids: vec![LimitOrder {
+                        tick: 57030,
+                        amount: format!("{:#x}", 30_000_000_000_u64),
+                    }],
+                },
+                range_orders: vec![
+                    RangeOrder {
+                        tick: 50000,
+                        liquidity: format!("{:#x}", 1_000_000_000_000_u64),
+                    },
+                    RangeOrder {
+                        tick: 64000,
+                        liquidity: "0x0".to_string(),
+                    },
+                ],
+            },
+        };
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let ob = OrderBook::new(&asset_pair, liquidity, U256::zero(), 57040);
+        let options = StatsOptions {
+            depth_bps: vec![10.0, 50.0, 100.0, 200.0],
+            reference_notional: 10_000.0,
+        };
+
+        let stats = BookStats::from_book(&ob, &options).unwrap();
+        // range liquidity is active at the price, so best prices are a tick step from it
+        assert_eq!(Some(tick_to_price(57040, &asset_pair)), stats.best_bid);
+        assert_eq!(Some(tick_to_price(57041, &asset_pair)), stats.best_ask);
+
+        // depth grows with the distance from the price
+        assert_eq!(4, stats.depth.len());
+        for pair in stats.depth.windows(2) {
+            assert!(pair[1].ask_base > pair[0].ask_base);
+            assert!(pair[1].bid_quote > pair[0].bid_quote);
+        }
+
+        let buy = stats.avg_buy_price.unwrap();
+        let sell = stats.avg_sell_price.unwrap();
+        assert!(sell < stats.mid_price && stats.mid_price < buy);
+        let spread = stats.effective_spread_bps.unwrap();
+        // about 9k of the notional fills from the band within 10 bps, the rest from the limit orders
+        assert!(spread > 10.0 && spread < 12.0, "spread {}", spread);
+
+        assert_eq!(1.0, stats.limit_base);
+        assert_eq!(30_000.0, stats.limit_quote);
+        assert!(stats.range_base > 0.0 && stats.range_quote > 0.0);
+    }
+}
@@ -0,0 +1,561 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::tick_math::{sqrt_price_at_tick, tick_at_sqrt_price, MAX_TICK, MIN_TICK};
+
+use super::{
+    asset_pair::AssetPair,
+    common::{SqrtPriceQ64F96, Tick},
+    liquidity::{LimitOrder, Liquidity, RangeOrder},
+    order_book::OrderBook,
+};
+
+/// What to do with a book whose node data fails validation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationPolicy {
+    /// Publish the book as it is, with its issues
+    #[default]
+    Warn,
+    /// Publish no book
+    Drop,
+    /// Fix the data where possible and publish the repaired book, with its issues
+    Repair,
+}
+
+impl FromStr for ValidationPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(ValidationPolicy::Warn),
+            "drop" => Ok(ValidationPolicy::Drop),
+            "repair" => Ok(ValidationPolicy::Repair),
+            _ => Err(format!(
+                "invalid validation policy {:?}, expected warn, drop or repair",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ValidationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationPolicy::Warn => write!(f, "warn"),
+            ValidationPolicy::Drop => write!(f, "drop"),
+            ValidationPolicy::Repair => write!(f, "repair"),
+        }
+    }
+}
+
+/// What is wrong with the data a book is built from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookIssueKind {
+    /// A range order tick below the one listed before it
+    UnsortedTicks,
+    /// A tick listed twice among the range orders or on one side of the limit orders
+    DuplicateTick,
+    /// The highest limit bid is above the lowest limit ask
+    CrossedLimitOrders,
+    /// A band with no liquidity, the node lists these for gaps between positions
+    EmptyBand,
+    /// A tick outside `MIN_TICK..=MAX_TICK`
+    TickOutOfRange,
+    /// The pool's tick is not the tick its sqrt price is in
+    SqrtPriceMismatch,
+    /// An amount or liquidity which is not a hex number
+    InvalidAmount,
+}
+
+impl BookIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BookIssueKind::UnsortedTicks => "unsorted_ticks",
+            BookIssueKind::DuplicateTick => "duplicate_tick",
+            BookIssueKind::CrossedLimitOrders => "crossed_limit_orders",
+            BookIssueKind::EmptyBand => "empty_band",
+            BookIssueKind::TickOutOfRange => "tick_out_of_range",
+            BookIssueKind::SqrtPriceMismatch => "sqrt_price_mismatch",
+            BookIssueKind::InvalidAmount => "invalid_amount",
+        }
+    }
+
+    /// Whether the issue is normal for node data, rather than an inconsistency. Books with only
+    /// these are published as they are whatever the policy.
+    pub fn is_informational(&self) -> bool {
+        matches!(self, BookIssueKind::EmptyBand)
+    }
+
+    /// Whether a book can't be built without repairing the issue first
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            BookIssueKind::TickOutOfRange | BookIssueKind::InvalidAmount
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BookIssue {
+    pub kind: BookIssueKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick: Option<Tick>,
+    pub detail: String,
+}
+
+impl BookIssue {
+    fn new(kind: BookIssueKind, tick: Option<Tick>, detail: String) -> Self {
+        BookIssue { kind, tick, detail }
+    }
+}
+
+/// Issues found in the data of a book, and whether it was published
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ValidationReport {
+    pub asset_pair: AssetPair,
+    pub issues: Vec<BookIssue>,
+    pub policy: ValidationPolicy,
+    /// Whether a book was published, as it was or repaired
+    pub published: bool,
+}
+
+impl ValidationReport {
+    /// Whether any issue is an inconsistency rather than informational
+    pub fn has_problems(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| !issue.kind.is_informational())
+    }
+
+    /// Number of issues of each kind
+    pub fn counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for issue in self.issues.iter() {
+            *counts.entry(issue.kind.as_str()).or_default() += 1;
+        }
+
+        counts
+    }
+}
+
+fn parse_amount(amount: &str) -> Option<U256> {
+    U256::from_str_radix(amount.trim_start_matches("0x"), 16).ok()
+}
+
+fn in_range(tick: Tick) -> bool {
+    (MIN_TICK..=MAX_TICK).contains(&tick)
+}
+
+/// Whether `tick` is the pool's tick at `sqrt_price`. A swap down ending exactly on a tick leaves
+/// the pool in the tick below, so that is consistent too.
+fn tick_matches_sqrt_price(sqrt_price: SqrtPriceQ64F96, tick: Tick) -> bool {
+    let expected = tick_at_sqrt_price(sqrt_price);
+
+    tick == expected || (tick == expected - 1 && sqrt_price == sqrt_price_at_tick(expected))
+}
+
+/// Check the liquidity and price a book would be built from
+pub fn validate(
+    liquidity: &Liquidity,
+    sqrt_price_x96: SqrtPriceQ64F96,
+    tick: Tick,
+) -> Vec<BookIssue> {
+    let mut issues = Vec::new();
+
+    if !in_range(tick) {
+        issues.push(BookIssue::new(
+            BookIssueKind::TickOutOfRange,
+            Some(tick),
+            "pool tick".to_string(),
+        ));
+    } else if !sqrt_price_x96.is_zero() && !tick_matches_sqrt_price(sqrt_price_x96, tick) {
+        issues.push(BookIssue::new(
+            BookIssueKind::SqrtPriceMismatch,
+            Some(tick),
+            format!(
+                "sqrt price {} is in tick {}",
+                sqrt_price_x96,
+                tick_at_sqrt_price(sqrt_price_x96)
+            ),
+        ));
+    }
+
+    let range_orders = &liquidity.result.range_orders;
+    for order in range_orders.iter() {
+        if !in_range(order.tick) {
+            issues.push(BookIssue::new(
+                BookIssueKind::TickOutOfRange,
+                Some(order.tick),
+                "range order".to_string(),
+            ));
+        }
+        if parse_amount(&order.liquidity).is_none() {
+            issues.push(BookIssue::new(
+                BookIssueKind::InvalidAmount,
+                Some(order.tick),
+                format!("range order liquidity {:?}", order.liquidity),
+            ));
+        }
+    }
+    for (start, end) in range_orders.iter().zip(range_orders.iter().skip(1)) {
+        if end.tick < start.tick {
+            issues.push(BookIssue::new(
+                BookIssueKind::UnsortedTicks,
+                Some(end.tick),
+                format!("range order after tick {}", start.tick),
+            ));
+        } else if end.tick == start.tick {
+            issues.push(BookIssue::new(
+                BookIssueKind::DuplicateTick,
+                Some(end.tick),
+                "range order".to_string(),
+            ));
+        } else if parse_amount(&start.liquidity).is_some_and(|liquidity| liquidity.is_zero()) {
+            issues.push(BookIssue::new(
+                BookIssueKind::EmptyBand,
+                Some(start.tick),
+                format!("no liquidity up to tick {}", end.tick),
+            ));
+        }
+    }
+
+    let limit_orders = &liquidity.result.limit_orders;
+    for (side, orders) in [("bid", &limit_orders.bids), ("ask", &limit_orders.asks)] {
+        let mut seen = BTreeSet::new();
+        for order in orders.iter() {
+            if !in_range(order.tick) {
+                issues.push(BookIssue::new(
+                    BookIssueKind::TickOutOfRange,
+                    Some(order.tick),
+                    format!("limit {}", side),
+                ));
+            }
+            if parse_amount(&order.amount).is_none() {
+                issues.push(BookIssue::new(
+                    BookIssueKind::InvalidAmount,
+                    Some(order.tick),
+                    format!("limit {} amount {:?}", side, order.amount),
+                ));
+            }
+            if !seen.insert(order.tick) {
+                issues.push(BookIssue::new(
+                    BookIssueKind::DuplicateTick,
+                    Some(order.tick),
+                    format!("limit {}", side),
+                ));
+            }
+        }
+    }
+
+    let highest_bid = limit_orders.bids.iter().map(|order| order.tick).max();
+    let lowest_ask = limit_orders.asks.iter().map(|order| order.tick).min();
+    if let (Some(bid), Some(ask)) = (highest_bid, lowest_ask) {
+        if bid > ask {
+            issues.push(BookIssue::new(
+                BookIssueKind::CrossedLimitOrders,
+                Some(bid),
+                format!("bid above the ask at tick {}", ask),
+            ));
+        }
+    }
+
+    issues
+}
+
+/// Fix what `validate` finds: orders with invalid amounts or ticks out of range are dropped, range
+/// orders sorted keeping the last listed at a tick, and limit orders deduplicated the same way. The
+/// tick is taken from the sqrt price, or clamped to the range if there is none. Crossed limit
+/// orders are resolved by dropping bids above the pool's tick and asks below it.
+pub fn repair(
+    mut liquidity: Liquidity,
+    sqrt_price_x96: SqrtPriceQ64F96,
+    tick: Tick,
+) -> (Liquidity, Tick) {
+    let tick = if sqrt_price_x96.is_zero() {
+        tick.clamp(MIN_TICK, MAX_TICK)
+    } else if in_range(tick) && tick_matches_sqrt_price(sqrt_price_x96, tick) {
+        tick
+    } else {
+        tick_at_sqrt_price(sqrt_price_x96)
+    };
+
+    let range_orders: BTreeMap<Tick, RangeOrder> = liquidity
+        .result
+        .range_orders
+        .into_iter()
+        .filter(|order| in_range(order.tick) && parse_amount(&order.liquidity).is_some())
+        .map(|order| (order.tick, order))
+        .collect();
+    liquidity.result.range_orders = range_orders.into_values().collect();
+
+    let dedup = |orders: Vec<LimitOrder>| -> Vec<LimitOrder> {
+        let orders: BTreeMap<Tick, LimitOrder> = orders
+            .into_iter()
+            .filter(|order| in_range(order.tick) && parse_amount(&order.amount).is_some())
+            .map(|order| (order.tick, order))
+            .collect();
+
+        orders.into_values().collect()
+    };
+    let limit_orders = &mut liquidity.result.limit_orders;
+    limit_orders.bids = dedup(std::mem::take(&mut limit_orders.bids));
+    limit_orders.asks = dedup(std::mem::take(&mut limit_orders.asks));
+
+    let highest_bid = limit_orders.bids.iter().map(|order| order.tick).max();
+    let lowest_ask = limit_orders.asks.iter().map(|order| order.tick).min();
+    if let (Some(bid), Some(ask)) = (highest_bid, lowest_ask) {
+        if bid > ask {
+            limit_orders.bids.retain(|order| order.tick <= tick);
+            limit_orders.asks.retain(|order| order.tick >= tick);
+        }
+    }
+
+    (liquidity, tick)
+}
+
+/// Validate the data of a book for `asset_pair` and build it according to `policy`. Books with
+/// fatal issues are only published repaired, books with only informational issues are published as
+/// they are.
+pub fn build_validated_book(
+    asset_pair: &AssetPair,
+    liquidity: Liquidity,
+    sqrt_price_x96: SqrtPriceQ64F96,
+    tick: Tick,
+    policy: ValidationPolicy,
+) -> (Option<OrderBook>, ValidationReport) {
+    let issues = validate(&liquidity, sqrt_price_x96, tick);
+    let fatal = issues.iter().any(|issue| issue.kind.is_fatal());
+    let informational = issues.iter().all(|issue| issue.kind.is_informational());
+
+    let ob = match policy {
+        _ if issues.is_empty() => Some(OrderBook::new(asset_pair, liquidity, sqrt_price_x96, tick)),
+        ValidationPolicy::Warn | ValidationPolicy::Drop if informational => {
+            Some(OrderBook::new(asset_pair, liquidity, sqrt_price_x96, tick))
+        }
+        ValidationPolicy::Warn if !fatal => {
+            Some(OrderBook::new(asset_pair, liquidity, sqrt_price_x96, tick))
+        }
+        ValidationPolicy::Warn | ValidationPolicy::Drop => None,
+        ValidationPolicy::Repair => {
+            let (liquidity, tick) = repair(liquidity, sqrt_price_x96, tick);
+            let mut ob = OrderBook::new(asset_pair, liquidity, sqrt_price_x96, tick);
+            ob.range_orders.retain(|band| !band.liquidity.is_zero());
+
+            Some(ob)
+        }
+    };
+
+    let report = ValidationReport {
+        asset_pair: asset_pair.clone(),
+        issues,
+        policy,
+        published: ob.is_some(),
+    };
+
+    (ob, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use crate::{
+        model::{
+            asset_pair::AssetPair,
+            liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+        },
+        tick_math::sqrt_price_at_tick,
+    };
+
+    use super::{build_validated_book, validate, BookIssueKind, ValidationPolicy};
+
+    fn liquidity(range_orders: Vec<(i32, &str)>, bids: Vec<i32>, asks: Vec<i32>) -> Liquidity {
+        let limit_orders = |ticks: Vec<i32>| -> Vec<LimitOrder> {
+            ticks
+                .into_iter()
+                .map(|tick| LimitOrder {
+                    tick,
+                    amount: "0x100".to_string(),
+                })
+                .collect()
+        };
+
+        Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: limit_orders(asks),
+                    bids: limit_orders(bids),
+                },
+                range_orders: range_orders
+                    .into_iter()
+                    .map(|(tick, liquidity)| RangeOrder {
+                        tick,
+                        liquidity: liquidity.to_string(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    fn kinds(liquidity: &Liquidity, sqrt_price: U256, tick: i32) -> Vec<BookIssueKind> {
+        validate(liquidity, sqrt_price, tick)
+            .into_iter()
+            .map(|issue| issue.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_book() {
+        let liquidity = liquidity(
+            vec![(56000, "0x1000"), (57000, "0x2000"), (58000, "0x0")],
+            vec![57030],
+            vec![57050],
+        );
+        assert!(kinds(&liquidity, sqrt_price_at_tick(57040) + 1, 57040).is_empty());
+        // exactly on a tick after a swap down
+        assert!(kinds(&liquidity, sqrt_price_at_tick(57040), 57039).is_empty());
+    }
+
+    #[test]
+    fn test_issues() {
+        let liquidity = liquidity(
+            vec![
+                (56000, "0x1000"),
+                (57000, "0x0"),
+                (57500, "0x2000"),
+                (57500, "0x3000"),
+                (57400, "0x0"),
+                (900_000, "0xzz"),
+            ],
+            vec![57100, 57030, 57030],
+            vec![57050],
+        );
+        let kinds = kinds(&liquidity, sqrt_price_at_tick(57040), 57000);
+
+        assert_eq!(
+            vec![
+                BookIssueKind::SqrtPriceMismatch,
+                BookIssueKind::TickOutOfRange,
+                BookIssueKind::InvalidAmount,
+                BookIssueKind::EmptyBand,
+                BookIssueKind::DuplicateTick,
+                BookIssueKind::UnsortedTicks,
+                BookIssueKind::EmptyBand,
+                BookIssueKind::DuplicateTick,
+                BookIssueKind::CrossedLimitOrders,
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn test_policies() {
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let sqrt_price = sqrt_price_at_tick(57040) + 1;
+        let crossed = liquidity(
+            vec![
+                (57500, "0x2000"),
+                (56000, "0x1000"),
+                (57000, "0x0"),
+                (58000, "0x0"),
+            ],
+            vec![57100, 57030],
+            vec![57050],
+        );
+
+        let (ob, report) = build_validated_book(
+            &asset_pair,
+            crossed.clone(),
+            sqrt_price,
+            57040,
+            ValidationPolicy::Warn,
+        );
+        assert!(report.published);
+        assert_eq!(3, ob.unwrap().range_orders.len());
+        assert_eq!(Some(&1), report.counts().get("crossed_limit_orders"));
+
+        let (ob, report) = build_validated_book(
+            &asset_pair,
+            crossed.clone(),
+            sqrt_price,
+            57040,
+            ValidationPolicy::Drop,
+        );
+        assert!(ob.is_none());
+        assert!(!report.published);
+
+        // sorted, the empty band and the bid above the price dropped
+        let (ob, report) = build_validated_book(
+            &asset_pair,
+            crossed,
+            sqrt_price,
+            57040,
+            ValidationPolicy::Repair,
+        );
+        let ob = ob.unwrap();
+        assert!(report.published);
+        assert_eq!(2, ob.range_orders.len());
+        assert_eq!(57000, ob.range_orders[0].end_tick);
+        assert_eq!(57500, ob.range_orders[1].start_tick);
+        assert_eq!(1, ob.limit_bids.len());
+        assert_eq!(57030, ob.limit_bids[0].tick);
+
+        // a book with an invalid amount can't be built as it is
+        let invalid = liquidity(vec![(56000, "0xzz"), (57000, "0x0")], vec![], vec![]);
+        let (ob, report) = build_validated_book(
+            &asset_pair,
+            invalid.clone(),
+            sqrt_price,
+            57040,
+            ValidationPolicy::Warn,
+        );
+        assert!(ob.is_none());
+        assert!(!report.published);
+        let (ob, _) = build_validated_book(
+            &asset_pair,
+            invalid,
+            sqrt_price,
+            57040,
+            ValidationPolicy::Repair,
+        );
+        assert!(ob.unwrap().range_orders.is_empty());
+    }
+
+    #[test]
+    fn test_gapped_pool_is_published() {
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let gapped = liquidity(
+            vec![
+                (56000, "0x1000"),
+                (57000, "0x0"),
+                (57500, "0x2000"),
+                (58000, "0x0"),
+            ],
+            vec![57030],
+            vec![57050],
+        );
+
+        // a gap between positions is informational, even dropping books with issues
+        let (ob, report) = build_validated_book(
+            &asset_pair,
+            gapped,
+            sqrt_price_at_tick(57040) + 1,
+            57040,
+            ValidationPolicy::Drop,
+        );
+        assert!(report.published);
+        assert!(!report.has_problems());
+        assert_eq!(Some(&1), report.counts().get("empty_band"));
+        assert_eq!(3, ob.unwrap().range_orders.len());
+    }
+}
@@ -0,0 +1,501 @@
+use std::{fmt, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{
+    tick_math::{raw_sqrt_price_at_tick, MAX_TICK, MIN_TICK},
+    util::{asset_decimals, u256_to_f64},
+};
+
+use super::{asset_pair::AssetPair, order_book::OrderBook};
+
+/// Width of the price levels of a depth ladder
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthGranularity {
+    /// Every `n` ticks, aligned to multiples of `n`
+    Ticks(u32),
+    /// Every `n` basis points away from the mid price
+    Bps(f64),
+    /// Every `step` of price, aligned to multiples of `step`
+    Price(f64),
+}
+
+impl FromStr for DepthGranularity {
+    type Err = String;
+
+    /// Parse `ticks:<n>`, `bps:<n>` or `price:<step>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid depth granularity {:?}, expected ticks:<n>, bps:<n> or price:<step>",
+                s
+            )
+        };
+        let (kind, width) = s.split_once(':').ok_or_else(invalid)?;
+
+        match kind {
+            "ticks" => match width.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(DepthGranularity::Ticks(n)),
+                _ => Err(invalid()),
+            },
+            "bps" | "price" => match width.parse::<f64>() {
+                Ok(width) if width.is_finite() && width > 0.0 => Ok(if kind == "bps" {
+                    DepthGranularity::Bps(width)
+                } else {
+                    DepthGranularity::Price(width)
+                }),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for DepthGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DepthGranularity::Ticks(n) => write!(f, "ticks:{}", n),
+            DepthGranularity::Bps(n) => write!(f, "bps:{}", n),
+            DepthGranularity::Price(step) => write!(f, "price:{}", step),
+        }
+    }
+}
+
+/// How a depth ladder is aggregated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthOptions {
+    pub granularity: DepthGranularity,
+    /// Price levels per side, counted outwards from the mid price
+    pub levels: usize,
+}
+
+/// Amounts tradable at a price level, in whole units of each asset
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DepthLevel {
+    /// Outer edge of the level, its lowest price for bids and highest for asks
+    pub price: f64,
+    /// Base asset bought from asks, or sold into bids, trading through the level
+    pub base_amount: f64,
+    /// Quote asset paid for asks, or received from bids, trading through the level
+    pub quote_amount: f64,
+}
+
+/// Range and limit liquidity of a book aggregated into price levels either side of the mid price,
+/// nearest level first. Levels without liquidity are left out.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DepthLadder {
+    pub granularity: String,
+    pub mid_price: f64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// A price level's bounds, as prices and as square roots of the pool's raw price
+struct Bucket {
+    price: f64,
+    lo_sqrt: f64,
+    hi_sqrt: f64,
+}
+
+/// Converts between prices in whole units and the pool's raw sqrt price and ticks
+pub(crate) struct Scale {
+    pub(crate) base_unit: f64,
+    pub(crate) quote_unit: f64,
+}
+
+impl Scale {
+    /// `None` if the decimals of either asset are unknown
+    pub(crate) fn of(asset_pair: &AssetPair) -> Option<Self> {
+        Some(Scale {
+            base_unit: 10_f64.powi(asset_decimals(&asset_pair.from)? as i32),
+            quote_unit: 10_f64.powi(asset_decimals(&asset_pair.to)? as i32),
+        })
+    }
+
+    /// Raw price per whole unit price
+    pub(crate) fn raw(&self) -> f64 {
+        self.quote_unit / self.base_unit
+    }
+
+    pub(crate) fn price_at_tick(&self, tick: f64) -> f64 {
+        self.sqrt_at_tick(tick).powi(2) / self.raw()
+    }
+
+    /// Raw sqrt price at `tick`, exact at whole ticks so it agrees with the pool's band edges
+    fn sqrt_at_tick(&self, tick: f64) -> f64 {
+        if tick.fract() == 0.0 && (MIN_TICK as f64..=MAX_TICK as f64).contains(&tick) {
+            raw_sqrt_price_at_tick(tick as i32)
+        } else {
+            1.0001_f64.powf(tick / 2.0)
+        }
+    }
+
+    fn tick_at_price(&self, price: f64) -> f64 {
+        (price * self.raw()).ln() / 1.0001_f64.ln()
+    }
+
+    pub(crate) fn sqrt_at_price(&self, price: f64) -> f64 {
+        (price * self.raw()).sqrt()
+    }
+
+    /// Price of `ob`'s pool, from its sqrt price or its tick if the sqrt price is unknown
+    pub(crate) fn mid_price(&self, ob: &OrderBook) -> f64 {
+        if ob.sqrt_price_x96.is_zero() {
+            self.price_at_tick(ob.tick as f64)
+        } else {
+            (u256_to_f64(ob.sqrt_price_x96) / 2_f64.powi(96)).powi(2) / self.raw()
+        }
+    }
+
+    fn tick_bucket(&self, lo: f64, hi: f64, price: f64) -> Bucket {
+        Bucket {
+            price,
+            lo_sqrt: self.sqrt_at_tick(lo),
+            hi_sqrt: self.sqrt_at_tick(hi),
+        }
+    }
+
+    fn bucket(&self, lo: f64, hi: f64, price: f64) -> Bucket {
+        Bucket {
+            price,
+            lo_sqrt: self.sqrt_at_price(lo),
+            hi_sqrt: self.sqrt_at_price(hi),
+        }
+    }
+}
+
+impl DepthLadder {
+    /// Aggregate the liquidity of `ob`, `None` if the decimals of either asset are unknown
+    pub fn from_book(ob: &OrderBook, options: &DepthOptions) -> Option<Self> {
+        let scale = Scale::of(&ob.asset_pair)?;
+        let mid_price = scale.mid_price(ob);
+
+        let (bid_buckets, ask_buckets) = buckets(&scale, mid_price, options);
+        let mut bids = levels(ob, &scale, &bid_buckets);
+        let mut asks = levels(ob, &scale, &ask_buckets);
+
+        for order in ob.limit_bids.iter() {
+            let price = scale.price_at_tick(order.tick as f64);
+            let Some(index) = bid_buckets.iter().position(|b| price >= b.price) else {
+                continue;
+            };
+            let quote_amount = u256_to_f64(order.amount) / scale.quote_unit;
+            bids[index].base_amount += quote_amount / price;
+            bids[index].quote_amount += quote_amount;
+        }
+        for order in ob.limit_asks.iter() {
+            let price = scale.price_at_tick(order.tick as f64);
+            let Some(index) = ask_buckets.iter().position(|b| price <= b.price) else {
+                continue;
+            };
+            let base_amount = u256_to_f64(order.amount) / scale.base_unit;
+            asks[index].base_amount += base_amount;
+            asks[index].quote_amount += base_amount * price;
+        }
+
+        let non_empty = |level: &DepthLevel| level.base_amount > 0.0 || level.quote_amount > 0.0;
+        bids.retain(non_empty);
+        asks.retain(non_empty);
+
+        Some(DepthLadder {
+            granularity: options.granularity.to_string(),
+            mid_price,
+            bids,
+            asks,
+        })
+    }
+}
+
+/// Range liquidity of `ob` in whole units, as the base asset above `mid_price` and the quote asset
+/// below it. `None` if the decimals of either asset are unknown.
+pub(crate) fn range_amounts(ob: &OrderBook, mid_price: f64) -> Option<(f64, f64)> {
+    let scale = Scale::of(&ob.asset_pair)?;
+    let mid = scale.sqrt_at_price(mid_price);
+    let above = scale.bucket(mid_price, f64::INFINITY, f64::INFINITY);
+    let below = Bucket {
+        price: 0.0,
+        lo_sqrt: 0.0,
+        hi_sqrt: mid,
+    };
+    let levels = levels(ob, &scale, &[above, below]);
+
+    Some((levels[0].base_amount, levels[1].quote_amount))
+}
+
+/// Bid and ask levels outwards from `mid_price`
+fn buckets(scale: &Scale, mid_price: f64, options: &DepthOptions) -> (Vec<Bucket>, Vec<Bucket>) {
+    let mut bids = Vec::new();
+    let mut asks = Vec::new();
+
+    match options.granularity {
+        DepthGranularity::Ticks(n) => {
+            let n = n as f64;
+            let mid_tick = scale.tick_at_price(mid_price);
+            let (below, above) = ((mid_tick / n).ceil(), (mid_tick / n).floor());
+            for k in 0..options.levels {
+                let k = k as f64;
+                let (lo, hi) = (
+                    ((below - k - 1.0) * n).max(MIN_TICK as f64),
+                    ((below - k) * n).min(mid_tick),
+                );
+                if lo < hi {
+                    bids.push(scale.tick_bucket(lo, hi, scale.price_at_tick(lo)));
+                }
+                let (lo, hi) = (
+                    ((above + k) * n).max(mid_tick),
+                    ((above + k + 1.0) * n).min(MAX_TICK as f64),
+                );
+                if lo < hi {
+                    asks.push(scale.tick_bucket(lo, hi, scale.price_at_tick(hi)));
+                }
+            }
+        }
+        DepthGranularity::Bps(n) => {
+            let width = mid_price * n / 10_000.0;
+            for k in 0..options.levels {
+                let k = k as f64;
+                let hi = mid_price - k * width;
+                if hi > 0.0 {
+                    let lo = (hi - width).max(0.0);
+                    bids.push(scale.bucket(lo, hi, lo));
+                }
+                let lo = mid_price + k * width;
+                asks.push(scale.bucket(lo, lo + width, lo + width));
+            }
+        }
+        DepthGranularity::Price(step) => {
+            let (below, above) = ((mid_price / step).ceil(), (mid_price / step).floor());
+            for k in 0..options.levels {
+                let k = k as f64;
+                let hi = ((below - k) * step).min(mid_price);
+                if hi > 0.0 {
+                    let lo = ((below - k - 1.0) * step).max(0.0);
+                    bids.push(scale.bucket(lo, hi, lo));
+                }
+                let (lo, hi) = (
+                    ((above + k) * step).max(mid_price),
+                    (above + k + 1.0) * step,
+                );
+                asks.push(scale.bucket(lo, hi, hi));
+            }
+        }
+    }
+
+    (bids, asks)
+}
+
+/// Range liquidity of `ob` within each of `buckets`. Trading across a band of liquidity `L` between
+/// sqrt prices `a` and `b` exchanges `L * (1/a - 1/b)` base for `L * (b - a)` quote.
+fn levels(ob: &OrderBook, scale: &Scale, buckets: &[Bucket]) -> Vec<DepthLevel> {
+    let bands: Vec<_> = ob
+        .range_orders
+        .iter()
+        .filter(|band| !band.liquidity.is_zero())
+        .map(|band| {
+            (
+                raw_sqrt_price_at_tick(band.start_tick),
+                raw_sqrt_price_at_tick(band.end_tick),
+                u256_to_f64(band.liquidity),
+            )
+        })
+        .collect();
+
+    buckets
+        .iter()
+        .map(|bucket| {
+            let (base, quote) = bands.iter().fold(
+                (0.0, 0.0),
+                |(base, quote), (band_lo, band_hi, liquidity)| {
+                    let lo = bucket.lo_sqrt.max(*band_lo);
+                    let hi = bucket.hi_sqrt.min(*band_hi);
+                    if lo >= hi {
+                        return (base, quote);
+                    }
+
+                    (
+                        base + liquidity * (1.0 / lo - 1.0 / hi),
+                        quote + liquidity * (hi - lo),
+                    )
+                },
+            );
+
+            DepthLevel {
+                price: bucket.price,
+                base_amount: base / scale.base_unit,
+                quote_amount: quote / scale.quote_unit,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+    use primitive_types::U256;
+
+    use crate::{
+        model::{
+            asset_pair::AssetPair,
+            liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+            order_book::OrderBook,
+        },
+        tick_math::raw_sqrt_price_at_tick,
+        util::tick_to_price,
+    };
+
+    use super::{DepthGranularity, DepthLadder, DepthOptions};
+
+    fn assert_price(expected: f64, actual: f64) {
+        assert!(
+            (actual / expected - 1.0).abs() < 1e-9,
+            "expected price {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn btc_usdc_book(
+        range_orders: Vec<(i32, u64)>,
+        asks: Vec<(i32, u64)>,
+        bids: Vec<(i32, u64)>,
+    ) -> OrderBook {
+        let order = |(tick, amount): (i32, u64)| LimitOrder {
+            tick,
+            amount: format!("{:#x}", amount),
+        };
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: asks.into_iter().map(order).collect(),
+                    bids: bids.into_iter().map(order).collect(),
+                },
+                range_orders: range_orders
+                    .into_iter()
+                    .map(|(tick, liquidity)| RangeOrder {
+                        tick,
+                        liquidity: format!("{:#x}", liquidity),
+                    })
+                    .collect(),
+            },
+        };
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+
+        OrderBook::new(&asset_pair, liquidity, U256::zero(), 57040)
+    }
+
+    #[test]
+    fn test_parse_granularity() {
+        assert_eq!(Ok(DepthGranularity::Ticks(10)), "ticks:10".parse());
+        assert_eq!(Ok(DepthGranularity::Bps(2.5)), "bps:2.5".parse());
+        assert_eq!(Ok(DepthGranularity::Price(0.01)), "price:0.01".parse());
+        assert!("ticks:0".parse::<DepthGranularity>().is_err());
+        assert!("bps:-1".parse::<DepthGranularity>().is_err());
+        assert!("levels:10".parse::<DepthGranularity>().is_err());
+    }
+
+    #[test]
+    fn test_range_liquidity_per_tick_bucket() {
+        let liquidity = 1_000_000_000_000;
+        let ob = btc_usdc_book(vec![(57000, liquidity), (57100, 0)], vec![], vec![]);
+        let options = DepthOptions {
+            granularity: DepthGranularity::Ticks(10),
+            levels: 10,
+        };
+
+        let ladder = DepthLadder::from_book(&ob, &options).unwrap();
+        let btc_usdc = &ob.asset_pair;
+        assert_price(tick_to_price(57040, btc_usdc), ladder.mid_price);
+        // the band ends 60 ticks above and starts 40 ticks below the price
+        assert_eq!(6, ladder.asks.len());
+        assert_eq!(4, ladder.bids.len());
+        assert_price(tick_to_price(57050, btc_usdc), ladder.asks[0].price);
+        assert_price(tick_to_price(57030, btc_usdc), ladder.bids[0].price);
+        assert_price(tick_to_price(57000, btc_usdc), ladder.bids[3].price);
+
+        // the levels add up to the band's amounts either side of the price
+        let sqrt = |tick: f64| raw_sqrt_price_at_tick(tick as i32);
+        let liquidity = liquidity as f64;
+        let ask_base: f64 = ladder.asks.iter().map(|level| level.base_amount).sum();
+        let bid_quote: f64 = ladder.bids.iter().map(|level| level.quote_amount).sum();
+        assert!(approx_eq!(
+            f64,
+            liquidity * (1.0 / sqrt(57040.0) - 1.0 / sqrt(57100.0)) / 1e8,
+            ask_base,
+            epsilon = 1e-12
+        ));
+        assert!(approx_eq!(
+            f64,
+            liquidity * (sqrt(57040.0) - sqrt(57000.0)) / 1e6,
+            bid_quote,
+            epsilon = 1e-9
+        ));
+        // a level's quote amount is close to its base amount at the level's price
+        let level = &ladder.asks[0];
+        assert!((level.quote_amount / level.base_amount / level.price - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_limit_orders_merged_into_levels() {
+        let ob = btc_usdc_book(
+            vec![],
+            vec![(57055, 100_000_000), (60000, 100_000_000)],
+            vec![(57030, 30_000_000_000)],
+        );
+        let options = DepthOptions {
+            granularity: DepthGranularity::Ticks(10),
+            levels: 10,
+        };
+
+        let ladder = DepthLadder::from_book(&ob, &options).unwrap();
+        // the ask beyond the last level is left out
+        assert_eq!(1, ladder.asks.len());
+        assert_price(tick_to_price(57060, &ob.asset_pair), ladder.asks[0].price);
+        assert_eq!(1.0, ladder.asks[0].base_amount);
+
+        assert_eq!(1, ladder.bids.len());
+        assert_price(tick_to_price(57030, &ob.asset_pair), ladder.bids[0].price);
+        assert_eq!(30_000.0, ladder.bids[0].quote_amount);
+    }
+
+    #[test]
+    fn test_bps_and_price_buckets() {
+        let ob = btc_usdc_book(
+            vec![],
+            vec![(57100, 100_000_000)],
+            vec![(56900, 100_000_000)],
+        );
+        let mid_price = tick_to_price(57040, &ob.asset_pair);
+
+        let bps = DepthLadder::from_book(
+            &ob,
+            &DepthOptions {
+                granularity: DepthGranularity::Bps(5.0),
+                levels: 40,
+            },
+        )
+        .unwrap();
+        // 60 ticks is about 60 bps, the 13th level of 5 bps
+        assert_price(mid_price * (1.0 + 13.0 * 5.0 / 10_000.0), bps.asks[0].price);
+        assert_price(mid_price * (1.0 - 28.0 * 5.0 / 10_000.0), bps.bids[0].price);
+
+        let price = DepthLadder::from_book(
+            &ob,
+            &DepthOptions {
+                granularity: DepthGranularity::Price(100.0),
+                levels: 10,
+            },
+        )
+        .unwrap();
+        assert_eq!("price:100", price.granularity);
+        assert_eq!(
+            (tick_to_price(57100, &ob.asset_pair) / 100.0).ceil() * 100.0,
+            price.asks[0].price
+        );
+        assert_eq!(
+            (tick_to_price(56900, &ob.asset_pair) / 100.0).floor() * 100.0,
+            price.bids[0].price
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ChainflipJsonRpcRequest {
     jsonrpc: String,
     id: String,
@@ -11,9 +11,50 @@ pub struct ChainflipJsonRpcRequest {
     params: HashMap<String, String>,
 }
 
+impl ChainflipJsonRpcRequest {
+    pub fn new(id: &str, method: &str, params: HashMap<String, String>) -> Self {
+        ChainflipJsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: id.to_string(),
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+
+    /// Key identifying the method and params of a request, independent of its id and of
+    /// param ordering. Used to match replayed responses to requests.
+    pub fn key(&self) -> String {
+        let mut params: Vec<_> = self.params.iter().collect();
+        params.sort();
+
+        let params: Vec<String> = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        format!("{}({})", self.method, params.join(","))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonRpcResponse {
     pub jsonrpc: String,
     pub id: String,
     pub result: String,
 }
+
+/// Response to a request acknowledged with a boolean, such as `cf_unsubscribe_pool_price`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRpcAck {
+    pub jsonrpc: String,
+    pub id: String,
+    pub result: bool,
+}
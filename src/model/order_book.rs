@@ -1,38 +1,44 @@
-use crate::util::{hex_string_to_u256, tick_to_price};
+use primitive_types::U256;
+use serde::Serialize;
+
+use crate::{
+    tick_math::tick_at_sqrt_price,
+    util::{hex_string_to_u256, tick_to_price},
+};
 
 use super::{
     asset_pair::AssetPair,
+    book_stats::BookStats,
+    book_validation::BookIssue,
     common::{Amount, SqrtPriceQ64F96, Tick},
+    depth::DepthLadder,
     liquidity::Liquidity,
+    pool_info::PoolInfo,
+    timestamp::BookLatency,
 };
 
-#[derive(Debug)]
-enum Side {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
     Buy,
     Sell,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct LimitOrder {
-    #[allow(unused)]
-    side: Side,
-    #[allow(unused)]
-    tick: Tick,
-    #[allow(unused)]
-    amount: Amount,
+    pub side: Side,
+    pub tick: Tick,
+    pub amount: Amount,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct RangeOrder {
-    #[allow(unused)]
-    start_tick: Tick,
-    #[allow(unused)]
-    end_tick: Tick,
-    #[allow(unused)]
-    liquidity: Amount,
+    pub start_tick: Tick,
+    pub end_tick: Tick,
+    pub liquidity: Amount,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct OrderBook {
     pub asset_pair: AssetPair,
     pub sqrt_price_x96: SqrtPriceQ64F96,
@@ -41,6 +47,23 @@ pub struct OrderBook {
     pub limit_bids: Vec<LimitOrder>,
     pub limit_asks: Vec<LimitOrder>,
     pub range_orders: Vec<RangeOrder>,
+    /// Built from a stale pool price
+    pub stale: bool,
+    /// Timings of the price update and liquidity request the book was built from, set by the
+    /// `OrderBookBuilder` when publishing
+    pub latency: Option<BookLatency>,
+    /// Price level ladder of the book's liquidity, set when publishing if configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<DepthLadder>,
+    /// Depth and spread summary of the book, set when publishing if configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<BookStats>,
+    /// Problems found validating the node data the book was built from, before any repair
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<BookIssue>,
+    /// Configuration of the pool, if it has been fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool_info: Option<PoolInfo>,
 }
 
 impl OrderBook {
@@ -94,21 +117,184 @@ impl OrderBook {
             limit_bids,
             limit_asks,
             range_orders,
+            stale: false,
+            latency: None,
+            depth: None,
+            stats: None,
+            issues: Vec::new(),
+            pool_info: None,
         }
     }
+
+    /// The same pool quoted the other way round, in `from` per `to`, trading exactly like this
+    /// book. Bids become asks and ticks are negated. The inverse sqrt price is rounded down, and
+    /// the tick is the one the inverse price falls in: `-tick - 1`, or `-tick` if the price is
+    /// exactly at `tick` or only the tick is known.
+    pub fn inverse(&self) -> OrderBook {
+        let asset_pair = self.asset_pair.inverse();
+        let sqrt_price_x96 = if self.sqrt_price_x96.is_zero() {
+            U256::zero()
+        } else {
+            (U256::one() << 192) / self.sqrt_price_x96
+        };
+        let tick = if sqrt_price_x96.is_zero() {
+            -self.tick
+        } else {
+            tick_at_sqrt_price(sqrt_price_x96)
+        };
+        let invert = |orders: &[LimitOrder], side: Side| {
+            orders
+                .iter()
+                .map(|order| LimitOrder {
+                    side,
+                    tick: -order.tick,
+                    amount: order.amount,
+                })
+                .collect()
+        };
+
+        OrderBook {
+            tick_price: tick_to_price(tick, &asset_pair),
+            asset_pair,
+            sqrt_price_x96,
+            tick,
+            // bids paid in the quote asset are offers of it, the new base asset
+            limit_bids: invert(&self.limit_asks, Side::Buy),
+            limit_asks: invert(&self.limit_bids, Side::Sell),
+            range_orders: self
+                .range_orders
+                .iter()
+                .rev()
+                .map(|band| RangeOrder {
+                    start_tick: -band.end_tick,
+                    end_tick: -band.start_tick,
+                    liquidity: band.liquidity,
+                })
+                .collect(),
+            stale: self.stale,
+            latency: self.latency,
+            depth: None,
+            stats: None,
+            issues: Vec::new(),
+            pool_info: self.pool_info.clone(),
+        }
+    }
+
+    /// Sum of liquidity over all range order bands
+    pub fn range_liquidity(&self) -> Amount {
+        self.range_orders
+            .iter()
+            .fold(Amount::zero(), |acc, r| acc.saturating_add(r.liquidity))
+    }
+
+    /// Sum of amounts over all limit bids
+    pub fn limit_bid_amount(&self) -> Amount {
+        self.limit_bids
+            .iter()
+            .fold(Amount::zero(), |acc, o| acc.saturating_add(o.amount))
+    }
+
+    /// Sum of amounts over all limit asks
+    pub fn limit_ask_amount(&self) -> Amount {
+        self.limit_asks
+            .iter()
+            .fold(Amount::zero(), |acc, o| acc.saturating_add(o.amount))
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use primitive_types::U256;
+    use proptest::prelude::*;
 
-    use crate::model::{
-        asset_pair::AssetPair,
-        common::{SqrtPriceQ64F96, Tick},
-        liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+    use crate::{
+        model::{
+            asset_pair::AssetPair,
+            common::{SqrtPriceQ64F96, Tick},
+            liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+        },
+        tick_math::{MAX_TICK, MIN_TICK},
+        util::hex_string_to_u256,
     };
 
-    use super::OrderBook;
+    use super::{OrderBook, Side};
+
+    fn orders() -> impl Strategy<Value = Vec<LimitOrder>> {
+        prop::collection::vec((MIN_TICK..=MAX_TICK, any::<u128>()), 0..8).prop_map(|orders| {
+            orders
+                .into_iter()
+                .map(|(tick, amount)| LimitOrder {
+                    tick,
+                    amount: format!("{:#x}", amount),
+                })
+                .collect()
+        })
+    }
+
+    fn liquidity() -> impl Strategy<Value = Liquidity> {
+        // range orders are listed by the node in tick order, one per tick
+        let range_orders = prop::collection::btree_map(MIN_TICK..=MAX_TICK, any::<u128>(), 0..16);
+        (orders(), orders(), range_orders).prop_map(|(asks, bids, range_orders)| Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2".to_string(),
+            result: Result {
+                limit_orders: LimitOrders { asks, bids },
+                range_orders: range_orders
+                    .into_iter()
+                    .map(|(tick, liquidity)| RangeOrder {
+                        tick,
+                        liquidity: format!("{:#x}", liquidity),
+                    })
+                    .collect(),
+            },
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_new_orderbook_bands(liquidity in liquidity(), tick in MIN_TICK..=MAX_TICK) {
+            let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+            let ob = OrderBook::new(&asset_pair, liquidity.clone(), U256::zero(), tick);
+            let range_orders = &liquidity.result.range_orders;
+
+            // contiguous bands between consecutive ticks, each with its start tick's liquidity
+            prop_assert_eq!(range_orders.len().saturating_sub(1), ob.range_orders.len());
+            for (band, (start, end)) in ob
+                .range_orders
+                .iter()
+                .zip(range_orders.iter().zip(range_orders.iter().skip(1)))
+            {
+                prop_assert!(band.start_tick < band.end_tick);
+                prop_assert_eq!(start.tick, band.start_tick);
+                prop_assert_eq!(end.tick, band.end_tick);
+                prop_assert_eq!(hex_string_to_u256(&start.liquidity), band.liquidity);
+            }
+            for (band, next) in ob.range_orders.iter().zip(ob.range_orders.iter().skip(1)) {
+                prop_assert_eq!(band.end_tick, next.start_tick);
+            }
+
+            // limit orders are kept as they are, on their side
+            prop_assert_eq!(liquidity.result.limit_orders.asks.len(), ob.limit_asks.len());
+            prop_assert_eq!(liquidity.result.limit_orders.bids.len(), ob.limit_bids.len());
+            prop_assert!(ob.limit_asks.iter().all(|order| order.side == Side::Sell));
+            prop_assert!(ob.limit_bids.iter().all(|order| order.side == Side::Buy));
+
+            // inverting twice gives back the same book
+            let twice = ob.inverse().inverse();
+            let bands = |ob: &OrderBook| -> BTreeMap<Tick, (Tick, U256)> {
+                ob.range_orders
+                    .iter()
+                    .map(|band| (band.start_tick, (band.end_tick, band.liquidity)))
+                    .collect()
+            };
+            prop_assert_eq!(bands(&ob), bands(&twice));
+            prop_assert_eq!(ob.tick, twice.tick);
+            prop_assert_eq!(ob.limit_bid_amount(), twice.limit_bid_amount());
+            prop_assert_eq!(ob.limit_ask_amount(), twice.limit_ask_amount());
+        }
+    }
 
     #[test]
     fn test_new_orderbook() {
@@ -156,7 +342,7 @@ mod tests {
 
         assert_eq!(2, ob.range_orders.len());
 
-        let range_order_0 = ob.range_orders.get(0).unwrap();
+        let range_order_0 = ob.range_orders.first().unwrap();
         assert_eq!(-1, range_order_0.start_tick);
         assert_eq!(10, range_order_0.end_tick);
 
@@ -164,4 +350,77 @@ mod tests {
         assert_eq!(10, range_order_1.start_tick);
         assert_eq!(100, range_order_1.end_tick);
     }
+
+    #[test]
+    fn test_inverse_tick_rounding() {
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![],
+                    bids: vec![],
+                },
+                range_orders: vec![],
+            },
+        };
+        // a raw price of 1 is exactly at tick 0, a little above it is within tick 0
+        let at_tick = OrderBook::new(&asset_pair, liquidity.clone(), U256::one() << 96, 0);
+        assert_eq!(0, at_tick.inverse().tick);
+        let above_tick = OrderBook::new(
+            &asset_pair,
+            liquidity,
+            (U256::one() << 96) + (U256::one() << 80),
+            0,
+        );
+        assert_eq!(-1, above_tick.inverse().tick);
+    }
+
+    #[test]
+    fn test_inverse() {
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![LimitOrder {
+                        tick: 1234,
+                        amount: "0x01".to_string(),
+                    }],
+                    bids: vec![LimitOrder {
+                        tick: 1233,
+                        amount: "0x01".to_string(),
+                    }],
+                },
+                range_orders: vec![
+                    RangeOrder {
+                        tick: -1,
+                        liquidity: "0x01".to_string(),
+                    },
+                    RangeOrder {
+                        tick: 10,
+                        liquidity: "0x01".to_string(),
+                    },
+                    RangeOrder {
+                        tick: 100,
+                        liquidity: "0x0".to_string(),
+                    },
+                ],
+            },
+        };
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let ob = OrderBook::new(&asset_pair, liquidity, U256::zero(), 1234);
+
+        // asks become bids and ticks are negated, bands keep their liquidity in reverse order
+        let inverse = ob.inverse();
+        assert_eq!("USDC-BTC", inverse.asset_pair.to_string());
+        assert_eq!(-1234, inverse.tick);
+        assert_eq!(Side::Buy, inverse.limit_bids[0].side);
+        assert_eq!(-1234, inverse.limit_bids[0].tick);
+        assert_eq!(-1233, inverse.limit_asks[0].tick);
+        assert_eq!(-100, inverse.range_orders[0].start_tick);
+        assert_eq!(-10, inverse.range_orders[0].end_tick);
+        assert_eq!(1, inverse.range_orders[1].end_tick);
+    }
 }
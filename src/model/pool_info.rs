@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// Fees of a pool from `cf_pool_info`, the only configuration the node reports. Chainflip pools
+/// accept orders at any tick in range, so there is no tick spacing to fetch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolInfo {
+    /// Fee charged on the input filled by limit orders
+    pub limit_order_fee_hundredth_pips: u32,
+    /// Fee charged on the input filled by range orders
+    pub range_order_fee_hundredth_pips: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PoolInfoResponse {
+    pub id: String,
+    pub jsonrpc: String,
+    pub result: PoolInfo,
+}
@@ -5,6 +5,11 @@ pub struct Result {
     pub price: String,
     pub sqrt_price: String,
     pub tick: i32,
+    /// Block the price was observed at, sent by nodes which support it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
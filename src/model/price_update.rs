@@ -1,4 +1,6 @@
-use super::{asset_pair::AssetPair, common::Tick};
+use std::time::Duration;
+
+use super::{asset_pair::AssetPair, common::Tick, timestamp::Timestamp};
 
 #[derive(Clone, Debug)]
 pub struct PriceUpdate {
@@ -6,4 +8,18 @@ pub struct PriceUpdate {
     pub price: String,
     pub sqrt_price: String,
     pub tick: Tick,
+    /// Block the price was observed at, if the node sent it
+    pub block_number: Option<u64>,
+    pub block_hash: Option<String>,
+    /// No update has been received for this pool within the staleness threshold, this is the last known price
+    pub stale: bool,
+    /// Local time the websocket frame carrying this update was received
+    pub received_at: Timestamp,
+}
+
+impl PriceUpdate {
+    /// Time since this update was received
+    pub fn age(&self) -> Duration {
+        self.received_at.elapsed()
+    }
 }
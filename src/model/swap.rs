@@ -0,0 +1,464 @@
+use serde::Serialize;
+
+use crate::{tick_math::raw_sqrt_price_at_tick, util::u256_to_f64};
+
+use super::{asset_pair::AssetPair, depth::Scale, order_book::OrderBook, pool_info::PoolInfo};
+
+/// Fees are charged in hundredths of a pip, a millionth of the amount
+const HUNDREDTH_PIPS: f64 = 1_000_000.0;
+
+/// Fees of a pool in hundredths of a pip, each charged on the input filled by its kind of order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapFees {
+    pub limit_order_hundredth_pips: u32,
+    pub range_order_hundredth_pips: u32,
+}
+
+impl SwapFees {
+    /// The same fee whichever orders fill
+    pub fn flat(fee_hundredth_pips: u32) -> Self {
+        SwapFees {
+            limit_order_hundredth_pips: fee_hundredth_pips,
+            range_order_hundredth_pips: fee_hundredth_pips,
+        }
+    }
+}
+
+impl From<&PoolInfo> for SwapFees {
+    fn from(pool_info: &PoolInfo) -> Self {
+        SwapFees {
+            limit_order_hundredth_pips: pool_info.limit_order_fee_hundredth_pips,
+            range_order_hundredth_pips: pool_info.range_order_fee_hundredth_pips,
+        }
+    }
+}
+
+/// Raw input of a swap left to trade, and the fees charged on what has been
+struct Input {
+    remaining: f64,
+    fee: f64,
+}
+
+impl Input {
+    /// Trade up to `capacity` of input, rounded up to the smallest unit, charging a fee of
+    /// `fee_hundredth_pips` on top of it rounded up. Returns the input traded, short of `capacity`
+    /// once there is too little left.
+    fn fill(&mut self, capacity: f64, fee_hundredth_pips: u32) -> f64 {
+        let fee_hundredth_pips = fee_hundredth_pips as f64;
+        let capacity = capacity.ceil();
+        let cost = (capacity * HUNDREDTH_PIPS / (HUNDREDTH_PIPS - fee_hundredth_pips)).ceil();
+        if self.remaining >= cost {
+            self.remaining -= cost;
+            self.fee += cost - capacity;
+
+            return capacity;
+        }
+
+        let fee = (self.remaining * fee_hundredth_pips / HUNDREDTH_PIPS).ceil();
+        let traded = self.remaining - fee;
+        self.fee += fee;
+        self.remaining = 0.0;
+
+        traded
+    }
+}
+
+/// Result of swapping through a single pool in either direction, prices in quote asset per base
+/// asset, rates in `to_asset` per `from_asset` and amounts in whole units
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SwapLeg {
+    pub asset_pair: AssetPair,
+    pub from_asset: String,
+    pub to_asset: String,
+    pub amount_in: f64,
+    /// Part of `amount_in` taken by the pool's fees, rounded up to the smallest unit
+    pub fee: f64,
+    /// Rounded down to the smallest unit
+    pub amount_out: f64,
+    /// Part of `amount_in` left over once the pool's liquidity ran out
+    pub unfilled: f64,
+    pub mid_price: f64,
+    /// Average price of the filled amount including the fee, `None` if nothing was filled
+    pub avg_price: Option<f64>,
+    /// Pool price after the swap
+    pub end_price: f64,
+    /// How much less `avg_rate` is than `mid_rate`, in basis points, the same whichever way round
+    /// the pool is quoted
+    pub price_impact_bps: Option<f64>,
+    /// `mid_price` in `to_asset` per `from_asset`
+    pub mid_rate: f64,
+    /// `avg_price` in `to_asset` per `from_asset`
+    pub avg_rate: Option<f64>,
+}
+
+/// Simulate swapping `amount_in` of `from_asset` through the liquidity of `ob`, selling the base
+/// asset or buying it with the quote asset. Limit orders at a price fill before the range liquidity
+/// beyond it, and each fill is charged the fee of its kind of order on the input it uses. Rounding
+/// favours the pool: fees are rounded up and the output down to the smallest unit. `None` if
+/// `from_asset` is not in the pair or the decimals of either asset are unknown.
+pub fn simulate_swap(
+    ob: &OrderBook,
+    from_asset: &str,
+    amount_in: f64,
+    fees: SwapFees,
+) -> Option<SwapLeg> {
+    let scale = Scale::of(&ob.asset_pair)?;
+    let sell_base = if from_asset == ob.asset_pair.from {
+        true
+    } else if from_asset == ob.asset_pair.to {
+        false
+    } else {
+        return None;
+    };
+
+    let mid_price = scale.mid_price(ob);
+    let (in_unit, out_unit) = if sell_base {
+        (scale.base_unit, scale.quote_unit)
+    } else {
+        (scale.quote_unit, scale.base_unit)
+    };
+
+    let amount_in_raw = (amount_in * in_unit).round();
+    let mut input = Input {
+        remaining: amount_in_raw,
+        fee: 0.0,
+    };
+    let (amount_out, end_sqrt) = if sell_base {
+        walk_down(ob, scale.sqrt_at_price(mid_price), &mut input, fees)
+    } else {
+        walk_up(ob, scale.sqrt_at_price(mid_price), &mut input, fees)
+    };
+
+    // input left over is not charged a fee
+    let unfilled = input.remaining / in_unit;
+    let fee = input.fee / in_unit;
+    let filled = amount_in_raw / in_unit - unfilled;
+    let amount_out = amount_out.floor() / out_unit;
+    let avg_price = (amount_out > 0.0).then(|| {
+        if sell_base {
+            amount_out / filled
+        } else {
+            filled / amount_out
+        }
+    });
+    let to_rate = |price: f64| if sell_base { price } else { 1.0 / price };
+    let mid_rate = to_rate(mid_price);
+    let avg_rate = avg_price.map(to_rate);
+
+    Some(SwapLeg {
+        asset_pair: ob.asset_pair.clone(),
+        from_asset: from_asset.to_string(),
+        to_asset: if sell_base {
+            ob.asset_pair.to.clone()
+        } else {
+            ob.asset_pair.from.clone()
+        },
+        amount_in,
+        fee,
+        amount_out,
+        unfilled,
+        mid_price,
+        avg_price,
+        end_price: end_sqrt.powi(2) / scale.raw(),
+        price_impact_bps: avg_rate.map(|rate| (mid_rate - rate) / mid_rate * 10_000.0),
+        mid_rate,
+        avg_rate,
+    })
+}
+
+/// Range liquidity bands of `ob` as raw sqrt price bounds and liquidity
+fn bands(ob: &OrderBook) -> Vec<(f64, f64, f64)> {
+    ob.range_orders
+        .iter()
+        .filter(|band| !band.liquidity.is_zero())
+        .map(|band| {
+            (
+                raw_sqrt_price_at_tick(band.start_tick),
+                raw_sqrt_price_at_tick(band.end_tick),
+                u256_to_f64(band.liquidity),
+            )
+        })
+        .collect()
+}
+
+/// Sell raw base `input` into bids from raw sqrt price `sqrt`, moving the price down. Returns the
+/// quote received and the final sqrt price.
+fn walk_down(ob: &OrderBook, mut sqrt: f64, input: &mut Input, fees: SwapFees) -> (f64, f64) {
+    let bands = bands(ob);
+    // highest bid last
+    let mut bids: Vec<(f64, f64)> = ob
+        .limit_bids
+        .iter()
+        .map(|order| {
+            (
+                raw_sqrt_price_at_tick(order.tick),
+                u256_to_f64(order.amount),
+            )
+        })
+        .collect();
+    bids.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut out = 0.0;
+
+    while input.remaining > 0.0 {
+        // bids at or above the price fill at their own price
+        if let Some((bid_sqrt, amount)) = bids.last().copied().filter(|(bid, _)| *bid >= sqrt) {
+            let price = bid_sqrt.powi(2);
+            let base = input.fill(amount / price, fees.limit_order_hundredth_pips);
+            out += (base * price).min(amount);
+            bids.pop();
+
+            continue;
+        }
+
+        let next = bands
+            .iter()
+            .flat_map(|(lo, hi, _)| [*lo, *hi])
+            .chain(bids.last().map(|(bid, _)| *bid))
+            .filter(|bound| *bound < sqrt)
+            .max_by(f64::total_cmp);
+        let Some(next) = next else {
+            break;
+        };
+
+        let liquidity: f64 = bands
+            .iter()
+            .filter(|(lo, hi, _)| *lo <= next && *hi >= sqrt)
+            .map(|(_, _, liquidity)| liquidity)
+            .sum();
+        if liquidity > 0.0 {
+            let capacity = liquidity * (1.0 / next - 1.0 / sqrt);
+            let base = input
+                .fill(capacity, fees.range_order_hundredth_pips)
+                .min(capacity);
+            if input.remaining <= 0.0 {
+                let end = 1.0 / (1.0 / sqrt + base / liquidity);
+                out += liquidity * (sqrt - end);
+
+                return (out, end);
+            }
+            out += liquidity * (sqrt - next);
+        }
+        sqrt = next;
+    }
+
+    (out, sqrt)
+}
+
+/// Buy raw base from asks with raw quote `input` from raw sqrt price `sqrt`, moving the price up.
+/// Returns the base received and the final sqrt price.
+fn walk_up(ob: &OrderBook, mut sqrt: f64, input: &mut Input, fees: SwapFees) -> (f64, f64) {
+    let bands = bands(ob);
+    // lowest ask last
+    let mut asks: Vec<(f64, f64)> = ob
+        .limit_asks
+        .iter()
+        .map(|order| {
+            (
+                raw_sqrt_price_at_tick(order.tick),
+                u256_to_f64(order.amount),
+            )
+        })
+        .collect();
+    asks.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut out = 0.0;
+
+    while input.remaining > 0.0 {
+        // asks at or below the price fill at their own price
+        if let Some((ask_sqrt, amount)) = asks.last().copied().filter(|(ask, _)| *ask <= sqrt) {
+            let price = ask_sqrt.powi(2);
+            let quote = input.fill(amount * price, fees.limit_order_hundredth_pips);
+            out += (quote / price).min(amount);
+            asks.pop();
+
+            continue;
+        }
+
+        let next = bands
+            .iter()
+            .flat_map(|(lo, hi, _)| [*lo, *hi])
+            .chain(asks.last().map(|(ask, _)| *ask))
+            .filter(|bound| *bound > sqrt)
+            .min_by(f64::total_cmp);
+        let Some(next) = next else {
+            break;
+        };
+
+        let liquidity: f64 = bands
+            .iter()
+            .filter(|(lo, hi, _)| *lo <= sqrt && *hi >= next)
+            .map(|(_, _, liquidity)| liquidity)
+            .sum();
+        if liquidity > 0.0 {
+            let capacity = liquidity * (next - sqrt);
+            let quote = input
+                .fill(capacity, fees.range_order_hundredth_pips)
+                .min(capacity);
+            if input.remaining <= 0.0 {
+                let end = sqrt + quote / liquidity;
+                out += liquidity * (1.0 / sqrt - 1.0 / end);
+
+                return (out, end);
+            }
+            out += liquidity * (1.0 / sqrt - 1.0 / next);
+        }
+        sqrt = next;
+    }
+
+    (out, sqrt)
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use crate::{
+        model::{
+            asset_pair::AssetPair,
+            liquidity::{LimitOrder, LimitOrders, Liquidity, RangeOrder, Result},
+            order_book::OrderBook,
+        },
+        util::tick_to_price,
+    };
+
+    use super::{simulate_swap, SwapFees};
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (actual / expected - 1.0).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn btc_usdc_book(range_orders: Vec<(i32, u64)>, asks: Vec<(i32, u64)>) -> OrderBook {
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: asks
+                        .into_iter()
+                        .map(|(tick, amount)| LimitOrder {
+                            tick,
+                            amount: format!("{:#x}", amount),
+                        })
+                        .collect(),
+                    bids: vec![],
+                },
+                range_orders: range_orders
+                    .into_iter()
+                    .map(|(tick, liquidity)| RangeOrder {
+                        tick,
+                        liquidity: format!("{:#x}", liquidity),
+                    })
+                    .collect(),
+            },
+        };
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+
+        OrderBook::new(&asset_pair, liquidity, U256::zero(), 57040)
+    }
+
+    #[test]
+    fn test_sell_into_range_liquidity() {
+        let liquidity = 1_000_000_000_000_u64;
+        let ob = btc_usdc_book(vec![(50000, liquidity), (64000, 0)], vec![]);
+
+        // 0.3% fee
+        let leg = simulate_swap(&ob, "BTC", 0.1, SwapFees::flat(3000)).unwrap();
+        assert_eq!("USDC", leg.to_asset);
+        assert_eq!(0.0, leg.unfilled);
+        assert_close(0.1 * 0.003, leg.fee);
+
+        // selling x base moves the sqrt price from s to 1 / (1/s + x/L), receiving L * (s - s')
+        let (liquidity, sqrt) = (liquidity as f64, 1.0001_f64.powf(57040.0 / 2.0));
+        let end = 1.0 / (1.0 / sqrt + 0.0997e8 / liquidity);
+        assert_close(liquidity * (sqrt - end) / 1e6, leg.amount_out);
+        assert_close(end * end * 100.0, leg.end_price);
+        let impact = leg.price_impact_bps.unwrap();
+        assert!(impact > 30.0 && impact < 35.0, "impact {}", impact);
+
+        // the band runs out below 50000, leaving the rest unfilled
+        let leg = simulate_swap(&ob, "BTC", 1_000.0, SwapFees::flat(0)).unwrap();
+        assert!(leg.unfilled > 0.0);
+        assert_close(tick_to_price(50000, &ob.asset_pair), leg.end_price);
+    }
+
+    #[test]
+    fn test_buy_through_limit_orders() {
+        // 0.5 BTC offered 10 ticks up and 1 BTC 20 ticks up, no range liquidity
+        let ob = btc_usdc_book(vec![], vec![(57050, 50_000_000), (57060, 100_000_000)]);
+        let price = |tick: i32| tick_to_price(tick, &ob.asset_pair);
+
+        // a micro USDC over, the cost of each fill is rounded up
+        let quote_in = 0.5 * price(57050) + 0.25 * price(57060) + 0.000_001;
+        let leg = simulate_swap(&ob, "USDC", quote_in, SwapFees::flat(0)).unwrap();
+        assert_eq!("BTC", leg.to_asset);
+        assert_close(0.75, leg.amount_out);
+        assert_close(quote_in / 0.75, leg.avg_price.unwrap());
+        assert!(leg.price_impact_bps.unwrap() > 10.0);
+
+        // more than the asks can fill
+        let leg = simulate_swap(&ob, "USDC", 1_000_000.0, SwapFees::flat(0)).unwrap();
+        assert_close(1.5, leg.amount_out);
+        assert_close(
+            1_000_000.0 - 0.5 * price(57050) - price(57060),
+            leg.unfilled,
+        );
+
+        assert_eq!(None, simulate_swap(&ob, "ETH", 1.0, SwapFees::flat(0)));
+    }
+
+    #[test]
+    fn test_inverse_book_quotes_the_same() {
+        let ob = btc_usdc_book(
+            vec![(50000, 1_000_000_000_000), (64000, 0)],
+            vec![(57050, 50_000_000)],
+        );
+        let inverse = ob.inverse();
+
+        // how much BTC 100k USDC buys, from the book and from its inverse selling USDC as the base
+        let leg = simulate_swap(&ob, "USDC", 100_000.0, SwapFees::flat(500)).unwrap();
+        let inverse_leg = simulate_swap(&inverse, "USDC", 100_000.0, SwapFees::flat(500)).unwrap();
+        assert_eq!("USDC-BTC", inverse_leg.asset_pair.to_string());
+        assert_eq!("BTC", inverse_leg.to_asset);
+        // within a satoshi
+        assert!((leg.amount_out - inverse_leg.amount_out).abs() <= 1e-8);
+        assert_close(leg.fee, inverse_leg.fee);
+        assert_close(leg.mid_rate, inverse_leg.mid_rate);
+        assert_close(leg.mid_price, 1.0 / inverse_leg.mid_price);
+        assert_close(leg.end_price, 1.0 / inverse_leg.end_price);
+        let (impact, inverse_impact) = (
+            leg.price_impact_bps.unwrap(),
+            inverse_leg.price_impact_bps.unwrap(),
+        );
+        assert!((impact - inverse_impact).abs() < 1e-6, "impact {}", impact);
+    }
+
+    #[test]
+    fn test_fee_per_kind_of_order() {
+        // 0.5 BTC offered at the pool's price, then range liquidity beyond it
+        let ob = btc_usdc_book(
+            vec![(50000, 1_000_000_000_000), (64000, 0)],
+            vec![(57040, 50_000_000)],
+        );
+        let fees = SwapFees {
+            limit_order_hundredth_pips: 0,
+            range_order_hundredth_pips: 3000,
+        };
+        let ask_cost = 0.5 * tick_to_price(57040, &ob.asset_pair);
+
+        // filled by the ask alone, free of the range fee
+        let leg = simulate_swap(&ob, "USDC", ask_cost.floor(), fees).unwrap();
+        assert_eq!(0.0, leg.fee);
+
+        // only the input beyond the ask pays the range fee
+        let leg = simulate_swap(&ob, "USDC", ask_cost + 10_000.0, fees).unwrap();
+        assert!((leg.fee - 30.0).abs() < 0.01, "fee {}", leg.fee);
+        assert!(leg.amount_out > 0.5);
+        let flat = simulate_swap(&ob, "USDC", ask_cost + 10_000.0, SwapFees::flat(3000)).unwrap();
+        assert!(flat.fee > leg.fee + 40.0, "fee {}", flat.fee);
+        assert!(flat.amount_out < leg.amount_out);
+    }
+}
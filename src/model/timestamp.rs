@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer};
+
+/// A local point in time, as both a monotonic instant for measuring latency and a wall clock time for
+/// reporting and correlating across hosts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timestamp {
+    pub monotonic: Instant,
+    pub wall: SystemTime,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// Time elapsed since this timestamp, measured on the monotonic clock
+    pub fn elapsed(&self) -> Duration {
+        self.monotonic.elapsed()
+    }
+
+    /// Microseconds since the unix epoch of the wall clock time
+    pub fn unix_micros(&self) -> u64 {
+        self.wall
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64
+    }
+}
+
+/// Serialised as microseconds since the unix epoch, the monotonic clock has no meaning outside the process
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.unix_micros())
+    }
+}
+
+fn serialize_duration_micros<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_micros() as u64)
+}
+
+/// Points in time along the path from a price update arriving to an `OrderBook` being published
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BookLatency {
+    /// Websocket frame carrying the price update the book was built from arrived
+    pub price_received_at: Timestamp,
+    /// `cf_pool_liquidity` was requested
+    pub liquidity_requested_at: Timestamp,
+    /// `cf_pool_liquidity` response was received
+    pub liquidity_received_at: Timestamp,
+    /// Book construction completed
+    pub built_at: Timestamp,
+    /// Time between the price update arriving and the book being published
+    #[serde(
+        rename = "age_at_publish_us",
+        serialize_with = "serialize_duration_micros"
+    )]
+    pub age_at_publish: Duration,
+}
+
+impl BookLatency {
+    /// Round trip time of the liquidity request
+    pub fn liquidity_request_duration(&self) -> Duration {
+        self.liquidity_received_at
+            .monotonic
+            .saturating_duration_since(self.liquidity_requested_at.monotonic)
+    }
+}
@@ -1,18 +1,85 @@
-use std::time::Duration;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
 
 use tokio::time::{interval, sleep, Interval};
+use tokio_util::sync::CancellationToken;
+use tracing::{field, Instrument};
 
+use crate::metrics;
 use crate::model::asset_pair::AssetPair;
+use crate::model::book_validation::{build_validated_book, ValidationPolicy};
 use crate::model::order_book::OrderBook;
+use crate::model::pool_info::PoolInfo;
+use crate::model::price_update::PriceUpdate;
+use crate::model::timestamp::{BookLatency, Timestamp};
 use crate::pool_info_provider::pool_info_provider_handle::PoolInfoProviderHandle;
 use crate::util::hex_string_to_u256;
 
+/// How often the pool's configuration is fetched again, it rarely changes
+const POOL_INFO_REFRESH: Duration = Duration::from_secs(600);
+
+/// Events which trigger an `OrderBookBuilder` to build a book
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerMode {
+    /// Every poll duration
+    Interval,
+    /// On every price change
+    PriceChange,
+    /// Every poll duration and on every price change
+    IntervalAndPriceChange,
+}
+
+impl TriggerMode {
+    fn on_interval(&self) -> bool {
+        matches!(
+            self,
+            TriggerMode::Interval | TriggerMode::IntervalAndPriceChange
+        )
+    }
+
+    fn on_price_change(&self) -> bool {
+        matches!(
+            self,
+            TriggerMode::PriceChange | TriggerMode::IntervalAndPriceChange
+        )
+    }
+}
+
+impl FromStr for TriggerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interval" => Ok(TriggerMode::Interval),
+            "price_change" => Ok(TriggerMode::PriceChange),
+            "interval_and_price_change" => Ok(TriggerMode::IntervalAndPriceChange),
+            _ => Err(format!(
+                "invalid trigger mode {:?}, expected interval, price_change or interval_and_price_change",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TriggerMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerMode::Interval => write!(f, "interval"),
+            TriggerMode::PriceChange => write!(f, "price_change"),
+            TriggerMode::IntervalAndPriceChange => write!(f, "interval_and_price_change"),
+        }
+    }
+}
+
 /// An enduring thread which queries liquidity information from a `PoolInfoProviderHandle` periodically
 /// to build an `OrderBook` before sending down stream over a channel.
 ///
-/// The trigger for building an order book is a combination of time based (`poll_duration`) and we also
-/// watch for price change updates and use this as an additional trigger to build books.
+/// The trigger for building an order book is time based (`poll_duration`), price change updates or a
+/// combination of both depending on the `TriggerMode`.
 pub struct OrderBookBuilder {
     /// Asset pair of interest
     asset_pair: AssetPair,
@@ -20,27 +87,42 @@ pub struct OrderBookBuilder {
     pool_info_provider_handle: PoolInfoProviderHandle,
     /// Poll duration between fetching & building books
     poll_duration: Duration,
+    /// Events which trigger building a book
+    trigger: TriggerMode,
+    /// Minimum time between builds, triggers arriving sooner are coalesced into a single build
+    min_rebuild_interval: Duration,
+    /// What to do with books whose node data fails validation
+    validation: ValidationPolicy,
     /// Downstream channel for consumers
     book_sender: mpsc::UnboundedSender<OrderBook>,
 }
 
-/// Create and start an order book builder and return the channel it publishes updates on
+/// Create and start an order book builder and return the channel it publishes updates on, the
+/// channel closes once `shutdown` is cancelled
 pub fn create_and_start_order_book_builder(
     asset_pair: &AssetPair,
     pool_info_provider_handle: PoolInfoProviderHandle,
     poll_duration: Duration,
+    trigger: TriggerMode,
+    min_rebuild_interval: Duration,
+    shutdown: CancellationToken,
 ) -> mpsc::UnboundedReceiver<OrderBook> {
     let (tx, rx) = mpsc::unbounded_channel();
     let orderbook_builder = OrderBookBuilder::new(
         asset_pair.clone(),
         pool_info_provider_handle,
         poll_duration,
+        trigger,
+        min_rebuild_interval,
         tx,
     );
 
-    tokio::spawn(async move {
-        orderbook_builder.run().await;
-    });
+    tokio::spawn(
+        async move {
+            orderbook_builder.run(shutdown).await;
+        }
+        .instrument(tracing::info_span!("order_book_builder", pair = %asset_pair)),
+    );
 
     rx
 }
@@ -50,18 +132,33 @@ impl OrderBookBuilder {
         asset_pair: AssetPair,
         pool_info_provider_handle: PoolInfoProviderHandle,
         poll_duration: Duration,
+        trigger: TriggerMode,
+        min_rebuild_interval: Duration,
         book_sender: mpsc::UnboundedSender<OrderBook>,
     ) -> Self {
         OrderBookBuilder {
             asset_pair,
             pool_info_provider_handle,
             poll_duration,
+            trigger,
+            min_rebuild_interval,
+            validation: ValidationPolicy::default(),
             book_sender,
         }
     }
 
-    pub async fn run(&self) {
+    /// Apply `policy` to books whose node data fails validation
+    pub fn with_validation(mut self, policy: ValidationPolicy) -> Self {
+        self.validation = policy;
+        self
+    }
+
+    /// Build books until `shutdown` is cancelled, a liquidity request in flight is completed and
+    /// its book published first
+    pub async fn run(&self, shutdown: CancellationToken) {
         let mut update_interval: Interval = interval(self.poll_duration);
+        let health_monitor = self.pool_info_provider_handle.health_monitor();
+        health_monitor.register_builder(&self.asset_pair);
 
         // poll until a price update watch channel is available for this AssetPair
         let mut price_update_watch = loop {
@@ -74,48 +171,208 @@ impl OrderBookBuilder {
                     break watch;
                 }
                 None => {
-                    sleep(Duration::from_secs(5)).await;
+                    tokio::select! {
+                        _ = shutdown.cancelled() => return,
+                        _ = sleep(Duration::from_secs(5)) => {},
+                    }
                 }
             }
         };
 
-        let mut latest_pool_price = price_update_watch.borrow().as_ref().unwrap().clone();
+        // wait for the first price update
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            first_price = price_update_watch.wait_for(|p| p.is_some()) => {
+                if let Err(e) = first_price {
+                    tracing::error!(pair = %self.asset_pair, error = ?e, "error waiting for first price update");
+
+                    return;
+                }
+            }
+        }
 
+        // without the interval to trigger it, build the first book off the current price
+        if !self.trigger.on_interval() {
+            price_update_watch.mark_changed();
+        }
+
+        let pool_info_refresh = shutdown.child_token();
+        let _stop_pool_info_refresh = pool_info_refresh.clone().drop_guard();
+        let pool_info_rx = self.spawn_pool_info_refresh(pool_info_refresh);
+
+        let mut last_built_at: Option<Instant> = None;
         loop {
             // block until the orderbook update interval has elapsed or a price update occurs
             tokio::select! {
-                _ = update_interval.tick() => {},
-                _ = price_update_watch.changed() => {
-                    update_interval.reset_immediately();
+                _ = shutdown.cancelled() => {
+                    tracing::info!(pair = %self.asset_pair, "stopping order book builder");
 
-                    latest_pool_price = price_update_watch.borrow().as_ref().unwrap().clone();
+                    break;
+                },
+                _ = update_interval.tick(), if self.trigger.on_interval() => {},
+                changed = price_update_watch.changed(), if self.trigger.on_price_change() => {
+                    if changed.is_err() {
+                        tracing::warn!(pair = %self.asset_pair, "price updates have stopped");
+
+                        break;
+                    }
+                    update_interval.reset_immediately();
                 }
             };
 
-            let liquidity = self
-                .pool_info_provider_handle
-                .get_pool_liquidity(&self.asset_pair)
-                .await
-                .unwrap();
-
-            // build order book
-            let sqrt_price_x96 = hex_string_to_u256(&latest_pool_price.sqrt_price);
-            let ob = OrderBook::new(
-                &self.asset_pair,
-                liquidity,
-                sqrt_price_x96,
-                latest_pool_price.tick,
+            // hold back rebuilds until the minimum interval has passed, the latest price is used
+            if let Some(last_built_at) = last_built_at {
+                let wait = self
+                    .min_rebuild_interval
+                    .saturating_sub(last_built_at.elapsed());
+                if !wait.is_zero() {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = sleep(wait) => {},
+                    }
+                }
+            }
+            last_built_at = Some(Instant::now());
+
+            let latest_pool_price = price_update_watch
+                .borrow_and_update()
+                .as_ref()
+                .unwrap()
+                .clone();
+
+            let pool_info = pool_info_rx.borrow().clone();
+
+            let build_span = tracing::info_span!(
+                "book_build",
+                pair = %self.asset_pair,
+                block = latest_pool_price.block_number,
+                tick = latest_pool_price.tick,
+                latency_ms = field::Empty,
             );
+            let Some(ob) = self
+                .build(latest_pool_price, pool_info)
+                .instrument(build_span)
+                .await
+            else {
+                // retried on the next trigger
+                tracing::error!(pair = %self.asset_pair, "no liquidity, skipping build");
+                metrics::inc_builds_without_liquidity(&self.asset_pair);
+
+                continue;
+            };
+            let Some(ob) = ob else {
+                continue;
+            };
 
             // send order book to consumers
             match self.book_sender.send(ob) {
-                Ok(_) => {}
+                Ok(_) => health_monitor.record_book_built(&self.asset_pair),
                 Err(e) => {
-                    log::error!("error sending orderbook update: {:?}", e);
+                    tracing::error!(pair = %self.asset_pair, error = ?e, "error sending orderbook update");
 
                     break;
                 }
             }
         }
     }
+
+    /// Fetch the pool's configuration now and every `POOL_INFO_REFRESH` until `shutdown` is
+    /// cancelled, off the build loop so a slow `cf_pool_info` never holds up a book. The last known
+    /// configuration is kept if a fetch fails.
+    fn spawn_pool_info_refresh(
+        &self,
+        shutdown: CancellationToken,
+    ) -> watch::Receiver<Option<PoolInfo>> {
+        let (pool_info_tx, pool_info_rx) = watch::channel(None);
+        let asset_pair = self.asset_pair.clone();
+        let pool_info_provider_handle = self.pool_info_provider_handle.clone();
+
+        tokio::spawn(
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        pool_info = pool_info_provider_handle.get_pool_info(&asset_pair) => {
+                            if let Some(info) = pool_info {
+                                pool_info_tx.send_replace(Some(info));
+                            }
+                        }
+                    }
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = sleep(POOL_INFO_REFRESH) => {},
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+
+        pool_info_rx
+    }
+
+    /// Build a book off `latest_pool_price` and the pool's liquidity, attaching its configuration if
+    /// known. `None` if liquidity is unavailable and `Some(None)` if the book was dropped by
+    /// validation.
+    async fn build(
+        &self,
+        latest_pool_price: PriceUpdate,
+        pool_info: Option<PoolInfo>,
+    ) -> Option<Option<OrderBook>> {
+        let liquidity_requested_at = Timestamp::now();
+        let liquidity = self
+            .pool_info_provider_handle
+            .get_pool_liquidity(&self.asset_pair)
+            .await?;
+        let liquidity_received_at = Timestamp::now();
+
+        // build order book
+        let sqrt_price_x96 = hex_string_to_u256(&latest_pool_price.sqrt_price);
+        let (ob, report) = build_validated_book(
+            &self.asset_pair,
+            liquidity,
+            sqrt_price_x96,
+            latest_pool_price.tick,
+            self.validation,
+        );
+        if report.has_problems() {
+            metrics::observe_validation_report(&report);
+            tracing::warn!(
+                issues = ?report.counts(),
+                policy = %report.policy,
+                published = report.published,
+                "invalid pool data"
+            );
+        }
+        let Some(mut ob) = ob else {
+            return Some(None);
+        };
+        ob.issues = report.issues;
+        ob.pool_info = pool_info;
+        ob.stale = latest_pool_price.stale;
+        let built_at = Timestamp::now();
+        metrics::observe_order_book(&ob);
+
+        let age_at_publish = latest_pool_price.age();
+        ob.latency = Some(BookLatency {
+            price_received_at: latest_pool_price.received_at,
+            liquidity_requested_at,
+            liquidity_received_at,
+            built_at,
+            age_at_publish,
+        });
+        metrics::observe_book_age_at_publish(&self.asset_pair, age_at_publish);
+        let latency_ms = built_at
+            .monotonic
+            .duration_since(liquidity_requested_at.monotonic)
+            .as_secs_f64()
+            * 1000.0;
+        tracing::Span::current().record("latency_ms", latency_ms);
+        tracing::debug!(
+            latency_ms,
+            age_ms = age_at_publish.as_secs_f64() * 1000.0,
+            "publishing order book"
+        );
+
+        Some(Some(ob))
+    }
 }
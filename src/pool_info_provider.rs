@@ -1,3 +1,6 @@
+pub mod liquidity_cache;
+pub mod node_connection;
 #[allow(clippy::module_inception)]
 pub mod pool_info_provider;
 pub mod pool_info_provider_handle;
+pub mod rate_limiter;
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::model::{asset_pair::AssetPair, liquidity::Liquidity};
+
+/// Pool and block hash of a cached liquidity response
+type CacheKey = (AssetPair, String);
+
+/// Least recently used cache of liquidity responses by pool and block hash. A block's liquidity never
+/// changes, entries of older blocks are dropped as new blocks arrive to leave room for current ones.
+pub struct LiquidityCache {
+    /// Maximum number of entries, 0 disables the cache
+    capacity: usize,
+    /// Cached liquidity and the tick of its last use
+    entries: HashMap<CacheKey, (Liquidity, u64)>,
+    /// Incremented on every use, orders entries by recency
+    clock: u64,
+    /// Hash of the latest block per pool
+    latest_blocks: HashMap<AssetPair, String>,
+}
+
+impl LiquidityCache {
+    pub fn new(capacity: usize) -> Self {
+        LiquidityCache {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+            latest_blocks: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Liquidity of `asset_pair` at `block_hash`, if cached
+    pub fn get(&mut self, asset_pair: &AssetPair, block_hash: &str) -> Option<Liquidity> {
+        self.clock += 1;
+        let (liquidity, last_used) = self
+            .entries
+            .get_mut(&(asset_pair.clone(), block_hash.to_string()))?;
+        *last_used = self.clock;
+
+        Some(liquidity.clone())
+    }
+
+    /// Cache `liquidity` of `asset_pair` at `block_hash`, evicting the least recently used entry if
+    /// full. Late responses for a block older than the pool's latest are not cached. Returns whether
+    /// an entry was evicted.
+    pub fn insert(
+        &mut self,
+        asset_pair: &AssetPair,
+        block_hash: &str,
+        liquidity: Liquidity,
+    ) -> bool {
+        let superseded = self
+            .latest_blocks
+            .get(asset_pair)
+            .is_some_and(|latest| latest != block_hash);
+        if self.capacity == 0 || superseded {
+            return false;
+        }
+
+        self.clock += 1;
+        let key = (asset_pair.clone(), block_hash.to_string());
+        let evict = !self.entries.contains_key(&key) && self.entries.len() >= self.capacity;
+        if evict {
+            if let Some(lru) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&lru);
+            }
+        }
+        self.entries.insert(key, (liquidity, self.clock));
+
+        evict
+    }
+
+    /// Drop entries of `asset_pair` for blocks other than `block_hash`, its latest block
+    pub fn on_new_block(&mut self, asset_pair: &AssetPair, block_hash: &str) {
+        self.entries
+            .retain(|(pair, hash), _| pair != asset_pair || hash == block_hash);
+        self.latest_blocks
+            .insert(asset_pair.clone(), block_hash.to_string());
+    }
+
+    /// Drop every entry of `asset_pair`
+    pub fn remove_pool(&mut self, asset_pair: &AssetPair) {
+        self.entries.retain(|(pair, _), _| pair != asset_pair);
+        self.latest_blocks.remove(asset_pair);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{
+        asset_pair::AssetPair,
+        liquidity::{LimitOrders, Liquidity, Result},
+    };
+
+    use super::LiquidityCache;
+
+    fn liquidity(id: &str) -> Liquidity {
+        Liquidity {
+            id: id.to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![],
+                    bids: vec![],
+                },
+                range_orders: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let btc = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let mut cache = LiquidityCache::new(2);
+
+        assert!(!cache.insert(&btc, "0x1", liquidity("1")));
+        assert!(!cache.insert(&btc, "0x2", liquidity("2")));
+        // using 0x1 makes 0x2 the least recently used
+        assert_eq!("1", cache.get(&btc, "0x1").unwrap().id);
+        assert!(cache.insert(&btc, "0x3", liquidity("3")));
+
+        assert!(cache.get(&btc, "0x2").is_none());
+        assert!(cache.get(&btc, "0x1").is_some());
+        assert!(cache.get(&btc, "0x3").is_some());
+    }
+
+    #[test]
+    fn test_new_block_drops_older_blocks_of_the_pool() {
+        let btc = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let eth = AssetPair::new("ETH".to_string(), "USDC".to_string());
+        let mut cache = LiquidityCache::new(8);
+        cache.insert(&btc, "0x1", liquidity("1"));
+        cache.insert(&eth, "0x1", liquidity("1"));
+
+        cache.on_new_block(&btc, "0x2");
+        assert!(cache.get(&btc, "0x1").is_none());
+        assert!(cache.get(&eth, "0x1").is_some());
+
+        // a late response for the older block isn't cached
+        cache.insert(&btc, "0x1", liquidity("1"));
+        assert!(cache.get(&btc, "0x1").is_none());
+        cache.insert(&btc, "0x2", liquidity("2"));
+        assert!(cache.get(&btc, "0x2").is_some());
+
+        assert!(LiquidityCache::new(0).is_empty());
+        let mut disabled = LiquidityCache::new(0);
+        disabled.insert(&btc, "0x2", liquidity("2"));
+        assert_eq!(0, disabled.len());
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    model::asset_pair::AssetPair,
+    transport::{
+        node_transport::{NodeTransport, TransportError},
+        websocket_transport::{NodeEndpoint, WebsocketTransport},
+    },
+};
+
+use super::rate_limiter::RateLimiter;
+
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a `PoolInfoProvider` connected to several nodes uses them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedundancyMode {
+    /// Price updates come from the active node only, the others are hot standbys subscribed to the
+    /// same pools. Fails over on disconnect or when the active node goes stale.
+    #[default]
+    Failover,
+    /// Price updates come from every node, the first arrival of each update is used and duplicates
+    /// (same block number and tick) are dropped
+    ActiveActive,
+}
+
+impl FromStr for RedundancyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "failover" => Ok(RedundancyMode::Failover),
+            "active_active" => Ok(RedundancyMode::ActiveActive),
+            _ => Err(format!(
+                "invalid redundancy mode {:?}, expected failover or active_active",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RedundancyMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedundancyMode::Failover => write!(f, "failover"),
+            RedundancyMode::ActiveActive => write!(f, "active_active"),
+        }
+    }
+}
+
+/// A pool subscription made on a node
+pub(crate) struct Subscription {
+    pub(crate) asset_pair: AssetPair,
+    /// Span of the subscription's lifetime, parent of events about its price updates
+    pub(crate) span: tracing::Span,
+}
+
+type ConnectFn =
+    dyn Fn() -> BoxFuture<'static, Result<Box<dyn NodeTransport>, TransportError>> + Send + Sync;
+
+/// Opens a transport to a node, used to (re)connect
+#[derive(Clone)]
+pub struct NodeConnector {
+    /// Label used in logs and metrics
+    name: String,
+    connect: Arc<ConnectFn>,
+}
+
+impl NodeConnector {
+    pub fn new(
+        name: &str,
+        connect: impl Fn() -> BoxFuture<'static, Result<Box<dyn NodeTransport>, TransportError>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        NodeConnector {
+            name: name.to_string(),
+            connect: Arc::new(connect),
+        }
+    }
+
+    /// Connect a `WebsocketTransport` to `endpoint`
+    pub fn websocket(endpoint: NodeEndpoint) -> Self {
+        let name = endpoint.label();
+
+        NodeConnector::new(&name, move || {
+            let endpoint = endpoint.clone();
+            async move {
+                let transport = WebsocketTransport::connect(&endpoint).await?;
+
+                Ok(Box::new(transport) as Box<dyn NodeTransport>)
+            }
+            .boxed()
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn connect(&self) -> BoxFuture<'static, Result<Box<dyn NodeTransport>, TransportError>> {
+        (self.connect)()
+    }
+}
+
+/// A node a `PoolInfoProvider` is, or is trying to be, connected to along with its subscriptions
+pub(crate) struct NodeConnection {
+    /// Label used in logs and metrics
+    pub(crate) name: String,
+    /// Open transport, `None` while disconnected
+    transport: Option<Box<dyn NodeTransport>>,
+    /// Reconnects the node, nodes without a connector are not reconnected
+    connector: Option<NodeConnector>,
+    /// A connection attempt is in flight
+    pub(crate) connecting: bool,
+    /// Time to wait after a failed connection attempt
+    reconnect_backoff: Duration,
+    /// Earliest time of the next connection attempt
+    next_connect_at: Instant,
+    /// Map of request_id to corresponding asset pair
+    request_id_map: HashMap<String, AssetPair>,
+    /// Subscribe requests whose pool was unsubscribed before the subscription id arrived, the
+    /// subscription is dropped once it does
+    cancelled_requests: HashSet<String>,
+    /// Map of subscription id to subscription for attributing websocket messages
+    /// to relevant asset pair.
+    subscription_map: HashMap<String, Subscription>,
+    /// Time the last price update was received from this node
+    pub(crate) last_price_update_at: Option<Instant>,
+    /// Limits the rate of REST requests, unlimited if `None`
+    pub(crate) rate_limiter: Option<RateLimiter>,
+}
+
+impl NodeConnection {
+    /// An already open transport which is not reconnected once it closes
+    pub(crate) fn connected(name: &str, transport: Box<dyn NodeTransport>) -> Self {
+        NodeConnection {
+            transport: Some(transport),
+            ..NodeConnection::new(name, None)
+        }
+    }
+
+    /// A node to connect to, and reconnect to whenever it disconnects
+    pub(crate) fn connectable(connector: NodeConnector) -> Self {
+        NodeConnection::new(&connector.name.clone(), Some(connector))
+    }
+
+    fn new(name: &str, connector: Option<NodeConnector>) -> Self {
+        NodeConnection {
+            name: name.to_string(),
+            transport: None,
+            connector,
+            connecting: false,
+            reconnect_backoff: MIN_RECONNECT_BACKOFF,
+            next_connect_at: Instant::now(),
+            request_id_map: HashMap::new(),
+            cancelled_requests: HashSet::new(),
+            subscription_map: HashMap::new(),
+            last_price_update_at: None,
+            rate_limiter: None,
+        }
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Whether the node is disconnected for good
+    pub(crate) fn is_closed(&self) -> bool {
+        self.transport.is_none() && self.connector.is_none()
+    }
+
+    pub(crate) fn transport(&self) -> Option<&dyn NodeTransport> {
+        self.transport.as_deref()
+    }
+
+    /// Start a connection attempt if the node is disconnected and its backoff has elapsed
+    pub(crate) fn start_connect(
+        &mut self,
+    ) -> Option<BoxFuture<'static, Result<Box<dyn NodeTransport>, TransportError>>> {
+        if self.is_connected() || self.connecting || Instant::now() < self.next_connect_at {
+            return None;
+        }
+
+        let connector = self.connector.as_ref()?;
+        self.connecting = true;
+
+        Some(connector.connect())
+    }
+
+    /// Use `transport` once a connection attempt succeeded
+    pub(crate) fn on_connected(&mut self, transport: Box<dyn NodeTransport>) {
+        self.connecting = false;
+        self.reconnect_backoff = MIN_RECONNECT_BACKOFF;
+        self.transport = Some(transport);
+    }
+
+    /// Back off before the next attempt once a connection attempt failed
+    pub(crate) fn on_connect_failed(&mut self) {
+        self.connecting = false;
+        self.next_connect_at = Instant::now() + self.reconnect_backoff;
+        self.reconnect_backoff = (self.reconnect_backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+
+    /// Drop the transport and every subscription made over it
+    pub(crate) fn disconnect(&mut self) {
+        self.transport = None;
+        self.request_id_map.clear();
+        self.cancelled_requests.clear();
+        self.subscription_map.clear();
+        self.last_price_update_at = None;
+        self.next_connect_at = Instant::now() + self.reconnect_backoff;
+    }
+
+    /// Send a `cf_subscribe_pool_price` request for `asset_pair`
+    pub(crate) async fn subscribe(&mut self, asset_pair: &AssetPair) -> Result<(), TransportError> {
+        let Some(transport) = self.transport.as_mut() else {
+            return Ok(());
+        };
+
+        let request_id = {
+            let mut rng = rand::thread_rng();
+            rng.gen::<i32>()
+        }
+        .to_string();
+
+        self.request_id_map
+            .insert(request_id.clone(), asset_pair.clone());
+
+        let to_send = json!({
+            "jsonrpc": "2.0",
+            "id": request_id,
+            "method": "cf_subscribe_pool_price",
+            "params": {
+                "from_asset": format!("{}", asset_pair.from),
+                "to_asset": format!("{}", asset_pair.to),
+            }
+        })
+        .to_string();
+
+        tracing::info!(
+            pair = %asset_pair,
+            node = %self.name,
+            request_id,
+            "subscribing to cf_subscribe_pool_price"
+        );
+
+        transport.send(to_send).await
+    }
+
+    /// Send a `cf_unsubscribe_pool_price` request for every subscription to `asset_pair`, and cancel
+    /// subscriptions still being made
+    pub(crate) async fn unsubscribe(
+        &mut self,
+        asset_pair: &AssetPair,
+    ) -> Result<(), TransportError> {
+        self.cancelled_requests.extend(
+            self.request_id_map
+                .iter()
+                .filter(|(_, pair)| *pair == asset_pair)
+                .map(|(request_id, _)| request_id.clone()),
+        );
+        let subscription_ids: Vec<String> = self
+            .subscription_map
+            .iter()
+            .filter(|(_, subscription)| subscription.asset_pair == *asset_pair)
+            .map(|(subscription_id, _)| subscription_id.clone())
+            .collect();
+
+        for subscription_id in subscription_ids {
+            let Some(subscription) = self.subscription_map.remove(&subscription_id) else {
+                continue;
+            };
+
+            if let Some(transport) = self.transport.as_mut() {
+                tracing::info!(parent: &subscription.span, "unsubscribing from cf_subscribe_pool_price");
+                transport
+                    .send(unsubscribe_request(&subscription_id))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unsubscribe from every pool and close the transport, the node is not reconnected afterwards
+    pub(crate) async fn shut_down(&mut self) {
+        self.connector = None;
+        let Some(mut transport) = self.transport.take() else {
+            return;
+        };
+
+        for subscription_id in self.subscription_map.keys() {
+            if let Err(e) = transport.send(unsubscribe_request(subscription_id)).await {
+                tracing::warn!(node = %self.name, error = ?e, "error unsubscribing");
+
+                break;
+            }
+        }
+
+        if let Err(e) = transport.close().await {
+            tracing::warn!(node = %self.name, error = ?e, "error closing connection");
+        }
+        self.disconnect();
+        tracing::info!(node = %self.name, "disconnected");
+    }
+
+    /// Record the subscription id returned for request `request_id`, unsubscribing straight away if
+    /// the request was cancelled. False if the request is unknown.
+    pub(crate) async fn on_subscribed(
+        &mut self,
+        request_id: &str,
+        subscription_id: String,
+    ) -> Result<bool, TransportError> {
+        let Some(asset_pair) = self.request_id_map.remove(request_id) else {
+            return Ok(false);
+        };
+
+        let span = tracing::info_span!(
+            "subscription",
+            pair = %asset_pair,
+            node = %self.name,
+            subscription_id = %subscription_id,
+        );
+        if self.cancelled_requests.remove(request_id) {
+            tracing::info!(parent: &span, request_id, "subscribed after being cancelled, unsubscribing");
+            if let Some(transport) = self.transport.as_mut() {
+                transport
+                    .send(unsubscribe_request(&subscription_id))
+                    .await?;
+            }
+
+            return Ok(true);
+        }
+
+        tracing::info!(parent: &span, request_id, "subscribed");
+        self.subscription_map
+            .insert(subscription_id, Subscription { asset_pair, span });
+
+        Ok(true)
+    }
+
+    /// Asset pair of subscription `subscription_id`
+    pub(crate) fn subscription(&self, subscription_id: &str) -> Option<&Subscription> {
+        self.subscription_map.get(subscription_id)
+    }
+}
+
+/// A `cf_unsubscribe_pool_price` request for subscription `subscription_id`
+fn unsubscribe_request(subscription_id: &str) -> String {
+    let request_id = {
+        let mut rng = rand::thread_rng();
+        rng.gen::<i32>()
+    }
+    .to_string();
+
+    json!({
+        "jsonrpc": "2.0",
+        "id": request_id,
+        "method": "cf_unsubscribe_pool_price",
+        "params": [subscription_id],
+    })
+    .to_string()
+}
+
+/// Receive the next frame from any connected node, along with the index of the node. Pending
+/// forever while no node is connected.
+///
+/// Cancel safe as every transport's `recv` is.
+pub(crate) async fn recv_any(
+    nodes: &mut [NodeConnection],
+) -> (usize, Option<Result<String, TransportError>>) {
+    let receives: Vec<_> = nodes
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(index, node)| {
+            node.transport
+                .as_mut()
+                .map(|transport| transport.recv().map(move |frame| (index, frame)))
+        })
+        .collect();
+
+    if receives.is_empty() {
+        return future::pending().await;
+    }
+
+    let (received, _, _) = future::select_all(receives).await;
+    received
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{NodeConnection, NodeConnector, RedundancyMode};
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let connector = NodeConnector::new("node", || {
+            Box::pin(async { Err(super::TransportError::Replay("down".to_string())) })
+        });
+        let mut node = NodeConnection::connectable(connector);
+
+        assert!(node.start_connect().is_some());
+        // only one attempt in flight at a time
+        assert!(node.start_connect().is_none());
+
+        node.on_connect_failed();
+        assert!(node.next_connect_at > Instant::now());
+        assert!(node.start_connect().is_none());
+
+        node.on_connect_failed();
+        node.on_connect_failed();
+        assert_eq!(super::MIN_RECONNECT_BACKOFF * 8, node.reconnect_backoff);
+        assert!(!node.is_closed());
+    }
+
+    #[test]
+    fn test_redundancy_mode_round_trip() {
+        for mode in [RedundancyMode::Failover, RedundancyMode::ActiveActive] {
+            assert_eq!(mode, mode.to_string().parse().unwrap());
+        }
+        assert!("active".parse::<RedundancyMode>().is_err());
+    }
+}
@@ -1,18 +1,40 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    future::Future,
+    time::{Duration, Instant},
+};
 
-use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use tokio::sync::{mpsc, watch};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-
-use crate::model::{
-    asset_pair::AssetPair, json_rpc::JsonRpcResponse, pool_price::PoolPrice,
-    price_update::PriceUpdate,
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::interval,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{field, Instrument};
+
+use crate::{
+    config::defaults,
+    health::HealthMonitor,
+    metrics::{self, LiquidityErrorKind},
+    model::{
+        asset_pair::AssetPair,
+        common::Tick,
+        json_rpc::{ChainflipJsonRpcRequest, JsonRpcAck, JsonRpcResponse},
+        liquidity::Liquidity,
+        pool_info::{PoolInfo, PoolInfoResponse},
+        pool_price::PoolPrice,
+        price_update::PriceUpdate,
+        timestamp::Timestamp,
+    },
+    transport::node_transport::{NodeTransport, TransportError},
 };
 
-use super::pool_info_provider_handle::{PoolInfoProviderHandle, PoolInfoProviderHandleMessage};
-use rand::prelude::*;
+use super::{
+    liquidity_cache::LiquidityCache,
+    node_connection::{recv_any, NodeConnection, NodeConnector, RedundancyMode},
+    pool_info_provider_handle::{PoolInfoProviderHandle, PoolInfoProviderHandleMessage},
+    rate_limiter::{RateLimit, RateLimiter},
+};
 
 /// Map of AssetPair to tokio watch channel (tx, rx)
 type AssetWatchChannelMap = HashMap<
@@ -31,22 +53,114 @@ enum WebsocketMessage {
     PoolPrice(PoolPrice),
     /// Response to a cf_subscribe_pool_price message with subscription id
     JsonRpcResponse(JsonRpcResponse),
+    /// Response to a cf_unsubscribe_pool_price message
+    JsonRpcAck(JsonRpcAck),
+}
+
+/// Shortest period of the health check, however small the staleness threshold
+const MIN_HEALTH_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Result of a connection attempt to the node at an index
+type ConnectResult = (usize, Result<Box<dyn NodeTransport>, TransportError>);
+
+/// Pool and block of a liquidity request, the block is the requested block hash or otherwise the
+/// pool's latest known block. `None` if neither is known.
+type LiquidityKey = (AssetPair, Option<String>);
+
+/// Clients waiting on a liquidity request
+type LiquidityWaiters = Vec<oneshot::Sender<Option<Liquidity>>>;
+
+/// Result of a liquidity request
+type LiquidityResult = (LiquidityKey, Option<Liquidity>);
+
+/// Prices of a pool published in its latest block, used to de-duplicate updates arriving from
+/// several nodes
+#[derive(Debug, Default)]
+struct PublishedPrices {
+    block_number: Option<u64>,
+    /// Ticks published in `block_number`
+    ticks: Vec<Tick>,
+    /// Tick and sqrt price of the last published update
+    last: Option<(Tick, String)>,
+}
+
+impl PublishedPrices {
+    /// Whether this is the first arrival of an update, recording it if so. Updates are identified
+    /// by block number and tick, updates from blocks older than the latest are dropped.
+    fn first_arrival(&mut self, block_number: Option<u64>, tick: Tick, sqrt_price: &str) -> bool {
+        let first = match (block_number, self.block_number) {
+            (Some(block), Some(latest)) if block < latest => false,
+            (Some(block), Some(latest)) if block == latest => !self.ticks.contains(&tick),
+            (Some(_), _) => true,
+            // without block numbers only a repeat of the last update can be recognised
+            (None, _) => self
+                .last
+                .as_ref()
+                .is_none_or(|(last_tick, last_sqrt_price)| {
+                    *last_tick != tick || last_sqrt_price != sqrt_price
+                }),
+        };
+
+        if first {
+            if block_number.is_some() && block_number != self.block_number {
+                self.block_number = block_number;
+                self.ticks.clear();
+            }
+            self.ticks.push(tick);
+            self.last = Some((tick, sqrt_price.to_string()));
+        }
+
+        first
+    }
 }
 
-/// An enduring thread which owns both websocket and REST communications with the node.
+/// An enduring thread which owns both websocket and REST communications with one or more nodes.
+///
+/// A single websocket is opened per node and multiple subscriptions to `cf_subscribe_pool_price` for
+/// different asset pairs can be made. Updates are pushed downstream internally (per asset pair) via a
+/// tokio::watch channel.
+///
+/// With several nodes every node is subscribed to every pool. Depending on the `RedundancyMode` either
+/// the active node alone supplies prices, failing over to a standby on disconnect or staleness, or
+/// every node does with duplicates dropped. REST requests go to the active node, falling back to the
+/// others on error. Disconnected nodes are reconnected with backoff.
+///
+/// Liquidity requests for the same pool and block share a single request in flight and responses are
+/// cached by pool and block. Requests to each node can be rate limited.
 ///
-/// A single websocket is opened and multiple subscriptions to `cf_subscribe_pool_price` for different
-/// asset pairs can be made. Updates are pushed downstream internally (per asset pair) via a tokio::watch channel.
+/// Communication goes through a `NodeTransport`, either a live node connection or a journal replay.
 pub struct PoolInfoProvider {
-    /// Hostname of node
-    hostname: String,
-    /// Map of request_id to corresponding asset pair
-    request_id_map: HashMap<String, AssetPair>,
+    /// Connections to the nodes, in order of preference
+    nodes: Vec<NodeConnection>,
+    /// Index of the node REST requests, and in failover mode prices, come from
+    active: usize,
+    /// When the active node was made active or last connected
+    active_since: Instant,
+    /// How the nodes are used
+    redundancy: RedundancyMode,
+    /// Latest published prices per pool, for de-duplicating updates in active-active mode
+    published: HashMap<AssetPair, PublishedPrices>,
+    /// Results of connection attempts
+    connect_tx: mpsc::UnboundedSender<ConnectResult>,
+    connect_rx: mpsc::UnboundedReceiver<ConnectResult>,
     /// Map of asset pair to tokio::watch channels for sending updates downstream
     asset_watch_channel_map: AssetWatchChannelMap,
-    /// Map of subscription id to asset_pair for attributing websocket messages
-    /// to relevant asset pair.
-    subscription_map: HashMap<String, AssetPair>,
+    /// Number of subscriptions to each pool, shared by its clients. The pool is unsubscribed once
+    /// the last is released.
+    subscriptions: HashMap<AssetPair, usize>,
+    /// Senders of the clients receiving every price update per pool
+    price_update_senders: HashMap<AssetPair, Vec<mpsc::UnboundedSender<PriceUpdate>>>,
+    /// Liquidity responses by pool and block hash
+    liquidity_cache: LiquidityCache,
+    /// Id of each liquidity request in flight and the clients waiting on it
+    liquidity_waiters: HashMap<LiquidityKey, (u64, LiquidityWaiters)>,
+    /// Id of the next liquidity request, identifies the request in logs and on the wire
+    next_request_id: u64,
+    /// Results of liquidity requests
+    liquidity_tx: mpsc::UnboundedSender<LiquidityResult>,
+    liquidity_rx: mpsc::UnboundedReceiver<LiquidityResult>,
+    /// Tracks price update times to detect stale pools
+    health_monitor: HealthMonitor,
     /// Handle which internal clients use to issue requests to this struct
     handle: PoolInfoProviderHandle,
     /// internal channel over which we receive requests from client handles
@@ -54,191 +168,709 @@ pub struct PoolInfoProvider {
 }
 
 impl PoolInfoProvider {
-    /// Create a new instance of `PoolInfoProvider`
-    pub fn new(hostname: &str) -> Self {
+    /// Create a new instance of `PoolInfoProvider` communicating over `transport`. The provider stops
+    /// once the transport closes.
+    pub fn new(transport: Box<dyn NodeTransport>, health_monitor: HealthMonitor) -> Self {
+        Self::with_node_connections(
+            vec![NodeConnection::connected("node", transport)],
+            RedundancyMode::Failover,
+            health_monitor,
+        )
+    }
+
+    /// Create a new instance of `PoolInfoProvider` connecting to every node, the first is the
+    /// initially active node. Nodes are reconnected whenever they disconnect.
+    pub fn with_nodes(
+        connectors: Vec<NodeConnector>,
+        redundancy: RedundancyMode,
+        health_monitor: HealthMonitor,
+    ) -> Self {
+        Self::with_node_connections(
+            connectors
+                .into_iter()
+                .map(NodeConnection::connectable)
+                .collect(),
+            redundancy,
+            health_monitor,
+        )
+    }
+
+    fn with_node_connections(
+        nodes: Vec<NodeConnection>,
+        redundancy: RedundancyMode,
+        health_monitor: HealthMonitor,
+    ) -> Self {
         let (internal_tx, internal_rx) = mpsc::unbounded_channel();
-        let handle = PoolInfoProviderHandle::new(internal_tx);
+        let (connect_tx, connect_rx) = mpsc::unbounded_channel();
+        let (liquidity_tx, liquidity_rx) = mpsc::unbounded_channel();
+        let handle = PoolInfoProviderHandle::new(internal_tx, health_monitor.clone());
 
         PoolInfoProvider {
-            hostname: hostname.to_string(),
-            request_id_map: HashMap::new(),
+            nodes,
+            active: 0,
+            active_since: Instant::now(),
+            redundancy,
+            published: HashMap::new(),
+            connect_tx,
+            connect_rx,
             asset_watch_channel_map: HashMap::new(),
-            subscription_map: HashMap::new(),
+            price_update_senders: HashMap::new(),
+            subscriptions: HashMap::new(),
+            liquidity_cache: LiquidityCache::new(defaults::LIQUIDITY_CACHE_ENTRIES),
+            liquidity_waiters: HashMap::new(),
+            next_request_id: 1,
+            liquidity_tx,
+            liquidity_rx,
+            health_monitor,
             handle,
             internal_rx,
         }
     }
 
+    /// Limit the rate of REST requests to the node at index `node`, in order of preference
+    pub fn with_rate_limit(mut self, node: usize, rate_limit: RateLimit) -> Self {
+        if let Some(node) = self.nodes.get_mut(node) {
+            node.rate_limiter = Some(RateLimiter::new(rate_limit));
+        }
+        self
+    }
+
+    /// Cache up to `capacity` liquidity responses, 0 disables caching
+    pub fn with_liquidity_cache_capacity(mut self, capacity: usize) -> Self {
+        self.liquidity_cache = LiquidityCache::new(capacity);
+        self
+    }
+
     /// Get a clone of the `PoolInfoProviderHandle` for interacting with this instance
     pub fn get_handle(&self) -> PoolInfoProviderHandle {
         self.handle.clone()
     }
 
-    /// Enduring loop, process websocket message and internal requests
-    pub async fn run(&mut self) {
-        let (ws_stream, _) = connect_async(format!("ws://{}", &self.hostname))
-            .await
-            .expect("error connecting to websocket");
-        let (mut ws_write, mut ws_read) = ws_stream.split();
+    /// Flag the latest price of pools which haven't updated within the staleness threshold as stale,
+    /// notifying watchers
+    fn mark_stale_pools(&self) {
+        for asset_pair in self.health_monitor.stale_pools() {
+            let Some((tx, _)) = self.asset_watch_channel_map.get(&asset_pair) else {
+                continue;
+            };
+
+            tx.send_if_modified(|price_update| match price_update {
+                Some(price_update) if !price_update.stale => {
+                    tracing::warn!(
+                        pair = %asset_pair,
+                        stale_after = ?self.health_monitor.stale_after(),
+                        "no price update, marking stale"
+                    );
+                    price_update.stale = true;
+
+                    true
+                }
+                _ => false,
+            });
+        }
+    }
+
+    /// Start connecting to every disconnected node whose reconnect backoff has elapsed
+    fn start_connections(&mut self) {
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            let Some(connect) = node.start_connect() else {
+                continue;
+            };
+
+            tracing::info!(node = %node.name, "connecting");
+            let connect_tx = self.connect_tx.clone();
+            tokio::spawn(async move {
+                let _ = connect_tx.send((index, connect.await));
+            });
+        }
+    }
+
+    /// Make the node at `index` the active node
+    fn fail_over_to(&mut self, index: usize, reason: &str) {
+        if index == self.active {
+            return;
+        }
+
+        tracing::warn!(
+            from = %self.nodes[self.active].name,
+            to = %self.nodes[index].name,
+            reason,
+            "failing over"
+        );
+        metrics::inc_node_failovers(&self.nodes[index].name);
+        self.active = index;
+        self.active_since = Instant::now();
+    }
+
+    /// Fail over if the active node is disconnected, or stale while a standby is receiving updates
+    fn check_active_node(&mut self) {
+        let stale_after = self.health_monitor.stale_after();
+        let active = &self.nodes[self.active];
+
+        if !active.is_connected() {
+            if let Some(index) = self.nodes.iter().position(|node| node.is_connected()) {
+                self.fail_over_to(index, "active node disconnected");
+            }
+
+            return;
+        }
+
+        // a node that never delivers an update is stale once it has been active for long enough
+        let active_is_stale = active
+            .last_price_update_at
+            .unwrap_or(self.active_since)
+            .elapsed()
+            > stale_after;
+        if !active_is_stale {
+            return;
+        }
+
+        let freshest = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_connected())
+            .filter_map(|(index, node)| Some((index, node.last_price_update_at?)))
+            .filter(|(_, at)| at.elapsed() <= stale_after)
+            .max_by_key(|(_, at)| *at);
+        if let Some((index, _)) = freshest {
+            self.fail_over_to(index, "active node is stale");
+        }
+    }
+
+    /// Drop the connection to the node at `index`, failing over if it was active
+    fn on_node_disconnected(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        node.disconnect();
+        metrics::set_node_connected(&node.name, false);
+
+        if index == self.active {
+            self.check_active_node();
+        }
+    }
+
+    /// Subscribe a newly connected node to every pool
+    async fn on_node_connected(&mut self, index: usize, transport: Box<dyn NodeTransport>) {
+        if index == self.active {
+            self.active_since = Instant::now();
+        }
+
+        let node = &mut self.nodes[index];
+        tracing::info!(node = %node.name, "connected");
+        node.on_connected(transport);
+        metrics::set_node_connected(&node.name, true);
+
+        for asset_pair in self.asset_watch_channel_map.keys() {
+            if let Err(e) = node.subscribe(asset_pair).await {
+                tracing::error!(node = %node.name, error = ?e, "error writing to websocket");
+                self.on_node_disconnected(index);
+
+                return;
+            }
+        }
+
+        self.check_active_node();
+    }
+
+    /// Issue `request` to the active node, falling back to the other connected nodes on error.
+    ///
+    /// The returned future does not borrow the provider, requests to fallback nodes are only made
+    /// once it is polled past the failure of the previous node.
+    fn request(
+        &self,
+        request: ChainflipJsonRpcRequest,
+    ) -> impl Future<Output = Result<String, TransportError>> + 'static {
+        let standbys = (0..self.nodes.len()).filter(|index| *index != self.active);
+        let attempts: Vec<_> = std::iter::once(self.active)
+            .chain(standbys)
+            .filter_map(|index| {
+                let node = &self.nodes[index];
+                Some((
+                    node.name.clone(),
+                    node.rate_limiter.clone(),
+                    node.transport()?.request(request.clone()),
+                ))
+            })
+            .collect();
+        let method = request.method().to_string();
+
+        async move {
+            let mut result = Err(TransportError::NotConnected);
+
+            let fallbacks = attempts.len() > 1;
+            for (name, rate_limiter, attempt) in attempts {
+                if let Some(rate_limiter) = rate_limiter {
+                    if rate_limiter.acquire().await {
+                        metrics::inc_rate_limited_requests(&name);
+                    }
+                }
+                result = attempt.await;
+                match &result {
+                    Ok(_) => break,
+                    Err(e) if fallbacks => {
+                        tracing::warn!(method, node = %name, error = %e, "request failed")
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            result
+        }
+    }
+
+    /// Handle a request from a `PoolInfoProviderHandle`, false if the provider should stop
+    async fn on_internal_message(&mut self, msg: PoolInfoProviderHandleMessage) -> bool {
+        match msg {
+            PoolInfoProviderHandleMessage::SubscribePoolPriceUpdates { asset_pair } => {
+                *self.subscriptions.entry(asset_pair.clone()).or_default() += 1;
+                if self.asset_watch_channel_map.contains_key(&asset_pair) {
+                    return true;
+                }
+
+                let (tx, rx) = watch::channel(None);
+                self.asset_watch_channel_map
+                    .insert(asset_pair.clone(), (tx, rx));
+                self.health_monitor.register_pool(&asset_pair);
+
+                // nodes which aren't connected yet subscribe once they are
+                for index in 0..self.nodes.len() {
+                    if let Err(e) = self.nodes[index].subscribe(&asset_pair).await {
+                        tracing::error!(
+                            node = %self.nodes[index].name,
+                            error = ?e,
+                            "error writing to websocket"
+                        );
+                        self.on_node_disconnected(index);
+                    }
+                }
+                if self.nodes.iter().all(|node| node.is_closed()) {
+                    return false;
+                }
+            }
+            PoolInfoProviderHandleMessage::UnsubscribePoolPriceUpdates { asset_pair } => {
+                // other clients still use the pool
+                let Some(subscriptions) = self.subscriptions.get_mut(&asset_pair) else {
+                    return true;
+                };
+                *subscriptions -= 1;
+                if *subscriptions > 0 {
+                    return true;
+                }
+                self.subscriptions.remove(&asset_pair);
+
+                // dropping the watch channel closes every price update stream of the pool
+                if self.asset_watch_channel_map.remove(&asset_pair).is_none() {
+                    return true;
+                }
+                self.price_update_senders.remove(&asset_pair);
+                self.published.remove(&asset_pair);
+                self.liquidity_cache.remove_pool(&asset_pair);
+                self.health_monitor.unregister_pool(&asset_pair);
+
+                for index in 0..self.nodes.len() {
+                    if let Err(e) = self.nodes[index].unsubscribe(&asset_pair).await {
+                        tracing::error!(
+                            node = %self.nodes[index].name,
+                            error = ?e,
+                            "error writing to websocket"
+                        );
+                        self.on_node_disconnected(index);
+                    }
+                }
+                if self.nodes.iter().all(|node| node.is_closed()) {
+                    return false;
+                }
+            }
+            PoolInfoProviderHandleMessage::GetLatestPoolPrice { asset_pair, tx } => {
+                let response = match self.asset_watch_channel_map.get(&asset_pair) {
+                    Some((_, rx)) => rx.borrow().clone().map(|mut price_update| {
+                        price_update.stale = self.health_monitor.is_pool_stale(&asset_pair);
+                        price_update
+                    }),
+                    None => None,
+                };
+
+                match tx.send(response) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::error!(error = ?e, "error sending GetLatestPoolPrice client response");
+
+                        return false;
+                    }
+                }
+            }
+            PoolInfoProviderHandleMessage::GetStreamingPoolPriceUpdates { asset_pair, tx } => {
+                let response = self
+                    .asset_watch_channel_map
+                    .get(&asset_pair)
+                    .map(|(_, rx)| rx.clone());
+
+                match tx.send(response) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::error!(
+                            error = ?e,
+                            "error sending GetStreamingPoolPriceUpdates client response"
+                        );
+
+                        return false;
+                    }
+                }
+            }
+            PoolInfoProviderHandleMessage::GetPoolPriceUpdateStream { asset_pair, tx } => {
+                let response = self
+                    .asset_watch_channel_map
+                    .contains_key(&asset_pair)
+                    .then(|| {
+                        let (update_tx, update_rx) = mpsc::unbounded_channel();
+                        self.price_update_senders
+                            .entry(asset_pair)
+                            .or_default()
+                            .push(update_tx);
+                        update_rx
+                    });
+
+                match tx.send(response) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::error!(
+                            error = ?e,
+                            "error sending GetPoolPriceUpdateStream client response"
+                        );
+
+                        return false;
+                    }
+                }
+            }
+            PoolInfoProviderHandleMessage::GetLiquidity {
+                asset_pair,
+                at,
+                tx,
+                span,
+            } => {
+                let block_hash = at.clone().or_else(|| {
+                    let (_, rx) = self.asset_watch_channel_map.get(&asset_pair)?;
+                    let latest_block_hash = rx.borrow().as_ref()?.block_hash.clone();
+                    latest_block_hash
+                });
+                if let Some(block_hash) = &block_hash {
+                    let cached = self.liquidity_cache.get(&asset_pair, block_hash);
+                    metrics::inc_liquidity_cache_lookups(&asset_pair, cached.is_some());
+                    if let Some(liquidity) = cached {
+                        tracing::debug!(
+                            parent: &span,
+                            pair = %asset_pair,
+                            block_hash,
+                            "liquidity served from cache"
+                        );
+                        if tx.send(Some(liquidity)).is_err() {
+                            tracing::warn!(pair = %asset_pair, "client stopped waiting for liquidity");
+                        }
+
+                        return true;
+                    }
+                }
+
+                let key = (asset_pair.clone(), block_hash);
+                if let Some((request_id, waiters)) = self.liquidity_waiters.get_mut(&key) {
+                    tracing::debug!(
+                        parent: &span,
+                        pair = %asset_pair,
+                        request_id = *request_id,
+                        "liquidity request coalesced with the request in flight"
+                    );
+                    metrics::inc_coalesced_liquidity_requests(&asset_pair);
+                    waiters.push(tx);
+
+                    return true;
+                }
+                let request_id = self.next_request_id;
+                self.next_request_id += 1;
+                self.liquidity_waiters
+                    .insert(key.clone(), (request_id, vec![tx]));
+
+                let mut params = HashMap::from([
+                    ("base_asset".to_string(), asset_pair.from.clone()),
+                    ("quote_asset".to_string(), asset_pair.to.clone()),
+                ]);
+                // pinned to the block it is cached under, rather than whatever the node's latest is
+                if let Some(block_hash) = key.1.clone() {
+                    params.insert("at".to_string(), block_hash);
+                }
+                let request = ChainflipJsonRpcRequest::new(
+                    &request_id.to_string(),
+                    "cf_pool_liquidity",
+                    params,
+                );
+
+                // a child of the requesting client's span, so a slow book can be traced to the
+                // request it waited on
+                let request_span = tracing::info_span!(
+                    parent: &span,
+                    "liquidity_request",
+                    pair = %asset_pair,
+                    request_id,
+                    block_hash = key.1.as_deref(),
+                    latency_ms = field::Empty,
+                );
+
+                // the request runs alongside the provider loop, its result is handed back over
+                // `liquidity_tx`
+                let response = self.request(request);
+                let liquidity_tx = self.liquidity_tx.clone();
+                tokio::spawn(
+                    async move {
+                        let started_at = Instant::now();
+                        let response = response.await;
+                        metrics::observe_liquidity_request(&asset_pair, started_at);
+                        let latency_ms = started_at.elapsed().as_secs_f64() * 1000.0;
+                        tracing::Span::current().record("latency_ms", latency_ms);
+
+                        let liquidity = parse_liquidity(&asset_pair, response);
+                        tracing::debug!(latency_ms, ok = liquidity.is_some(), "liquidity received");
+                        let _ = liquidity_tx.send((key, liquidity));
+                    }
+                    .instrument(request_span),
+                );
+            }
+            PoolInfoProviderHandleMessage::GetPoolInfo { asset_pair, tx } => {
+                let request_id = self.next_request_id;
+                self.next_request_id += 1;
+                let params = HashMap::from([
+                    ("base_asset".to_string(), asset_pair.from.clone()),
+                    ("quote_asset".to_string(), asset_pair.to.clone()),
+                ]);
+                let request =
+                    ChainflipJsonRpcRequest::new(&request_id.to_string(), "cf_pool_info", params);
+
+                let response = self.request(request);
+                tokio::spawn(async move {
+                    let pool_info = parse_pool_info(&asset_pair, response.await);
+                    if tx.send(pool_info).is_err() {
+                        tracing::warn!(pair = %asset_pair, "client stopped waiting for pool info");
+                    }
+                });
+            }
+        }
+
+        true
+    }
+
+    /// Answer every client waiting on the liquidity request for `key`
+    fn on_liquidity(&mut self, key: LiquidityKey, liquidity: Option<Liquidity>) {
+        if let (Some(block_hash), Some(liquidity)) = (&key.1, &liquidity) {
+            if self
+                .liquidity_cache
+                .insert(&key.0, block_hash, liquidity.clone())
+            {
+                metrics::inc_liquidity_cache_evictions();
+            }
+        }
+
+        let (_, waiters) = self.liquidity_waiters.remove(&key).unwrap_or_default();
+        for tx in waiters {
+            if tx.send(liquidity.clone()).is_err() {
+                tracing::warn!(pair = %key.0, "client stopped waiting for liquidity");
+            }
+        }
+    }
+
+    /// Answer requests already queued and wait for requests in flight, then unsubscribe from every
+    /// pool and close every node connection
+    async fn shut_down(&mut self) {
+        tracing::info!("shutting down pool info provider");
+
+        self.internal_rx.close();
+        while let Some(msg) = self.internal_rx.recv().await {
+            if !self.on_internal_message(msg).await {
+                break;
+            }
+        }
+        while !self.liquidity_waiters.is_empty() {
+            let Some((key, liquidity)) = self.liquidity_rx.recv().await else {
+                break;
+            };
+            self.on_liquidity(key, liquidity);
+        }
+
+        for node in self.nodes.iter_mut() {
+            node.shut_down().await;
+            metrics::set_node_connected(&node.name, false);
+        }
+    }
+
+    /// Enduring loop, process websocket message and internal requests until `shutdown` is cancelled
+    pub async fn run(&mut self, shutdown: CancellationToken) {
+        let mut health_check_interval = interval(
+            (self
+                .health_monitor
+                .stale_after()
+                .min(Duration::from_secs(2))
+                / 2)
+            .max(MIN_HEALTH_CHECK_INTERVAL),
+        );
 
         loop {
             tokio::select! {
-                websocket_message = ws_read.next() => {
+                _ = shutdown.cancelled() => {
+                    self.shut_down().await;
+
+                    break;
+                },
+                _ = health_check_interval.tick() => {
+                    self.health_monitor.record_provider_heartbeat();
+                    self.mark_stale_pools();
+                    self.check_active_node();
+                    self.start_connections();
+                },
+                Some((index, result)) = self.connect_rx.recv() => {
+                    match result {
+                        Ok(transport) => self.on_node_connected(index, transport).await,
+                        Err(e) => {
+                            tracing::error!(node = %self.nodes[index].name, error = %e, "error connecting");
+                            self.nodes[index].on_connect_failed();
+                        },
+                    }
+                },
+                (index, websocket_message) = recv_any(&mut self.nodes) => {
+                    let received_at = Timestamp::now();
                     let websocket_message = match websocket_message {
                         Some(msg) => match msg {
-                            Ok(msg) => match msg {
-                                Message::Text(msg) => msg,
-                                _ => {
-
-                                    continue;
-                                }
-                            },
+                            Ok(msg) => msg,
                             Err(e) => {
-                                log::error!("error receiving websocket message: {:?}", e);
+                                tracing::error!(node = %self.nodes[index].name, error = ?e, "error receiving websocket message");
+                                self.on_node_disconnected(index);
 
-                                break;
+                                if self.nodes.iter().all(|node| node.is_closed()) {
+                                    break;
+                                }
+                                continue;
                             },
                         },
                         None => {
-                            log::error!("websocket disconnected");
+                            tracing::error!(node = %self.nodes[index].name, "websocket disconnected");
+                            self.on_node_disconnected(index);
 
-                            break;
+                            if self.nodes.iter().all(|node| node.is_closed()) {
+                                break;
+                            }
+                            continue;
                         }
                     };
 
-                    log::trace!("websocket recv: {:?}", &websocket_message);
+                    tracing::trace!(node = %self.nodes[index].name, frame = %websocket_message, "websocket recv");
+
+                    let deser: WebsocketMessage = match serde_json::from_str(&websocket_message) {
+                        Ok(deser) => deser,
+                        Err(e) => {
+                            tracing::warn!(frame = %websocket_message, error = %e, "unrecognised websocket message");
 
-                    let deser: WebsocketMessage = serde_json::from_str(&websocket_message).unwrap();
+                            continue;
+                        },
+                    };
                     match deser {
                         WebsocketMessage::PoolPrice(pp) => {
-                            let asset_pair = self.subscription_map.get(&pp.params.subscription).unwrap();
-                            let update = PriceUpdate {asset_pair:asset_pair.clone(),price:pp.params.result.price,sqrt_price:pp.params.result.sqrt_price,tick:pp.params.result.tick };
+                            let node = &mut self.nodes[index];
+                            let (asset_pair, subscription_span) = match node.subscription(&pp.params.subscription) {
+                                Some(subscription) => (subscription.asset_pair.clone(), subscription.span.clone()),
+                                None => {
+                                    tracing::debug!(node = %node.name, subscription_id = %pp.params.subscription, "price update for unknown subscription");
+
+                                    continue;
+                                },
+                            };
+                            let asset_pair = &asset_pair;
+                            metrics::inc_websocket_messages_received(asset_pair);
+                            node.last_price_update_at = Some(received_at.monotonic);
+
+                            let result = pp.params.result;
+                            tracing::trace!(
+                                parent: &subscription_span,
+                                block = result.block_number,
+                                tick = result.tick,
+                                "price update"
+                            );
+                            match self.redundancy {
+                                // standbys are only kept subscribed
+                                RedundancyMode::Failover if index != self.active => continue,
+                                RedundancyMode::Failover => {},
+                                RedundancyMode::ActiveActive => {
+                                    let published = self.published.entry(asset_pair.clone()).or_default();
+                                    if !published.first_arrival(result.block_number, result.tick, &result.sqrt_price) {
+                                        metrics::inc_duplicate_price_updates(asset_pair);
+
+                                        continue;
+                                    }
+                                },
+                            }
+
+                            if let Some(block_hash) = &result.block_hash {
+                                self.liquidity_cache.on_new_block(asset_pair, block_hash);
+                            }
+
+                            let update = PriceUpdate {
+                                asset_pair: asset_pair.clone(),
+                                price: result.price,
+                                sqrt_price: result.sqrt_price,
+                                tick: result.tick,
+                                block_number: result.block_number,
+                                block_hash: result.block_hash,
+                                stale: false,
+                                received_at,
+                            };
+
+                            // clients dropping their stream stop being sent updates
+                            if let Some(senders) = self.price_update_senders.get_mut(asset_pair) {
+                                senders.retain(|update_tx| update_tx.send(update.clone()).is_ok());
+                            }
 
                             let (tx, _) = self.asset_watch_channel_map.get(asset_pair).unwrap();
 
                             let send_result = tx.send(Some(update));
 
                             match send_result {
-                                Ok(_) => {},
+                                Ok(_) => {
+                                    metrics::inc_price_updates(asset_pair);
+                                    self.health_monitor.record_price_update(asset_pair);
+                                },
                                 Err(e) => {
-                                    log::error!("error sending latest price update: {:?}", e);
+                                    tracing::error!(pair = %asset_pair, error = ?e, "error sending latest price update");
 
                                     break;
                                 },
                             }
                         },
                         WebsocketMessage::JsonRpcResponse(resp) => {
-                            let asset_pair = self.request_id_map.get(&resp.id).unwrap();
-                            self.subscription_map.insert(resp.result, asset_pair.clone());
+                            match self.nodes[index].on_subscribed(&resp.id, resp.result.clone()).await {
+                                Ok(true) => {},
+                                Ok(false) => {
+                                    tracing::warn!(node = %self.nodes[index].name, request_id = %resp.id, "response to unknown request");
+                                },
+                                Err(e) => {
+                                    tracing::error!(node = %self.nodes[index].name, error = ?e, "error writing to websocket");
+                                    self.on_node_disconnected(index);
+                                },
+                            }
+                        },
+                        WebsocketMessage::JsonRpcAck(ack) => {
+                            tracing::debug!(node = %self.nodes[index].name, request_id = %ack.id, result = ack.result, "acknowledged");
                         },
                     }
                 },
+                Some((key, liquidity)) = self.liquidity_rx.recv() => {
+                    self.on_liquidity(key, liquidity);
+                },
                 internal_message = self.internal_rx.recv() => {
+                    metrics::set_queue_depth("pool_info_provider", self.internal_rx.len());
+
                     match internal_message {
                         Some(msg) => {
-                            match msg {
-                                PoolInfoProviderHandleMessage::SubscribePoolPriceUpdates { asset_pair } => {
-                                    if self.asset_watch_channel_map.contains_key(&asset_pair) {
-
-                                        continue;
-                                    }
-
-                                    let (tx, rx) = watch::channel(None);
-                                    self.asset_watch_channel_map.insert(asset_pair.clone(), (tx, rx));
-
-                                    let request_id = {
-                                        let mut rng = rand::thread_rng();
-                                        rng.gen::<i32>()
-                                    }.to_string();
-
-                                    self.request_id_map.insert(request_id.clone(), asset_pair.clone());
-
-                                    let to_send = json!({
-                                        "jsonrpc": "2.0",
-                                        "id": request_id,
-                                        "method": "cf_subscribe_pool_price",
-                                        "params": {
-                                            "from_asset": format!("{}", asset_pair.from),
-                                            "to_asset": format!("{}", asset_pair.to),
-                                        }
-                                    }).to_string();
-
-                                    log::info!("subscribing to cf_subscribe_pool_price for {:?}", &asset_pair);
-
-                                    match ws_write.send(Message::Text(to_send)).await {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            log::error!("error writing to websocket: {:?}", e);
-
-                                            break;
-                                        },
-                                    }
-                                },
-                                PoolInfoProviderHandleMessage::GetLatestPoolPrice { asset_pair, tx } => {
-                                    let response = match self.asset_watch_channel_map.get(&asset_pair) {
-                                        Some((_, rx)) => {
-                                            rx.borrow().clone()
-                                        },
-                                        None => None
-                                    };
-
-                                    match tx.send(response) {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            log::error!("error sending GetLatestPoolPrice client response: {:?}", e);
-
-                                            break;
-                                        },
-                                    }
-                                },
-                                PoolInfoProviderHandleMessage::GetStreamingPoolPriceUpdates { asset_pair, tx } => {
-                                    let response = self.asset_watch_channel_map.get(&asset_pair).map(|(_, rx)| rx.clone());
-
-                                    match tx.send(response) {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            log::error!("error sending GetStreamingPoolPriceUpdates client response: {:?}", e);
-
-                                            break;
-                                        },
-                                    }
-                                },
-                                PoolInfoProviderHandleMessage::GetLiquidity { asset_pair, tx } => {
-                                    let client = reqwest::Client::new();
-
-                                    let to_send = json!({
-                                        "jsonrpc": "2.0",
-                                        "id": "1",
-                                        "method": "cf_pool_liquidity",
-                                        "params": {
-                                            "base_asset": format!("{}", asset_pair.from),
-                                            "quote_asset": format!("{}", asset_pair.to),
-                                        }
-                                    });
-
-                                    let resp = client
-                                        .post(format!("http://{}", &self.hostname))
-                                        .json(&to_send)
-                                        .send()
-                                        .await
-                                        .unwrap();
-
-                                    let response_text = resp.text().await.unwrap();
-                                    let liquidity = serde_json::from_str(&response_text).unwrap();
-
-                                    match tx.send(Some(liquidity)) {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            log::error!("error sending GetLiquidity client response: {:?}", e);
-
-                                            break;
-                                        },
-                                    }
-                                },
+                            if !self.on_internal_message(msg).await {
+                                break;
                             }
                         },
                         None => {
-                            log::error!("error receiving internal message");
+                            tracing::error!("error receiving internal message");
 
                             break;
                         },
@@ -248,3 +880,86 @@ impl PoolInfoProvider {
         }
     }
 }
+
+/// Parse a `cf_pool_liquidity` response, recording errors
+fn parse_liquidity(
+    asset_pair: &AssetPair,
+    response: Result<String, TransportError>,
+) -> Option<Liquidity> {
+    match response {
+        Ok(response_text) => match serde_json::from_str(&response_text) {
+            Ok(liquidity) => Some(liquidity),
+            Err(e) => {
+                tracing::error!(
+                    pair = %asset_pair,
+                    error = %e,
+                    "error parsing cf_pool_liquidity response"
+                );
+                metrics::inc_liquidity_request_errors(asset_pair, LiquidityErrorKind::Parse);
+
+                None
+            }
+        },
+        Err(e) => {
+            tracing::error!(
+                pair = %asset_pair,
+                error = %e,
+                "error requesting cf_pool_liquidity"
+            );
+            metrics::inc_liquidity_request_errors(asset_pair, LiquidityErrorKind::Transport);
+
+            None
+        }
+    }
+}
+
+/// Parse a `cf_pool_info` response, logging errors
+fn parse_pool_info(
+    asset_pair: &AssetPair,
+    response: Result<String, TransportError>,
+) -> Option<PoolInfo> {
+    match response {
+        Ok(response_text) => match serde_json::from_str::<PoolInfoResponse>(&response_text) {
+            Ok(response) => Some(response.result),
+            Err(e) => {
+                tracing::warn!(pair = %asset_pair, error = %e, "error parsing cf_pool_info response");
+
+                None
+            }
+        },
+        Err(e) => {
+            tracing::warn!(pair = %asset_pair, error = %e, "error requesting cf_pool_info");
+
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PublishedPrices;
+
+    #[test]
+    fn test_first_arrival_by_block_and_tick() {
+        let mut published = PublishedPrices::default();
+
+        assert!(published.first_arrival(Some(10), 100, "0x1"));
+        // the same update from another node
+        assert!(!published.first_arrival(Some(10), 100, "0x1"));
+        // a different tick in the same block
+        assert!(published.first_arrival(Some(10), 101, "0x2"));
+        assert!(published.first_arrival(Some(11), 101, "0x2"));
+        // a lagging node
+        assert!(!published.first_arrival(Some(10), 102, "0x3"));
+    }
+
+    #[test]
+    fn test_first_arrival_without_block_numbers() {
+        let mut published = PublishedPrices::default();
+
+        assert!(published.first_arrival(None, 100, "0x1"));
+        assert!(!published.first_arrival(None, 100, "0x1"));
+        assert!(published.first_arrival(None, 100, "0x2"));
+        assert!(published.first_arrival(None, 100, "0x1"));
+    }
+}
@@ -2,13 +2,21 @@ use std::sync::Arc;
 
 use tokio::sync::{mpsc, oneshot, watch};
 
-use crate::model::{asset_pair::AssetPair, liquidity::Liquidity, price_update::PriceUpdate};
+use crate::{
+    health::HealthMonitor,
+    model::{
+        asset_pair::AssetPair, liquidity::Liquidity, pool_info::PoolInfo, price_update::PriceUpdate,
+    },
+};
 
 /// Requests a `PoolInfoProviderHandle` can send to the `PoolInfoProvider` instance
 pub enum PoolInfoProviderHandleMessage {
     SubscribePoolPriceUpdates {
         asset_pair: AssetPair,
     },
+    UnsubscribePoolPriceUpdates {
+        asset_pair: AssetPair,
+    },
     GetLatestPoolPrice {
         asset_pair: AssetPair,
         tx: oneshot::Sender<Option<PriceUpdate>>,
@@ -17,9 +25,21 @@ pub enum PoolInfoProviderHandleMessage {
         asset_pair: AssetPair,
         tx: oneshot::Sender<Option<watch::Receiver<Option<PriceUpdate>>>>,
     },
+    GetPoolPriceUpdateStream {
+        asset_pair: AssetPair,
+        tx: oneshot::Sender<Option<mpsc::UnboundedReceiver<PriceUpdate>>>,
+    },
     GetLiquidity {
         asset_pair: AssetPair,
+        /// Block hash to query at, the latest block if `None`
+        at: Option<String>,
         tx: oneshot::Sender<Option<Liquidity>>,
+        /// Span of the requesting client, parent of the request's span
+        span: tracing::Span,
+    },
+    GetPoolInfo {
+        asset_pair: AssetPair,
+        tx: oneshot::Sender<Option<PoolInfo>>,
     },
 }
 
@@ -27,17 +47,27 @@ pub enum PoolInfoProviderHandleMessage {
 pub struct PoolInfoProviderHandle {
     /// Sender channel for communicating with `PoolInfoProvider` instance
     pool_info_provider_handle_tx: Arc<mpsc::UnboundedSender<PoolInfoProviderHandleMessage>>,
+    /// Health of the `PoolInfoProvider` and the builders using it
+    health_monitor: HealthMonitor,
 }
 
 impl PoolInfoProviderHandle {
     pub fn new(
         pool_info_provider_handle_tx: mpsc::UnboundedSender<PoolInfoProviderHandleMessage>,
+        health_monitor: HealthMonitor,
     ) -> Self {
         PoolInfoProviderHandle {
             pool_info_provider_handle_tx: Arc::new(pool_info_provider_handle_tx),
+            health_monitor,
         }
     }
 
+    pub fn health_monitor(&self) -> &HealthMonitor {
+        &self.health_monitor
+    }
+
+    /// Receive price updates for `asset_pair`. Subscriptions are shared, each is released with
+    /// `unsubscribe_pool_price_updates`.
     pub fn subscribe_pool_price_updates(&self, asset_pair: &AssetPair) {
         // TODO: dont consume result with `_`
         let _ = self.pool_info_provider_handle_tx.send(
@@ -47,6 +77,16 @@ impl PoolInfoProviderHandle {
         );
     }
 
+    /// Release a subscription to `asset_pair`. Once the last is released price updates stop and
+    /// every price update stream of the pool closes.
+    pub fn unsubscribe_pool_price_updates(&self, asset_pair: &AssetPair) {
+        let _ = self.pool_info_provider_handle_tx.send(
+            PoolInfoProviderHandleMessage::UnsubscribePoolPriceUpdates {
+                asset_pair: asset_pair.clone(),
+            },
+        );
+    }
+
     pub async fn get_streaming_pool_price_updates(
         &self,
         asset_pair: &AssetPair,
@@ -60,7 +100,57 @@ impl PoolInfoProviderHandle {
             },
         );
 
-        rx.await.unwrap()
+        // the provider has shut down if the request goes unanswered
+        rx.await.ok().flatten()
+    }
+
+    /// Every price update of a subscribed pool from now on, none are dropped however slowly they are
+    /// received. Unlike the streaming updates, stale prices are not re-sent flagged as such. The
+    /// stream closes once the pool is unsubscribed.
+    pub async fn get_pool_price_update_stream(
+        &self,
+        asset_pair: &AssetPair,
+    ) -> Option<mpsc::UnboundedReceiver<PriceUpdate>> {
+        let (tx, rx) = oneshot::channel();
+
+        let _ = self.pool_info_provider_handle_tx.send(
+            PoolInfoProviderHandleMessage::GetPoolPriceUpdateStream {
+                asset_pair: asset_pair.clone(),
+                tx,
+            },
+        );
+
+        // the provider has shut down if the request goes unanswered
+        rx.await.ok().flatten()
+    }
+
+    /// Subscribe to every pool of `pools` and forward every one of their price updates on
+    /// `update_tx`, `None` once a pool's updates stop
+    pub async fn forward_pool_price_updates(
+        &self,
+        pools: &[AssetPair],
+        update_tx: mpsc::UnboundedSender<(AssetPair, Option<PriceUpdate>)>,
+    ) {
+        for asset_pair in pools.iter() {
+            self.subscribe_pool_price_updates(asset_pair);
+            let Some(mut price_update_rx) = self.get_pool_price_update_stream(asset_pair).await
+            else {
+                tracing::error!(pair = %asset_pair, "no price updates");
+
+                continue;
+            };
+
+            let asset_pair = asset_pair.clone();
+            let update_tx = update_tx.clone();
+            tokio::spawn(async move {
+                while let Some(update) = price_update_rx.recv().await {
+                    if update_tx.send((asset_pair.clone(), Some(update))).is_err() {
+                        return;
+                    }
+                }
+                let _ = update_tx.send((asset_pair, None));
+            });
+        }
     }
 
     pub async fn get_latest_pool_price(&self, asset_pair: &AssetPair) -> Option<PriceUpdate> {
@@ -73,19 +163,56 @@ impl PoolInfoProviderHandle {
             },
         );
 
-        rx.await.unwrap()
+        // the provider has shut down if the request goes unanswered
+        rx.await.ok().flatten()
     }
 
     pub async fn get_pool_liquidity(&self, asset_pair: &AssetPair) -> Option<Liquidity> {
+        self.request_pool_liquidity(asset_pair, None).await
+    }
+
+    /// Liquidity of the pool as of the block with hash `block_hash`
+    pub async fn get_pool_liquidity_at(
+        &self,
+        asset_pair: &AssetPair,
+        block_hash: &str,
+    ) -> Option<Liquidity> {
+        self.request_pool_liquidity(asset_pair, Some(block_hash.to_string()))
+            .await
+    }
+
+    /// Configuration of the pool, `None` if the node doesn't serve it
+    pub async fn get_pool_info(&self, asset_pair: &AssetPair) -> Option<PoolInfo> {
+        let (tx, rx) = oneshot::channel();
+
+        let _ =
+            self.pool_info_provider_handle_tx
+                .send(PoolInfoProviderHandleMessage::GetPoolInfo {
+                    asset_pair: asset_pair.clone(),
+                    tx,
+                });
+
+        // the provider has shut down if the request goes unanswered
+        rx.await.ok().flatten()
+    }
+
+    async fn request_pool_liquidity(
+        &self,
+        asset_pair: &AssetPair,
+        at: Option<String>,
+    ) -> Option<Liquidity> {
         let (tx, rx) = oneshot::channel();
 
         let _ =
             self.pool_info_provider_handle_tx
                 .send(PoolInfoProviderHandleMessage::GetLiquidity {
                     asset_pair: asset_pair.clone(),
+                    at,
                     tx,
+                    span: tracing::Span::current(),
                 });
 
-        rx.await.unwrap()
+        // the provider has shut down if the request goes unanswered
+        rx.await.ok().flatten()
     }
 }
@@ -0,0 +1,99 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::time::sleep;
+
+/// Maximum rate of REST requests to a node
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Sustained requests per second
+    pub requests_per_sec: f64,
+    /// Requests which may be made at once after a quiet period
+    pub burst: u32,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Available requests, negative when requests are queued waiting for the bucket to refill
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket limiting the rate of requests to a node. Requests are let through in the order they
+/// reserve a slot. Cheap to clone, all clones share the same bucket.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    rate_limit: RateLimit,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_limit: RateLimit) -> Self {
+        RateLimiter {
+            rate_limit,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: rate_limit.burst.max(1) as f64,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Reserve a request slot at `now`, returning how long to wait before making the request
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let refill = now
+            .saturating_duration_since(bucket.refilled_at)
+            .as_secs_f64()
+            * self.rate_limit.requests_per_sec;
+        bucket.tokens = (bucket.tokens + refill).min(self.rate_limit.burst.max(1) as f64);
+        bucket.refilled_at = now;
+
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rate_limit.requests_per_sec)
+        }
+    }
+
+    /// Wait until a request may be made, true if it had to wait
+    pub async fn acquire(&self) -> bool {
+        let delay = self.reserve(Instant::now());
+        if delay.is_zero() {
+            return false;
+        }
+
+        sleep(delay).await;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{RateLimit, RateLimiter};
+
+    #[test]
+    fn test_burst_then_sustained_rate() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_sec: 10.0,
+            burst: 2,
+        });
+        let now = Instant::now();
+
+        assert_eq!(Duration::ZERO, limiter.reserve(now));
+        assert_eq!(Duration::ZERO, limiter.reserve(now));
+        // queued requests are spaced out at the sustained rate
+        assert_eq!(Duration::from_millis(100), limiter.reserve(now));
+        assert_eq!(Duration::from_millis(200), limiter.reserve(now));
+
+        // the bucket refills up to the burst only
+        let later = now + Duration::from_secs(10);
+        assert_eq!(Duration::ZERO, limiter.reserve(later));
+        assert_eq!(Duration::ZERO, limiter.reserve(later));
+        assert_eq!(Duration::from_millis(100), limiter.reserve(later));
+    }
+}
@@ -0,0 +1,276 @@
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+
+use crate::model::{
+    asset_pair::AssetPair,
+    order_book::OrderBook,
+    swap::{simulate_swap, SwapFees, SwapLeg},
+};
+
+/// Every pool is quoted against this asset, swaps between other assets route through it
+pub const HUB_ASSET: &str = "USDC";
+
+/// Quote of a swap through one or two pools, amounts in whole units and rates in `to` per `from`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteQuote {
+    pub from: String,
+    pub to: String,
+    pub amount_in: f64,
+    pub amount_out: f64,
+    /// Swap through each pool in order, the output of a leg is the input of the next
+    pub legs: Vec<SwapLeg>,
+    /// Rate implied by the mid price of every pool
+    pub mid_rate: f64,
+    /// Average rate of the whole swap including fees, `None` if nothing was filled or a later leg
+    /// could only swap part of the previous leg's output
+    pub avg_rate: Option<f64>,
+    /// How much worse `avg_rate` is than `mid_rate`, in basis points
+    pub price_impact_bps: Option<f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+    /// Swapping an asset for itself
+    SameAsset(String),
+    /// No book for a pool on the route
+    NoBook(AssetPair),
+    /// Decimals unknown for an asset on the route
+    UnknownAsset(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteError::SameAsset(asset) => write!(f, "can't swap {} for itself", asset),
+            RouteError::NoBook(asset_pair) => write!(f, "no book for {}", asset_pair),
+            RouteError::UnknownAsset(asset) => write!(f, "unknown asset {}", asset),
+        }
+    }
+}
+
+/// Quotes swaps between any two assets from the latest book of each pool, routing swaps between
+/// two non hub assets through both of their pools as the chain does
+pub struct Router {
+    books: HashMap<AssetPair, OrderBook>,
+    /// Fee per pool in hundredths of a pip, charged on limit and range order fills alike
+    pool_fees: HashMap<AssetPair, u32>,
+    /// Fee of pools without their own or a known pool configuration, in hundredths of a pip
+    default_fee: u32,
+}
+
+impl Router {
+    pub fn new(default_fee_hundredth_pips: u32) -> Self {
+        Router {
+            books: HashMap::new(),
+            pool_fees: HashMap::new(),
+            default_fee: default_fee_hundredth_pips,
+        }
+    }
+
+    /// Charge `fee_hundredth_pips` for swaps through `asset_pair` instead of the fees of its pool
+    /// configuration or the default fee
+    pub fn with_pool_fee(mut self, asset_pair: &AssetPair, fee_hundredth_pips: u32) -> Self {
+        self.pool_fees
+            .insert(asset_pair.clone(), fee_hundredth_pips);
+        self
+    }
+
+    /// Quote off `ob` for its pool from now on
+    pub fn update_book(&mut self, ob: OrderBook) {
+        self.books.insert(ob.asset_pair.clone(), ob);
+    }
+
+    pub fn remove_book(&mut self, asset_pair: &AssetPair) {
+        self.books.remove(asset_pair);
+    }
+
+    /// Quote swapping `amount_in` of `from` for `to`
+    pub fn quote(&self, from: &str, to: &str, amount_in: f64) -> Result<RouteQuote, RouteError> {
+        if from == to {
+            return Err(RouteError::SameAsset(from.to_string()));
+        }
+
+        let mut legs: Vec<SwapLeg> = Vec::new();
+        for asset in [from, to] {
+            if asset == HUB_ASSET {
+                continue;
+            }
+
+            let asset_pair = AssetPair::new(asset.to_string(), HUB_ASSET.to_string());
+            let ob = self
+                .books
+                .get(&asset_pair)
+                .ok_or_else(|| RouteError::NoBook(asset_pair.clone()))?;
+            let (leg_from, leg_amount) = match legs.last() {
+                Some(previous) => (previous.to_asset.as_str(), previous.amount_out),
+                None if asset == from => (from, amount_in),
+                None => (HUB_ASSET, amount_in),
+            };
+            let fees = self
+                .pool_fees
+                .get(&asset_pair)
+                .copied()
+                .map(SwapFees::flat)
+                .or(ob.pool_info.as_ref().map(SwapFees::from))
+                .unwrap_or(SwapFees::flat(self.default_fee));
+            let leg = simulate_swap(ob, leg_from, leg_amount, fees)
+                .ok_or_else(|| RouteError::UnknownAsset(asset.to_string()))?;
+            legs.push(leg);
+        }
+
+        let mid_rate = legs.iter().map(|leg| leg.mid_rate).product();
+        let amount_out = legs.last().map_or(0.0, |leg| leg.amount_out);
+        // input filled by the first leg, it's only all converted end to end if every later leg filled
+        let filled = legs.first().map_or(0.0, |leg| leg.amount_in - leg.unfilled);
+        let routed = legs.iter().skip(1).all(|leg| leg.unfilled == 0.0);
+        let avg_rate = (amount_out > 0.0 && routed).then(|| amount_out / filled);
+
+        Ok(RouteQuote {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount_in,
+            amount_out,
+            legs,
+            mid_rate,
+            avg_rate,
+            price_impact_bps: avg_rate.map(|rate| (mid_rate - rate) / mid_rate * 10_000.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use crate::model::{
+        asset_pair::AssetPair,
+        liquidity::{LimitOrders, Liquidity, RangeOrder, Result},
+        order_book::OrderBook,
+        pool_info::PoolInfo,
+        swap::{simulate_swap, SwapFees},
+    };
+
+    use super::{RouteError, Router};
+
+    /// A book of `asset`-USDC with a single band of liquidity 1000 ticks either side of `tick`
+    fn book(asset: &str, tick: i32, liquidity: u128) -> OrderBook {
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![],
+                    bids: vec![],
+                },
+                range_orders: vec![
+                    RangeOrder {
+                        tick: tick - 1000,
+                        liquidity: format!("{:#x}", liquidity),
+                    },
+                    RangeOrder {
+                        tick: tick + 1000,
+                        liquidity: "0x0".to_string(),
+                    },
+                ],
+            },
+        };
+        let asset_pair = AssetPair::new(asset.to_string(), "USDC".to_string());
+
+        OrderBook::new(&asset_pair, liquidity, U256::zero(), tick)
+    }
+
+    #[test]
+    fn test_cross_pair_routes_through_usdc() {
+        // BTC around 30000 and ETH around 3000 USDC
+        let btc = book("BTC", 57040, 1_000_000_000_000);
+        let eth = book("ETH", -196_257, 1_000_000_000_000_000_000);
+        let mut router = Router::new(1000).with_pool_fee(&eth.asset_pair, 2000);
+        router.update_book(btc.clone());
+        router.update_book(eth.clone());
+
+        let quote = router.quote("BTC", "ETH", 0.5).unwrap();
+        assert_eq!(2, quote.legs.len());
+        assert_eq!("USDC", quote.legs[0].to_asset);
+        assert_eq!("USDC", quote.legs[1].from_asset);
+
+        // the USDC out of the first leg is swapped by the second, each at its pool's fee
+        let btc_leg = simulate_swap(&btc, "BTC", 0.5, SwapFees::flat(1000)).unwrap();
+        let eth_leg =
+            simulate_swap(&eth, "USDC", btc_leg.amount_out, SwapFees::flat(2000)).unwrap();
+        assert_eq!(btc_leg, quote.legs[0]);
+        assert_eq!(eth_leg, quote.legs[1]);
+        assert_eq!(eth_leg.amount_out, quote.amount_out);
+
+        let mid_rate = btc_leg.mid_price / eth_leg.mid_price;
+        assert!((quote.mid_rate / mid_rate - 1.0).abs() < 1e-12);
+        assert_eq!(Some(quote.amount_out / 0.5), quote.avg_rate);
+        // both fees, 30 bps, plus the impact of each leg
+        let impact = quote.price_impact_bps.unwrap();
+        let legs_impact = btc_leg.price_impact_bps.unwrap() + eth_leg.price_impact_bps.unwrap();
+        assert!(impact > 30.0, "impact {}", impact);
+        assert!((impact - legs_impact).abs() < 0.2, "impact {}", impact);
+    }
+
+    #[test]
+    fn test_single_leg_and_errors() {
+        let mut router = Router::new(0);
+        router.update_book(book("BTC", 57040, 1_000_000_000_000));
+
+        let quote = router.quote("USDC", "BTC", 10_000.0).unwrap();
+        assert_eq!(1, quote.legs.len());
+        assert_eq!("BTC", quote.legs[0].to_asset);
+        assert!((quote.mid_rate * quote.legs[0].mid_price - 1.0).abs() < 1e-12);
+
+        assert_eq!(
+            Err(RouteError::NoBook(AssetPair::new(
+                "ETH".to_string(),
+                "USDC".to_string()
+            ))),
+            router.quote("BTC", "ETH", 1.0)
+        );
+        assert_eq!(
+            Err(RouteError::SameAsset("BTC".to_string())),
+            router.quote("BTC", "BTC", 1.0)
+        );
+    }
+
+    #[test]
+    fn test_partially_filled_second_leg_has_no_rate() {
+        let mut router = Router::new(0);
+        router.update_book(book("BTC", 57040, 1_000_000_000_000));
+        router.update_book(book("ETH", -196_257, 1_000_000_000));
+
+        let quote = router.quote("BTC", "ETH", 0.5).unwrap();
+        assert_eq!(0.0, quote.legs[0].unfilled);
+        assert!(quote.legs[1].unfilled > 0.0);
+        assert!(quote.amount_out > 0.0);
+        assert_eq!(None, quote.avg_rate);
+        assert_eq!(None, quote.price_impact_bps);
+    }
+
+    #[test]
+    fn test_pool_info_fees() {
+        let pool_info = PoolInfo {
+            limit_order_fee_hundredth_pips: 100,
+            range_order_fee_hundredth_pips: 2000,
+        };
+        let btc = OrderBook {
+            pool_info: Some(pool_info.clone()),
+            ..book("BTC", 57040, 1_000_000_000_000)
+        };
+        let mut router = Router::new(1000);
+        router.update_book(btc.clone());
+
+        // each kind of order charges the pool's own fee, unless one is set for the pool
+        let quote = router.quote("BTC", "USDC", 0.5).unwrap();
+        let leg = simulate_swap(&btc, "BTC", 0.5, SwapFees::from(&pool_info)).unwrap();
+        assert_eq!(leg, quote.legs[0]);
+
+        let mut router = Router::new(1000).with_pool_fee(&btc.asset_pair, 500);
+        router.update_book(btc.clone());
+        let quote = router.quote("BTC", "USDC", 0.5).unwrap();
+        let leg = simulate_swap(&btc, "BTC", 0.5, SwapFees::flat(500)).unwrap();
+        assert_eq!(leg, quote.legs[0]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::model::order_book::OrderBook;
+
+/// Destination for published order books
+pub trait BookSink: Send {
+    fn publish(&mut self, ob: &OrderBook) -> io::Result<()>;
+
+    /// Flush any buffered books
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs every book at info level
+pub struct LogSink;
+
+impl BookSink for LogSink {
+    fn publish(&mut self, ob: &OrderBook) -> io::Result<()> {
+        tracing::info!(pair = %ob.asset_pair, book = ?ob, "received orderbook");
+
+        Ok(())
+    }
+}
+
+/// Appends every book to a file as a json line
+pub struct JsonLinesSink {
+    writer: BufWriter<File>,
+}
+
+impl JsonLinesSink {
+    /// Create the sink, appending to the file at `path` if it exists
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+
+        Ok(JsonLinesSink {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl BookSink for JsonLinesSink {
+    fn publish(&mut self, ob: &OrderBook) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, ob)?;
+        writeln!(self.writer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use crate::model::{
+        asset_pair::AssetPair,
+        liquidity::{LimitOrders, Liquidity, RangeOrder, Result},
+        order_book::OrderBook,
+    };
+
+    use super::{BookSink, JsonLinesSink};
+
+    #[test]
+    fn test_json_lines_sink() {
+        let path = std::env::temp_dir().join(format!("books-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: Result {
+                limit_orders: LimitOrders {
+                    asks: vec![],
+                    bids: vec![],
+                },
+                range_orders: vec![
+                    RangeOrder {
+                        tick: -10,
+                        liquidity: "0x539".to_string(),
+                    },
+                    RangeOrder {
+                        tick: 10,
+                        liquidity: "0x0".to_string(),
+                    },
+                ],
+            },
+        };
+        let asset_pair = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let ob = OrderBook::new(&asset_pair, liquidity, U256::from(1), 0);
+
+        let mut sink = JsonLinesSink::create(&path).unwrap();
+        sink.publish(&ob).unwrap();
+        sink.publish(&ob).unwrap();
+        sink.flush().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("BTC-USDC", lines[0]["asset_pair"]);
+        assert_eq!("0x539", lines[0]["range_orders"][0]["liquidity"]);
+        assert_eq!(-10, lines[0]["range_orders"][0]["start_tick"]);
+    }
+}
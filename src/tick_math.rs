@@ -0,0 +1,248 @@
+use std::fmt;
+
+use primitive_types::{U256, U512};
+
+use crate::{
+    model::{
+        asset_pair::AssetPair,
+        common::{SqrtPriceQ64F96, Tick},
+    },
+    util::{asset_decimals, u256_to_f64},
+};
+
+pub const MIN_TICK: Tick = -887272;
+pub const MAX_TICK: Tick = 887272;
+/// Sqrt price at `MIN_TICK`
+pub const MIN_SQRT_PRICE: SqrtPriceQ64F96 = U256([4295128739, 0, 0, 0]);
+/// Sqrt price at `MAX_TICK`
+pub const MAX_SQRT_PRICE: SqrtPriceQ64F96 =
+    U256([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+
+/// Factors of 1/sqrt(1.0001)^(2^i) as Q128.128 numbers, for bits 1 to 19 of the absolute tick
+const RATIO_FACTORS: [u128; 19] = [
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+    0x48a170391f7dc42444e8fa2,
+];
+
+/// Exact sqrt price at `tick` as the chain computes it, the `getSqrtRatioAtTick` algorithm of
+/// Uniswap V3. Rounded up, so the tick of the result is `tick`. Ticks outside
+/// `MIN_TICK..=MAX_TICK` are clamped to it.
+pub fn sqrt_price_at_tick(tick: Tick) -> SqrtPriceQ64F96 {
+    let tick = tick.clamp(MIN_TICK, MAX_TICK);
+    let abs_tick = tick.unsigned_abs();
+
+    // 1/sqrt(1.0001)^abs_tick as a Q128.128 number
+    let mut ratio = if abs_tick & 1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001_u128)
+    } else {
+        U256::one() << 128
+    };
+    for (bit, factor) in RATIO_FACTORS.iter().enumerate() {
+        if abs_tick & (2 << bit) != 0 {
+            ratio = (ratio * U256::from(*factor)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Q128.128 to Q64.96, rounding up
+    let round_up = if (ratio & U256::from(u32::MAX)).is_zero() {
+        U256::zero()
+    } else {
+        U256::one()
+    };
+    (ratio >> 32) + round_up
+}
+
+/// Square root of the raw price at `tick` as an `f64`, from the exact sqrt price
+pub fn raw_sqrt_price_at_tick(tick: Tick) -> f64 {
+    u256_to_f64(sqrt_price_at_tick(tick)) / 2_f64.powi(96)
+}
+
+/// Greatest tick whose sqrt price is at most `sqrt_price`, the tick the price is in. Clamped to
+/// `MIN_TICK..=MAX_TICK`.
+pub fn tick_at_sqrt_price(sqrt_price: SqrtPriceQ64F96) -> Tick {
+    let (mut lo, mut hi) = (MIN_TICK, MAX_TICK);
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if sqrt_price_at_tick(mid) <= sqrt_price {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    lo
+}
+
+/// An exact price in whole units of the quote asset per whole unit of the base asset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Price {
+    pub numerator: U512,
+    pub denominator: U512,
+}
+
+impl Price {
+    /// Price of a pool at `sqrt_price`, adjusted for the decimals of `asset_pair`. `None` if the
+    /// decimals of either asset are unknown.
+    pub fn at_sqrt_price(sqrt_price: SqrtPriceQ64F96, asset_pair: &AssetPair) -> Option<Self> {
+        let base_decimals = asset_decimals(&asset_pair.from)?;
+        let quote_decimals = asset_decimals(&asset_pair.to)?;
+        let sqrt_price = U512::from(sqrt_price);
+
+        Some(Price {
+            numerator: sqrt_price * sqrt_price * U512::exp10(base_decimals as usize),
+            denominator: (U512::one() << 192) * U512::exp10(quote_decimals as usize),
+        })
+    }
+
+    /// Price at `tick`, see `at_sqrt_price`
+    pub fn at_tick(tick: Tick, asset_pair: &AssetPair) -> Option<Self> {
+        Price::at_sqrt_price(sqrt_price_at_tick(tick), asset_pair)
+    }
+
+    /// The nearest `f64`, for convenience where exactness isn't needed
+    pub fn to_f64(&self) -> f64 {
+        u512_to_f64(self.numerator) / u512_to_f64(self.denominator)
+    }
+
+    /// Decimal representation with `places` decimal places, rounded down
+    pub fn to_decimal_string(&self, places: usize) -> String {
+        let integer = self.numerator / self.denominator;
+        if places == 0 {
+            return integer.to_string();
+        }
+        let fraction = (self.numerator % self.denominator) * U512::exp10(places) / self.denominator;
+
+        format!(
+            "{}.{:0>places$}",
+            integer,
+            fraction.to_string(),
+            places = places
+        )
+    }
+}
+
+impl fmt::Display for Price {
+    /// 18 decimal places, rounded down
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_decimal_string(18))
+    }
+}
+
+fn u512_to_f64(value: U512) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2_f64.powi(64) + *limb as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+    use proptest::prelude::*;
+
+    use crate::model::asset_pair::AssetPair;
+
+    use super::{
+        sqrt_price_at_tick, tick_at_sqrt_price, Price, MAX_SQRT_PRICE, MAX_TICK, MIN_SQRT_PRICE,
+        MIN_TICK,
+    };
+
+    #[test]
+    fn test_sqrt_price_at_tick() {
+        // the bounds of the chain's tick range
+        assert_eq!(MIN_SQRT_PRICE, sqrt_price_at_tick(MIN_TICK));
+        assert_eq!(MAX_SQRT_PRICE, sqrt_price_at_tick(MAX_TICK));
+        assert_eq!(
+            U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap(),
+            MAX_SQRT_PRICE
+        );
+        assert_eq!(U256::one() << 96, sqrt_price_at_tick(0));
+
+        // sqrt(1.0001^tick) * 2^96 rounded up, checked against 80 digit decimal arithmetic
+        assert_eq!(
+            U256::from_dec_str("1372225608461327131957077920121").unwrap(),
+            sqrt_price_at_tick(57040)
+        );
+        assert_eq!(
+            U256::from_dec_str("4339363644587371378270009").unwrap(),
+            sqrt_price_at_tick(-196_257)
+        );
+
+        // out of range ticks, ie. the tick above the book's tick at the top of the range
+        assert_eq!(MAX_SQRT_PRICE, sqrt_price_at_tick(MAX_TICK + 1));
+        assert_eq!(MIN_SQRT_PRICE, sqrt_price_at_tick(i32::MIN));
+    }
+
+    #[test]
+    fn test_tick_at_sqrt_price() {
+        for tick in [MIN_TICK, -196_257, -69082, -1, 0, 1, 57040, MAX_TICK - 1] {
+            let sqrt_price = sqrt_price_at_tick(tick);
+            assert_eq!(tick, tick_at_sqrt_price(sqrt_price));
+            // anywhere up to the next tick is still within it
+            let next = sqrt_price_at_tick(tick + 1);
+            assert_eq!(tick, tick_at_sqrt_price(next - 1));
+            assert_eq!((tick - 1).max(MIN_TICK), tick_at_sqrt_price(sqrt_price - 1));
+        }
+
+        // clamped to the range
+        assert_eq!(MIN_TICK, tick_at_sqrt_price(U256::zero()));
+        assert_eq!(MAX_TICK, tick_at_sqrt_price(U256::MAX));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_tick_sqrt_price_round_trip(tick in MIN_TICK..MAX_TICK, offset in any::<u128>()) {
+            let (lo, hi) = (sqrt_price_at_tick(tick), sqrt_price_at_tick(tick + 1));
+            prop_assert!(lo < hi);
+            prop_assert!(MIN_SQRT_PRICE <= lo && hi <= MAX_SQRT_PRICE);
+            prop_assert_eq!(tick, tick_at_sqrt_price(lo + U256::from(offset) % (hi - lo)));
+        }
+
+        #[test]
+        fn prop_price_between_tick_prices(tick in MIN_TICK..MAX_TICK) {
+            let asset_pair = AssetPair::new("ETH".to_string(), "USDC".to_string());
+            let price = Price::at_tick(tick, &asset_pair).unwrap();
+            let next = Price::at_tick(tick + 1, &asset_pair).unwrap();
+            // compare the fractions exactly, the denominators are the same
+            prop_assert_eq!(price.denominator, next.denominator);
+            prop_assert!(price.numerator < next.numerator);
+        }
+    }
+
+    #[test]
+    fn test_decimal_adjusted_price() {
+        let btc_usdc = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let price = Price::at_tick(57040, &btc_usdc).unwrap();
+        assert_eq!("29997.970399328936", price.to_decimal_string(12));
+        assert_eq!("29997", price.to_decimal_string(0));
+        assert!((price.to_f64() - 29997.970399328936).abs() < 1e-9);
+
+        let eth_usdc = AssetPair::new("ETH".to_string(), "USDC".to_string());
+        let price = Price::at_tick(-196_257, &eth_usdc).unwrap();
+        assert_eq!("2999.804309975331", price.to_decimal_string(12));
+
+        let xyz = AssetPair::new("XYZ".to_string(), "USDC".to_string());
+        assert_eq!(None, Price::at_tick(0, &xyz));
+    }
+}
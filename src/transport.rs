@@ -0,0 +1,5 @@
+pub mod journal;
+pub mod node_transport;
+pub mod recording_transport;
+pub mod replay_transport;
+pub mod websocket_transport;
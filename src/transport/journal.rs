@@ -0,0 +1,154 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::json_rpc::ChainflipJsonRpcRequest;
+
+/// A single recorded interaction with a node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// Text frame sent on the websocket
+    WsSent { message: String },
+    /// Text frame received on the websocket
+    WsReceived { message: String },
+    /// REST request and the response body returned for it
+    Rest {
+        request: ChainflipJsonRpcRequest,
+        response: String,
+    },
+}
+
+/// A `JournalEvent` stamped with the time since recording started
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Microseconds since the journal was opened
+    pub elapsed_us: u64,
+    /// Wall clock time of the event in microseconds since the unix epoch, missing from journals
+    /// recorded before it was added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_us: Option<u64>,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+impl JournalEntry {
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.elapsed_us)
+    }
+}
+
+/// Appends `JournalEntry`s to a file as json lines. Cheap to clone, all clones write to the same file.
+#[derive(Clone)]
+pub struct JournalWriter {
+    /// Time the journal was opened, entries are stamped relative to this
+    started_at: Instant,
+    /// Output file
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl JournalWriter {
+    /// Create (or truncate) the journal at `path`
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+
+        Ok(JournalWriter {
+            started_at: Instant::now(),
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    /// Append an event to the journal
+    pub fn record(&self, event: JournalEvent) {
+        let entry = JournalEntry {
+            elapsed_us: self.started_at.elapsed().as_micros() as u64,
+            unix_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since_epoch| since_epoch.as_micros() as u64),
+            event,
+        };
+
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!(error = ?e, "error serialising journal entry");
+
+                return;
+            }
+        };
+
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            tracing::error!(error = ?e, "error writing journal entry");
+        }
+    }
+
+    /// Flush the journal through to disk
+    pub fn flush(&self) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    }
+}
+
+/// Read every entry of the journal at `path`
+pub fn read_journal(path: &Path) -> io::Result<Vec<JournalEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::model::json_rpc::ChainflipJsonRpcRequest;
+
+    use super::{read_journal, JournalEvent, JournalWriter};
+
+    #[test]
+    fn test_journal_round_trip() {
+        let path = std::env::temp_dir().join(format!("journal-{}.jsonl", std::process::id()));
+
+        let writer = JournalWriter::create(&path).unwrap();
+        writer.record(JournalEvent::WsSent {
+            message: "subscribe".to_string(),
+        });
+        writer.record(JournalEvent::Rest {
+            request: ChainflipJsonRpcRequest::new("1", "cf_pool_liquidity", HashMap::new()),
+            response: "{}".to_string(),
+        });
+
+        let entries = read_journal(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, entries.len());
+        assert_eq!(
+            JournalEvent::WsSent {
+                message: "subscribe".to_string()
+            },
+            entries[0].event
+        );
+        assert!(entries[0].elapsed_us <= entries[1].elapsed_us);
+        assert!(entries[0].unix_us.is_some());
+    }
+}
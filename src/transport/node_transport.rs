@@ -0,0 +1,92 @@
+use std::{error, fmt, io};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio_tungstenite::tungstenite;
+
+use crate::model::json_rpc::ChainflipJsonRpcRequest;
+
+/// Errors surfaced by a `NodeTransport`
+#[derive(Debug)]
+pub enum TransportError {
+    /// Websocket connection or framing error, boxed as it dwarfs the other variants
+    Websocket(Box<tungstenite::Error>),
+    /// REST request error
+    Http(reqwest::Error),
+    /// Journal read / write error
+    Io(io::Error),
+    /// Replay source could not satisfy a request
+    Replay(String),
+    /// An operation did not complete in time
+    Timeout(String),
+    /// Certificates or keys could not be loaded
+    Tls(String),
+    /// The endpoint is misconfigured, ie. an invalid header
+    Config(String),
+    /// No node is connected to make the request to
+    NotConnected,
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportError::Websocket(e) => write!(f, "websocket error: {}", e),
+            TransportError::Http(e) => write!(f, "http error: {}", e),
+            TransportError::Io(e) => write!(f, "io error: {}", e),
+            TransportError::Replay(e) => write!(f, "replay error: {}", e),
+            TransportError::Timeout(e) => write!(f, "timed out {}", e),
+            TransportError::Tls(e) => write!(f, "tls error: {}", e),
+            TransportError::Config(e) => write!(f, "invalid endpoint: {}", e),
+            TransportError::NotConnected => write!(f, "no node connected"),
+        }
+    }
+}
+
+impl error::Error for TransportError {}
+
+impl From<tungstenite::Error> for TransportError {
+    fn from(e: tungstenite::Error) -> Self {
+        TransportError::Websocket(Box::new(e))
+    }
+}
+
+impl From<reqwest::Error> for TransportError {
+    /// The url is dropped, it may carry an API key
+    fn from(e: reqwest::Error) -> Self {
+        TransportError::Http(e.without_url())
+    }
+}
+
+impl From<io::Error> for TransportError {
+    fn from(e: io::Error) -> Self {
+        TransportError::Io(e)
+    }
+}
+
+/// The connection(s) a `PoolInfoProvider` uses to talk to a node.
+///
+/// A transport carries two kinds of traffic: a subscription stream of text frames (the websocket)
+/// and one-shot JSON-RPC requests (REST). Implemented by the live node connection as well as by
+/// the journal replayer, so the rest of the pipeline cannot tell the two apart.
+pub trait NodeTransport: Send {
+    /// Send a text frame on the subscription stream
+    fn send(&mut self, message: String) -> BoxFuture<'_, Result<(), TransportError>>;
+
+    /// Receive the next text frame from the subscription stream, `None` once the stream has closed.
+    ///
+    /// Must be cancel safe, it is polled from within `tokio::select!`.
+    fn recv(&mut self) -> BoxFuture<'_, Option<Result<String, TransportError>>>;
+
+    /// Issue a JSON-RPC request and return the raw response body.
+    ///
+    /// The returned future does not borrow the transport so requests can be awaited concurrently.
+    fn request(
+        &self,
+        request: ChainflipJsonRpcRequest,
+    ) -> BoxFuture<'static, Result<String, TransportError>>;
+
+    /// Close the subscription stream politely and flush anything buffered. The transport is not
+    /// used afterwards.
+    fn close(&mut self) -> BoxFuture<'_, Result<(), TransportError>> {
+        async { Ok(()) }.boxed()
+    }
+}
@@ -0,0 +1,81 @@
+use futures::{future::BoxFuture, FutureExt};
+
+use crate::model::json_rpc::ChainflipJsonRpcRequest;
+
+use super::{
+    journal::{JournalEvent, JournalWriter},
+    node_transport::{NodeTransport, TransportError},
+};
+
+/// Wraps another `NodeTransport` and records all traffic through it to a journal which can later
+/// be fed to a `ReplayTransport`.
+pub struct RecordingTransport {
+    /// Transport doing the actual communication
+    inner: Box<dyn NodeTransport>,
+    /// Journal traffic is recorded to
+    journal: JournalWriter,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn NodeTransport>, journal: JournalWriter) -> Self {
+        RecordingTransport { inner, journal }
+    }
+}
+
+impl NodeTransport for RecordingTransport {
+    fn send(&mut self, message: String) -> BoxFuture<'_, Result<(), TransportError>> {
+        async move {
+            self.journal.record(JournalEvent::WsSent {
+                message: message.clone(),
+            });
+
+            self.inner.send(message).await
+        }
+        .boxed()
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Option<Result<String, TransportError>>> {
+        async move {
+            let message = self.inner.recv().await;
+
+            if let Some(Ok(message)) = &message {
+                self.journal.record(JournalEvent::WsReceived {
+                    message: message.clone(),
+                });
+            }
+
+            message
+        }
+        .boxed()
+    }
+
+    fn request(
+        &self,
+        request: ChainflipJsonRpcRequest,
+    ) -> BoxFuture<'static, Result<String, TransportError>> {
+        let journal = self.journal.clone();
+        let response = self.inner.request(request.clone());
+
+        async move {
+            let response = response.await?;
+
+            journal.record(JournalEvent::Rest {
+                request,
+                response: response.clone(),
+            });
+
+            Ok(response)
+        }
+        .boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<(), TransportError>> {
+        async move {
+            let closed = self.inner.close().await;
+            self.journal.flush()?;
+
+            closed
+        }
+        .boxed()
+    }
+}
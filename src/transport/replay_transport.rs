@@ -0,0 +1,358 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::{json, Value};
+use tokio::time::{sleep_until, Instant};
+
+use crate::model::json_rpc::{ChainflipJsonRpcRequest, JsonRpcResponse};
+
+use super::{
+    journal::{JournalEntry, JournalEvent},
+    node_transport::{NodeTransport, TransportError},
+};
+
+/// Rate at which recorded websocket frames are replayed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// Replay with the timing frames were originally received at
+    Original,
+    /// Replay `n` times faster than originally received
+    Accelerated(f64),
+    /// Replay frames back to back without waiting
+    AsFastAsPossible,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    /// Parse "max" as `AsFastAsPossible`, otherwise a speed multiplier such as "1", "10" or "2.5x"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if s == "max" {
+            return Ok(ReplaySpeed::AsFastAsPossible);
+        }
+
+        let multiplier: f64 = s
+            .trim_end_matches('x')
+            .parse()
+            .map_err(|_| format!("invalid replay speed: {:?}", s))?;
+
+        if !multiplier.is_finite() || multiplier <= 0.0 {
+            Err(format!("replay speed must be positive: {:?}", s))
+        } else if multiplier == 1.0 {
+            Ok(ReplaySpeed::Original)
+        } else {
+            Ok(ReplaySpeed::Accelerated(multiplier))
+        }
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplaySpeed::Original => write!(f, "1x"),
+            ReplaySpeed::Accelerated(multiplier) => write!(f, "{}x", multiplier),
+            ReplaySpeed::AsFastAsPossible => write!(f, "max"),
+        }
+    }
+}
+
+/// Extract the params of a `cf_subscribe_pool_price` request and the id it was sent with
+fn parse_subscribe_request(message: &str) -> Option<(String, String)> {
+    let value: Value = serde_json::from_str(message).ok()?;
+    if value.get("method")?.as_str()? != "cf_subscribe_pool_price" {
+        return None;
+    }
+
+    let id = value.get("id")?.as_str()?.to_string();
+    Some((id, value.get("params")?.to_string()))
+}
+
+/// A `NodeTransport` which plays back a journal recorded by a `RecordingTransport`.
+///
+/// Subscription requests are answered with the subscription id recorded for the same params, so
+/// the recorded price updates are attributed to the right asset pair. Recorded frames are then
+/// released relative to the first subscription request, at the configured `ReplaySpeed`. REST
+/// requests are answered, in recorded order, with the responses recorded for the same method and params.
+pub struct ReplayTransport {
+    /// Replay rate
+    speed: ReplaySpeed,
+    /// Remaining websocket frames to replay
+    inbound: VecDeque<JournalEntry>,
+    /// Map of subscription params to the subscription ids the node assigned them when recording
+    recorded_subscriptions: HashMap<String, VecDeque<String>>,
+    /// Subscription responses waiting to be returned ahead of recorded frames
+    subscription_responses: VecDeque<String>,
+    /// Time within the journal at which the first subscription request was sent
+    journal_origin: Duration,
+    /// Time replay started, set on the first subscription request
+    replay_origin: Option<Instant>,
+    /// Map of request key to remaining recorded REST responses
+    rest_responses: Arc<Mutex<HashMap<String, VecDeque<String>>>>,
+}
+
+impl ReplayTransport {
+    pub fn new(entries: Vec<JournalEntry>, speed: ReplaySpeed) -> Self {
+        // map request id of recorded subscription requests to their params
+        let mut subscribe_requests = HashMap::new();
+        let mut journal_origin = None;
+        for entry in entries.iter() {
+            if let JournalEvent::WsSent { message } = &entry.event {
+                if let Some((id, params)) = parse_subscribe_request(message) {
+                    journal_origin.get_or_insert(entry.elapsed());
+                    subscribe_requests.insert(id, params);
+                }
+            }
+        }
+
+        let mut inbound = VecDeque::new();
+        let mut recorded_subscriptions: HashMap<String, VecDeque<String>> = HashMap::new();
+        let mut rest_responses: HashMap<String, VecDeque<String>> = HashMap::new();
+
+        for entry in entries.into_iter() {
+            match &entry.event {
+                JournalEvent::WsSent { .. } => {}
+                JournalEvent::WsReceived { message } => {
+                    // subscription responses are answered on demand rather than replayed
+                    let subscription = serde_json::from_str::<JsonRpcResponse>(message)
+                        .ok()
+                        .and_then(|resp| Some((subscribe_requests.get(&resp.id)?, resp.result)));
+
+                    match subscription {
+                        Some((params, subscription_id)) => recorded_subscriptions
+                            .entry(params.clone())
+                            .or_default()
+                            .push_back(subscription_id),
+                        None => inbound.push_back(entry),
+                    }
+                }
+                JournalEvent::Rest { request, response } => rest_responses
+                    .entry(request.key())
+                    .or_default()
+                    .push_back(response.clone()),
+            }
+        }
+
+        ReplayTransport {
+            speed,
+            inbound,
+            recorded_subscriptions,
+            subscription_responses: VecDeque::new(),
+            journal_origin: journal_origin.unwrap_or_default(),
+            replay_origin: None,
+            rest_responses: Arc::new(Mutex::new(rest_responses)),
+        }
+    }
+
+    /// Instant at which a frame recorded at `elapsed` should be released, `None` if it is due immediately
+    fn deadline(&self, elapsed: Duration) -> Option<Instant> {
+        let replay_origin = self.replay_origin?;
+        let offset = elapsed.saturating_sub(self.journal_origin);
+
+        match self.speed {
+            ReplaySpeed::Original => Some(replay_origin + offset),
+            ReplaySpeed::Accelerated(multiplier) => {
+                Some(replay_origin + offset.div_f64(multiplier))
+            }
+            ReplaySpeed::AsFastAsPossible => None,
+        }
+    }
+}
+
+impl NodeTransport for ReplayTransport {
+    fn send(&mut self, message: String) -> BoxFuture<'_, Result<(), TransportError>> {
+        async move {
+            let Some((id, params)) = parse_subscribe_request(&message) else {
+                tracing::debug!(sent = %message, "replay ignoring sent message");
+
+                return Ok(());
+            };
+
+            self.replay_origin.get_or_insert_with(Instant::now);
+
+            match self
+                .recorded_subscriptions
+                .get_mut(&params)
+                .and_then(|ids| ids.pop_front())
+            {
+                Some(subscription_id) => {
+                    let response = json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "result": subscription_id,
+                    });
+                    self.subscription_responses.push_back(response.to_string());
+                }
+                None => tracing::warn!(%params, "no recorded subscription"),
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Option<Result<String, TransportError>>> {
+        async move {
+            if let Some(response) = self.subscription_responses.pop_front() {
+                return Some(Ok(response));
+            }
+
+            // nothing is released until the first subscription has been made
+            if self.replay_origin.is_none() {
+                futures::future::pending::<()>().await;
+            }
+
+            let elapsed = self.inbound.front()?.elapsed();
+            match self.deadline(elapsed) {
+                Some(deadline) => sleep_until(deadline).await,
+                None => tokio::task::yield_now().await,
+            }
+
+            match self.inbound.pop_front()?.event {
+                JournalEvent::WsReceived { message } => Some(Ok(message)),
+                _ => unreachable!("only received frames are replayed"),
+            }
+        }
+        .boxed()
+    }
+
+    fn request(
+        &self,
+        request: ChainflipJsonRpcRequest,
+    ) -> BoxFuture<'static, Result<String, TransportError>> {
+        let key = request.key();
+        let response = self
+            .rest_responses
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .and_then(|responses| responses.pop_front());
+
+        async move {
+            response
+                .ok_or_else(|| TransportError::Replay(format!("no recorded response for {}", key)))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::{
+        model::json_rpc::{ChainflipJsonRpcRequest, JsonRpcResponse},
+        transport::{
+            journal::{JournalEntry, JournalEvent},
+            node_transport::NodeTransport,
+        },
+    };
+
+    use super::{ReplaySpeed, ReplayTransport};
+
+    fn subscribe_message(id: &str) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "cf_subscribe_pool_price",
+            "params": {"from_asset": "BTC", "to_asset": "USDC"}
+        })
+        .to_string()
+    }
+
+    fn liquidity_request() -> ChainflipJsonRpcRequest {
+        let params = HashMap::from([
+            ("base_asset".to_string(), "BTC".to_string()),
+            ("quote_asset".to_string(), "USDC".to_string()),
+        ]);
+        ChainflipJsonRpcRequest::new("1", "cf_pool_liquidity", params)
+    }
+
+    fn journal() -> Vec<JournalEntry> {
+        let events = vec![
+            JournalEvent::WsSent {
+                message: subscribe_message("42"),
+            },
+            JournalEvent::WsReceived {
+                message: json!({"jsonrpc": "2.0", "id": "42", "result": "sub-1"}).to_string(),
+            },
+            JournalEvent::WsReceived {
+                message: "price-1".to_string(),
+            },
+            JournalEvent::Rest {
+                request: liquidity_request(),
+                response: "liquidity-1".to_string(),
+            },
+            JournalEvent::WsReceived {
+                message: "price-2".to_string(),
+            },
+        ];
+
+        events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| JournalEntry {
+                elapsed_us: i as u64 * 1_000,
+                unix_us: None,
+                event,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_replay_speed() {
+        assert_eq!(Ok(ReplaySpeed::Original), "1".parse());
+        assert_eq!(Ok(ReplaySpeed::Accelerated(10.0)), "10x".parse());
+        assert_eq!(Ok(ReplaySpeed::AsFastAsPossible), "max".parse());
+        assert!("-1".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay_rewrites_subscription_response() {
+        let mut transport = ReplayTransport::new(journal(), ReplaySpeed::AsFastAsPossible);
+
+        transport.send(subscribe_message("7")).await.unwrap();
+
+        let response: JsonRpcResponse =
+            serde_json::from_str(&transport.recv().await.unwrap().unwrap()).unwrap();
+        assert_eq!("7", response.id);
+        assert_eq!("sub-1", response.result);
+
+        assert_eq!("price-1", transport.recv().await.unwrap().unwrap());
+        assert_eq!("price-2", transport.recv().await.unwrap().unwrap());
+        assert!(transport.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_replay_rest_responses() {
+        let transport = ReplayTransport::new(journal(), ReplaySpeed::AsFastAsPossible);
+
+        let response = transport.request(liquidity_request()).await.unwrap();
+        assert_eq!("liquidity-1", response);
+
+        assert!(transport.request(liquidity_request()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_original_timing() {
+        let mut transport = ReplayTransport::new(journal(), ReplaySpeed::Original);
+        transport.send(subscribe_message("7")).await.unwrap();
+        transport.recv().await.unwrap().unwrap();
+
+        let start = tokio::time::Instant::now();
+        assert_eq!("price-2", {
+            transport.recv().await.unwrap().unwrap();
+            transport.recv().await.unwrap().unwrap()
+        });
+        assert_eq!(4, start.elapsed().as_millis());
+    }
+}
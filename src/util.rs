@@ -2,7 +2,10 @@ use lazy_static::lazy_static;
 use primitive_types::U256;
 use std::collections::HashMap;
 
-use crate::model::{asset_pair::AssetPair, common::Tick};
+use crate::{
+    model::{asset_pair::AssetPair, common::Tick, order_book::Side},
+    tick_math::{sqrt_price_at_tick, MAX_TICK, MIN_TICK},
+};
 
 lazy_static! {
     static ref DECIMALS: HashMap<String, u32> = [
@@ -17,12 +20,73 @@ lazy_static! {
     .collect();
 }
 
-/// Convert `Tick` into a floating point representaiton of price
+/// Whether decimals are known for `asset`, prices can only be derived for pairs of known assets
+pub fn is_known_asset(asset: &str) -> bool {
+    DECIMALS.contains_key(asset)
+}
+
+/// Number of decimals of `asset`'s smallest unit, if known
+pub fn asset_decimals(asset: &str) -> Option<u32> {
+    DECIMALS.get(asset).copied()
+}
+
+/// Convert `Tick` into a floating point representation of price, from the exact sqrt price at the
+/// tick. `tick_math::Price` is exact.
 pub fn tick_to_price(tick: Tick, asset_pair: &AssetPair) -> f64 {
+    sqrt_price_to_price(sqrt_price_at_tick(tick), asset_pair)
+}
+
+/// Convert a Q64.96 sqrt price into a floating point representation of price, like `tick_to_price`
+pub fn sqrt_price_to_price(sqrt_price_x96: U256, asset_pair: &AssetPair) -> f64 {
     let decimals0 = *DECIMALS.get(&asset_pair.from).unwrap() as i32;
     let decimals1 = *DECIMALS.get(&asset_pair.to).unwrap() as i32;
 
-    1.0001_f64.powi(tick) / 10_f64.powf((decimals1 - decimals0) as f64)
+    (u256_to_f64(sqrt_price_x96) / 2_f64.powi(96)).powi(2) / 10_f64.powi(decimals1 - decimals0)
+}
+
+/// Price of a pool from the hex sqrt price and tick of a price update, the tick is used if the sqrt
+/// price is zero. `None` if the decimals of either asset are unknown or the sqrt price isn't hex.
+pub fn pool_price(sqrt_price: &str, tick: Tick, asset_pair: &AssetPair) -> Option<f64> {
+    if !is_known_asset(&asset_pair.from) || !is_known_asset(&asset_pair.to) {
+        return None;
+    }
+
+    let sqrt_price_x96 = U256::from_str_radix(sqrt_price.trim_start_matches("0x"), 16).ok()?;
+    if sqrt_price_x96.is_zero() {
+        Some(tick_to_price(tick, asset_pair))
+    } else {
+        Some(sqrt_price_to_price(sqrt_price_x96, asset_pair))
+    }
+}
+
+/// Tick to place our own limit order of `side` at for a price no worse than `price`, in whole units
+/// of `asset_pair`'s quote asset: the highest tick at or below it for a bid, the lowest at or above
+/// it for an ask. Pools accept orders at any tick in range. `None` if there is no such tick or the
+/// decimals of either asset are unknown.
+pub fn limit_order_tick(asset_pair: &AssetPair, side: Side, price: f64) -> Option<Tick> {
+    if !price.is_finite() || price <= 0.0 {
+        return None;
+    }
+    if !is_known_asset(&asset_pair.from) || !is_known_asset(&asset_pair.to) {
+        return None;
+    }
+
+    // the greatest tick priced at or below `price`, estimated and then corrected exactly
+    let reference = tick_to_price(0, asset_pair);
+    let estimate = ((price / reference).ln() / 1.0001_f64.ln()).floor();
+    let mut tick = estimate.clamp(MIN_TICK as f64, MAX_TICK as f64) as Tick;
+    while tick < MAX_TICK && tick_to_price(tick + 1, asset_pair) <= price {
+        tick += 1;
+    }
+    while tick > MIN_TICK && tick_to_price(tick, asset_pair) > price {
+        tick -= 1;
+    }
+
+    match side {
+        Side::Buy => (tick_to_price(tick, asset_pair) <= price).then_some(tick),
+        Side::Sell if tick_to_price(tick, asset_pair) >= price => Some(tick),
+        Side::Sell => (tick < MAX_TICK).then_some(tick + 1),
+    }
 }
 
 /// Convert hex string ie. "0xC0FFEE" into a `U256` decimal representation
@@ -31,14 +95,76 @@ pub fn hex_string_to_u256(hex_string: &str) -> U256 {
     U256::from_str_radix(without_prefix, 16).unwrap()
 }
 
+/// Convert a `U256` into the nearest `f64`, losing precision beyond 53 bits
+pub fn u256_to_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2_f64.powi(64) + *limb as f64)
+}
+
 #[cfg(test)]
 mod tests {
     use float_cmp::approx_eq;
     use primitive_types::U256;
+    use proptest::prelude::*;
+
+    use crate::{
+        model::{asset_pair::AssetPair, order_book::Side},
+        tick_math::{sqrt_price_at_tick, Price, MAX_TICK, MIN_TICK},
+        util::hex_string_to_u256,
+    };
+
+    use super::{limit_order_tick, pool_price, sqrt_price_to_price, tick_to_price, u256_to_f64};
+
+    fn asset_pair() -> impl Strategy<Value = AssetPair> {
+        prop::sample::select(vec![
+            "BTC-USDC",
+            "ETH-USDC",
+            "DOT-USDC",
+            "FLIP-USDC",
+            "ETH-BTC",
+        ])
+        .prop_map(|pair| pair.parse().unwrap())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_tick_to_price_increases(tick in MIN_TICK..MAX_TICK, asset_pair in asset_pair()) {
+            prop_assert!(tick_to_price(tick + 1, &asset_pair) > tick_to_price(tick, &asset_pair));
+        }
 
-    use crate::{model::asset_pair::AssetPair, util::hex_string_to_u256};
+        #[test]
+        fn prop_inverse_pair_price(tick in MIN_TICK..=MAX_TICK, asset_pair in asset_pair()) {
+            // the inverse pool's price at the negated tick is the reciprocal, to the precision of
+            // the sqrt price, which has only 32 bits at the lowest tick
+            let price = tick_to_price(tick, &asset_pair);
+            let inverse = tick_to_price(-tick, &asset_pair.inverse());
+            prop_assert!((price * inverse - 1.0).abs() < 1e-8, "{} * {}", price, inverse);
+        }
 
-    use super::tick_to_price;
+        #[test]
+        fn prop_hex_string_round_trip(words in any::<[u64; 4]>()) {
+            let value = U256(words);
+            prop_assert_eq!(value, hex_string_to_u256(&format!("{:#x}", value)));
+            prop_assert_eq!(value, hex_string_to_u256(&format!("{:X}", value)));
+        }
+
+        #[test]
+        fn prop_sqrt_price_decoding(
+            tick in MIN_TICK..MAX_TICK,
+            offset in any::<u64>(),
+            asset_pair in asset_pair(),
+        ) {
+            // anywhere within the tick, against the exact price
+            let (lo, hi) = (sqrt_price_at_tick(tick), sqrt_price_at_tick(tick + 1));
+            let sqrt_price = lo + U256::from(offset) % (hi - lo);
+            let price = sqrt_price_to_price(sqrt_price, &asset_pair);
+            let exact = Price::at_sqrt_price(sqrt_price, &asset_pair).unwrap().to_f64();
+            prop_assert!((price / exact - 1.0).abs() < 1e-12, "{} vs {}", price, exact);
+        }
+    }
 
     #[test]
     fn test_tick_to_price_btc() {
@@ -50,9 +176,9 @@ mod tests {
         let price: f64 = tick_to_price(57040, &asset_pair);
         assert!(approx_eq!(
             f64,
-            29997.9703993,
+            29997.970399328936,
             price,
-            epsilon = 0.00000003,
+            epsilon = 1e-10,
             ulps = 2
         ));
     }
@@ -67,15 +193,73 @@ mod tests {
         let price = tick_to_price(-69082, &asset_pair);
         assert!(approx_eq!(
             f64,
-            9.99900670,
+            9.999006708899933,
             price,
-            epsilon = 0.00000003,
+            epsilon = 1e-14,
             ulps = 2
         ));
     }
 
+    #[test]
+    fn test_sqrt_price_to_price() {
+        let asset_pair = AssetPair {
+            from: "BTC".to_string(),
+            to: "USDC".to_string(),
+        };
+
+        // 2^96 is a raw price of 1
+        assert_eq!(100.0, sqrt_price_to_price(U256::one() << 96, &asset_pair));
+    }
+
+    #[test]
+    fn test_pool_price() {
+        let asset_pair: AssetPair = "BTC-USDC".parse().unwrap();
+
+        assert_eq!(
+            Some(100.0),
+            pool_price("0x1000000000000000000000000", 0, &asset_pair)
+        );
+        // no sqrt price, from the tick
+        assert_eq!(
+            Some(tick_to_price(57040, &asset_pair)),
+            pool_price("0x0", 57040, &asset_pair)
+        );
+        assert_eq!(None, pool_price("0xzz", 57040, &asset_pair));
+        assert_eq!(None, pool_price("0x0", 0, &"BTC-XYZ".parse().unwrap()));
+    }
+
     #[test]
     fn test_hex_string_to_u256() {
         assert_eq!(U256::from(1337), hex_string_to_u256("0x539"));
     }
+
+    #[test]
+    fn test_u256_to_f64() {
+        assert_eq!(1337.0, u256_to_f64(U256::from(1337)));
+        assert_eq!(2_f64.powi(96), u256_to_f64(U256::one() << 96));
+    }
+
+    #[test]
+    fn test_limit_order_tick() {
+        let btc_usdc = AssetPair::new("BTC".to_string(), "USDC".to_string());
+        let price = tick_to_price(57043, &btc_usdc) + 0.01;
+
+        // bids no higher and asks no lower than the price
+        assert_eq!(Some(57043), limit_order_tick(&btc_usdc, Side::Buy, price));
+        assert_eq!(Some(57044), limit_order_tick(&btc_usdc, Side::Sell, price));
+
+        // exactly at a tick's price
+        let price = tick_to_price(57040, &btc_usdc);
+        assert_eq!(Some(57040), limit_order_tick(&btc_usdc, Side::Buy, price));
+        assert_eq!(Some(57040), limit_order_tick(&btc_usdc, Side::Sell, price));
+
+        // beyond the tick range
+        let highest = tick_to_price(MAX_TICK, &btc_usdc);
+        assert_eq!(None, limit_order_tick(&btc_usdc, Side::Sell, highest * 2.0));
+        assert_eq!(
+            Some(MAX_TICK),
+            limit_order_tick(&btc_usdc, Side::Buy, highest * 2.0)
+        );
+        assert_eq!(None, limit_order_tick(&btc_usdc, Side::Buy, 0.0));
+    }
 }
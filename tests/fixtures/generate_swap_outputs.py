@@ -0,0 +1,201 @@
+#!/usr/bin/env python3
+"""Regenerate the outputs of swap_outputs.json from its inputs.
+
+Swaps are computed with exact integer pool maths: Q64.96 sqrt prices from getSqrtRatioAtTick,
+range liquidity with the Uniswap V3 amount formulas, limit orders filling at their tick's price
+before the range liquidity beyond it, each fill charged the fee of its kind of order on the input
+it uses, and every rounding in the pool's favour. This is the model
+the simulator implements, in exact arithmetic rather than f64. It is not captured from a node.
+
+    python3 tests/fixtures/generate_swap_outputs.py > tests/fixtures/swap_outputs.json
+"""
+
+import json
+import os
+import sys
+
+MIN_TICK = -887272
+MAX_TICK = 887272
+Q96 = 1 << 96
+HUNDREDTH_PIPS = 1_000_000
+
+RATIO_FACTORS = [
+    0xFFF97272373D413259A46990580E213A,
+    0xFFF2E50F5F656932EF12357CF3C7FDCC,
+    0xFFE5CACA7E10E4E61C3624EAA0941CD0,
+    0xFFCB9843D60F6159C9DB58835C926644,
+    0xFF973B41FA98C081472E6896DFB254C0,
+    0xFF2EA16466C96A3843EC78B326B52861,
+    0xFE5DEE046A99A2A811C461F1969C3053,
+    0xFCBE86C7900A88AEDCFFC83B479AA3A4,
+    0xF987A7253AC413176F2B074CF7815E54,
+    0xF3392B0822B70005940C7A398E4B70F3,
+    0xE7159475A2C29B7443B29C7FA6E889D9,
+    0xD097F3BDFD2022B8845AD8F792AA5825,
+    0xA9F746462D870FDF8A65DC1F90E061E5,
+    0x70D869A156D2A1B890BB3DF62BAF32F7,
+    0x31BE135F97D08FD981231505542FCFA6,
+    0x9AA508B5B7A84E1C677DE54F3E99BC9,
+    0x5D6AF8DEDB81196699C329225EE604,
+    0x2216E584F5FA1EA926041BEDFE98,
+    0x48A170391F7DC42444E8FA2,
+]
+
+
+def sqrt_price_at_tick(tick):
+    """getSqrtRatioAtTick, the Q64.96 sqrt price at `tick` rounded up"""
+    abs_tick = abs(tick)
+    ratio = 0xFFFCB933BD6FAD37AA2D162D1A594001 if abs_tick & 1 else 1 << 128
+    for bit, factor in enumerate(RATIO_FACTORS):
+        if abs_tick & (2 << bit):
+            ratio = (ratio * factor) >> 128
+    if tick > 0:
+        ratio = ((1 << 256) - 1) // ratio
+
+    return (ratio >> 32) + (1 if ratio & 0xFFFFFFFF else 0)
+
+
+def div_up(a, b):
+    return -(-a // b)
+
+
+class Input:
+    """Raw input of a swap left to trade"""
+
+    def __init__(self, amount):
+        self.remaining = amount
+
+    def fill(self, capacity, fee_rate):
+        """Trade up to `capacity` of input, charging `fee_rate` on top of it rounded up, returns
+        the input traded"""
+        cost = div_up(capacity * HUNDREDTH_PIPS, HUNDREDTH_PIPS - fee_rate)
+        if self.remaining >= cost:
+            self.remaining -= cost
+            return capacity
+
+        traded = self.remaining - div_up(self.remaining * fee_rate, HUNDREDTH_PIPS)
+        self.remaining = 0
+        return traded
+
+
+def bands(case):
+    """Range liquidity bands as (lower sqrt, upper sqrt, liquidity)"""
+    orders = case["range_orders"]
+    return [
+        (sqrt_price_at_tick(lo["tick"]), sqrt_price_at_tick(hi["tick"]), int(lo["liquidity"], 16))
+        for lo, hi in zip(orders, orders[1:])
+        if int(lo["liquidity"], 16) > 0
+    ]
+
+
+def walk_down(case, sqrt, swap_input):
+    """Sell raw base into bids, returns the quote received"""
+    bids = sorted(
+        (sqrt_price_at_tick(order["tick"]), int(order["amount"], 16))
+        for order in case["limit_orders"]["bids"]
+    )
+    liquidity_bands = bands(case)
+    out = 0
+
+    while swap_input.remaining > 0:
+        if bids and bids[-1][0] >= sqrt:
+            bid_sqrt, amount = bids.pop()
+            # base the bid's quote buys, rounded up, and the quote paid for it rounded down
+            base = div_up(amount * Q96 * Q96, bid_sqrt * bid_sqrt)
+            base = swap_input.fill(base, case["limit_order_fee_hundredth_pips"])
+            out += min(amount, base * bid_sqrt * bid_sqrt // (Q96 * Q96))
+            continue
+
+        bounds = [bound for band in liquidity_bands for bound in band[:2]]
+        bounds += [bid for bid, _ in bids[-1:]]
+        bounds = [bound for bound in bounds if bound < sqrt]
+        if not bounds:
+            break
+        next_sqrt = max(bounds)
+
+        liquidity = sum(l for lo, hi, l in liquidity_bands if lo <= next_sqrt and hi >= sqrt)
+        if liquidity > 0:
+            capacity = div_up(liquidity * Q96 * (sqrt - next_sqrt), sqrt * next_sqrt)
+            base = swap_input.fill(capacity, case["range_order_fee_hundredth_pips"])
+            if swap_input.remaining == 0:
+                end = div_up(liquidity * Q96 * sqrt, liquidity * Q96 + base * sqrt)
+                end = max(next_sqrt, end)
+                out += liquidity * (sqrt - end) // Q96
+                return out
+            out += liquidity * (sqrt - next_sqrt) // Q96
+        sqrt = next_sqrt
+
+    return out
+
+
+def walk_up(case, sqrt, swap_input):
+    """Buy raw base from asks with raw quote, returns the base received"""
+    asks = sorted(
+        (
+            (sqrt_price_at_tick(order["tick"]), int(order["amount"], 16))
+            for order in case["limit_orders"]["asks"]
+        ),
+        reverse=True,
+    )
+    liquidity_bands = bands(case)
+    out = 0
+
+    while swap_input.remaining > 0:
+        if asks and asks[-1][0] <= sqrt:
+            ask_sqrt, amount = asks.pop()
+            # quote the ask's base costs, rounded up, and the base bought with it rounded down
+            quote = div_up(amount * ask_sqrt * ask_sqrt, Q96 * Q96)
+            quote = swap_input.fill(quote, case["limit_order_fee_hundredth_pips"])
+            out += min(amount, quote * Q96 * Q96 // (ask_sqrt * ask_sqrt))
+            continue
+
+        bounds = [bound for band in liquidity_bands for bound in band[:2]]
+        bounds += [ask for ask, _ in asks[-1:]]
+        bounds = [bound for bound in bounds if bound > sqrt]
+        if not bounds:
+            break
+        next_sqrt = min(bounds)
+
+        liquidity = sum(l for lo, hi, l in liquidity_bands if lo <= sqrt and hi >= next_sqrt)
+        if liquidity > 0:
+            capacity = div_up(liquidity * (next_sqrt - sqrt), Q96)
+            quote = swap_input.fill(capacity, case["range_order_fee_hundredth_pips"])
+            if swap_input.remaining == 0:
+                end = min(next_sqrt, sqrt + quote * Q96 // liquidity)
+                out += liquidity * Q96 * (end - sqrt) // (sqrt * end)
+                return out
+            out += liquidity * Q96 * (next_sqrt - sqrt) // (sqrt * next_sqrt)
+        sqrt = next_sqrt
+
+    return out
+
+
+def swap(case):
+    """Output and unfilled input of the case's swap, in the smallest units"""
+    swap_input = Input(int(case["amount_in"], 16))
+    sqrt = int(case["sqrt_price"], 16)
+
+    base, _ = case["asset_pair"].split("-")
+    walk = walk_down if case["from_asset"] == base else walk_up
+    out = walk(case, sqrt, swap_input)
+
+    # input left over is not charged a fee
+    return out, swap_input.remaining
+
+
+def main():
+    path = os.path.join(os.path.dirname(__file__), "swap_outputs.json")
+    with open(path) as f:
+        outputs = json.load(f)
+
+    for case in outputs["cases"]:
+        amount_out, unfilled = swap(case)
+        case["amount_out"] = hex(amount_out)
+        case["unfilled"] = hex(unfilled)
+
+    json.dump(outputs, sys.stdout, indent=2)
+    print()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,71 @@
+mod integration {
+    mod book_manager;
+    mod builder_supervisor;
+    mod candles;
+    mod consistency;
+    mod cross_rates;
+    mod failover;
+    mod mock_node;
+    mod orderbook_builder;
+    mod pool_info_provider;
+    mod swap_outputs;
+
+    use std::time::Duration;
+
+    use chainflip_feedhandler_rs::{
+        health::HealthMonitor,
+        model::asset_pair::AssetPair,
+        pool_info_provider::{
+            pool_info_provider::PoolInfoProvider, pool_info_provider_handle::PoolInfoProviderHandle,
+        },
+        transport::websocket_transport::{NodeEndpoint, WebsocketTransport},
+    };
+    use tokio::task::JoinHandle;
+    use tokio_util::sync::CancellationToken;
+
+    use mock_node::MockNode;
+
+    pub fn btc_usdc() -> AssetPair {
+        AssetPair::new("BTC".to_string(), "USDC".to_string())
+    }
+
+    pub fn eth_usdc() -> AssetPair {
+        AssetPair::new("ETH".to_string(), "USDC".to_string())
+    }
+
+    /// Connect a `PoolInfoProvider` to `node` and run it
+    pub async fn start_provider(node: &MockNode) -> (PoolInfoProviderHandle, JoinHandle<()>) {
+        start_provider_with_stale_after(node, Duration::from_secs(60)).await
+    }
+
+    /// Connect a `PoolInfoProvider` considering pools stale after `stale_after` to `node` and run it
+    pub async fn start_provider_with_stale_after(
+        node: &MockNode,
+        stale_after: Duration,
+    ) -> (PoolInfoProviderHandle, JoinHandle<()>) {
+        start_provider_with_endpoint(
+            &NodeEndpoint::from_address(&node.hostname()),
+            stale_after,
+            CancellationToken::new(),
+        )
+        .await
+    }
+
+    /// Connect a `PoolInfoProvider` to `endpoint` and run it until `shutdown` is cancelled
+    pub async fn start_provider_with_endpoint(
+        endpoint: &NodeEndpoint,
+        stale_after: Duration,
+        shutdown: CancellationToken,
+    ) -> (PoolInfoProviderHandle, JoinHandle<()>) {
+        let transport = WebsocketTransport::connect(endpoint).await.unwrap();
+        let mut pool_info_provider =
+            PoolInfoProvider::new(Box::new(transport), HealthMonitor::new(stale_after));
+        let handle = pool_info_provider.get_handle();
+
+        let task = tokio::spawn(async move {
+            pool_info_provider.run(shutdown).await;
+        });
+
+        (handle, task)
+    }
+}
@@ -0,0 +1,124 @@
+use std::{collections::HashSet, time::Duration};
+
+use chainflip_feedhandler_rs::{book_manager::BookManager, orderbook_builder::TriggerMode};
+use tokio::{sync::mpsc, time::timeout};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    btc_usdc, eth_usdc,
+    mock_node::{sample_liquidity, MockNode},
+    start_provider,
+};
+
+#[tokio::test]
+async fn test_merges_books_and_removes_pools() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    node.set_liquidity(&eth_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    let shutdown = CancellationToken::new();
+    let mut book_manager = BookManager::new(handle, shutdown.clone());
+    for asset_pair in [btc_usdc(), eth_usdc()] {
+        assert!(book_manager.add_pool(
+            &asset_pair,
+            Duration::from_secs(3600),
+            TriggerMode::PriceChange,
+            Duration::ZERO
+        ));
+    }
+    assert!(!book_manager.add_pool(
+        &btc_usdc(),
+        Duration::from_secs(1),
+        TriggerMode::Interval,
+        Duration::ZERO
+    ));
+    assert_eq!(vec![btc_usdc(), eth_usdc()], book_manager.pools());
+
+    node.wait_for_subscriptions(2).await;
+    node.push_price(&btc_usdc(), 100, "0x1");
+    node.push_price(&eth_usdc(), 200, "0x2");
+
+    // books of both pools arrive on the merged stream tagged by their pair
+    let mut received = HashSet::new();
+    while received.len() < 2 {
+        let (asset_pair, ob) = timeout(Duration::from_secs(5), book_manager.next_book())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(asset_pair, ob.asset_pair);
+        received.insert(ob.tick);
+    }
+    assert_eq!(HashSet::from([100, 200]), received);
+    assert_eq!(200, book_manager.latest_book(&eth_usdc()).unwrap().tick);
+
+    // a removed pool no one else subscribes to is unsubscribed and no longer built
+    assert!(book_manager.remove_pool(&btc_usdc()));
+    assert!(!book_manager.remove_pool(&btc_usdc()));
+    assert_eq!(vec!["sub-BTC-USDC"], node.wait_for_unsubscriptions(1).await);
+    assert_eq!(vec![eth_usdc()], book_manager.pools());
+    assert!(book_manager.latest_book(&btc_usdc()).is_none());
+
+    node.push_price(&btc_usdc(), 101, "0x3");
+    node.push_price(&eth_usdc(), 201, "0x4");
+    let (asset_pair, ob) = timeout(Duration::from_secs(5), book_manager.next_book())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((eth_usdc(), 201), (asset_pair, ob.tick));
+
+    // the stream ends on shutdown
+    shutdown.cancel();
+    assert!(timeout(Duration::from_secs(5), book_manager.next_book())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(!book_manager.add_pool(
+        &btc_usdc(),
+        Duration::from_secs(1),
+        TriggerMode::Interval,
+        Duration::ZERO
+    ));
+}
+
+#[tokio::test]
+async fn test_removing_a_pool_keeps_other_subscribers() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    let mut book_manager = BookManager::new(handle.clone(), CancellationToken::new());
+    assert!(book_manager.add_pool(
+        &btc_usdc(),
+        Duration::from_secs(3600),
+        TriggerMode::PriceChange,
+        Duration::ZERO
+    ));
+    // a second consumer of the pool's prices, as candles and cross rates are
+    let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+    handle
+        .forward_pool_price_updates(&[btc_usdc()], update_tx)
+        .await;
+    node.wait_for_subscriptions(1).await;
+
+    node.push_price(&btc_usdc(), 100, "0x1");
+    let (_, update) = timeout(Duration::from_secs(5), update_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(100, update.unwrap().tick);
+
+    // the subscription outlives the pool's builder
+    assert!(book_manager.remove_pool(&btc_usdc()));
+    node.push_price(&btc_usdc(), 101, "0x2");
+    let (_, update) = timeout(Duration::from_secs(5), update_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(101, update.unwrap().tick);
+    assert!(
+        timeout(Duration::from_millis(200), node.wait_for_unsubscriptions(1))
+            .await
+            .is_err()
+    );
+}
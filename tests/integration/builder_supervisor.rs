@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::{
+    builder_supervisor::BuilderSupervisor, metrics, orderbook_builder::TriggerMode,
+};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    eth_usdc,
+    mock_node::{sample_liquidity, MockNode},
+    start_provider,
+};
+
+#[tokio::test]
+async fn test_restarts_failed_builder_on_same_receiver() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&eth_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&eth_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&eth_usdc(), 57040, "0x539");
+
+    let shutdown = CancellationToken::new();
+    let supervisor = BuilderSupervisor::new(handle.clone(), shutdown.clone())
+        .with_restart_backoff(Duration::from_millis(10), Duration::from_millis(100));
+    let mut book_rx = supervisor.supervise(
+        &eth_usdc(),
+        Duration::from_secs(3600),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+    );
+
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(57040, ob.tick);
+    assert_eq!(0, supervisor.restart_count(&eth_usdc()));
+
+    // resubscribing closes the builder's price update stream, stopping it
+    handle.unsubscribe_pool_price_updates(&eth_usdc());
+    handle.subscribe_pool_price_updates(&eth_usdc());
+    node.wait_for_subscriptions(2).await;
+    node.push_price(&eth_usdc(), 57041, "0x53a");
+
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(57041, ob.tick);
+    assert_eq!(1, supervisor.restart_count(&eth_usdc()));
+    assert!(metrics::gather()
+        .contains(r#"feedhandler_builder_restarts_total{asset_pair="ETH-USDC"} 1"#));
+
+    // shutdown stops the builder for good and closes the receiver
+    shutdown.cancel();
+    timeout(Duration::from_secs(5), async {
+        while book_rx.recv().await.is_some() {}
+    })
+    .await
+    .unwrap();
+    assert_eq!(1, supervisor.restart_count(&eth_usdc()));
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::candles::{
+    create_and_start_candle_builder, Candle, CandleAggregator,
+};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use super::{btc_usdc, mock_node::MockNode, start_provider};
+
+#[tokio::test]
+async fn test_publishes_closed_candles() {
+    let node = MockNode::start().await;
+    let (handle, _) = start_provider(&node).await;
+    let mut candle_rx = create_and_start_candle_builder(
+        handle,
+        vec![btc_usdc()],
+        CandleAggregator::new(vec!["1s".parse().unwrap()]),
+        CancellationToken::new(),
+    );
+    node.wait_for_subscriptions(1).await;
+
+    // back to back, none are dropped
+    node.push_price(&btc_usdc(), 57040, "0x0");
+    node.push_price(&btc_usdc(), 57042, "0x0");
+    node.push_price(&btc_usdc(), 57041, "0x0");
+
+    // closed once the second is over, the updates may straddle two candles
+    let candles = timeout(Duration::from_secs(5), async {
+        let mut candles: Vec<Candle> = Vec::new();
+        while candles.iter().map(|candle| candle.updates).sum::<u64>() < 3 {
+            candles.push(candle_rx.recv().await.unwrap());
+        }
+        candles
+    })
+    .await
+    .unwrap();
+
+    assert!(candles.iter().all(|candle| candle.asset_pair == btc_usdc()));
+    assert_eq!(57040, candles[0].open_tick);
+    assert_eq!(57041, candles.last().unwrap().close_tick);
+    assert_eq!(
+        3,
+        candles.iter().map(|candle| candle.ticks_moved).sum::<u64>()
+    );
+}
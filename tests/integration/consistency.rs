@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::{
+    consistency::{create_and_start_consistency_checker, ConsistencyOptions, DivergenceKind},
+    transport::websocket_transport::NodeEndpoint,
+};
+use serde_json::json;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    btc_usdc,
+    mock_node::{sample_liquidity, MockNode},
+    start_provider_with_endpoint,
+};
+
+#[tokio::test]
+async fn test_reports_diverging_nodes() {
+    let first = MockNode::start().await;
+    let second = MockNode::start().await;
+    first.set_liquidity(&btc_usdc(), sample_liquidity());
+    let mut liquidity = sample_liquidity();
+    liquidity["range_orders"][1]["liquidity"] = json!("0x2001");
+    second.set_liquidity(&btc_usdc(), liquidity);
+
+    let mut nodes = Vec::new();
+    for node in [&first, &second] {
+        let (handle, _) = start_provider_with_endpoint(
+            &NodeEndpoint::from_address(&node.hostname()),
+            Duration::from_secs(60),
+            CancellationToken::new(),
+        )
+        .await;
+        nodes.push((node.hostname(), handle));
+    }
+    let mut report_rx = create_and_start_consistency_checker(
+        nodes,
+        vec![btc_usdc()],
+        ConsistencyOptions {
+            block_timeout: Duration::from_secs(30),
+            liquidity_interval: Duration::from_secs(60),
+        },
+        CancellationToken::new(),
+    );
+    first.wait_for_subscriptions(1).await;
+    second.wait_for_subscriptions(1).await;
+
+    // agreeing prices, but the liquidity at that block differs
+    first.push_price_at_block(&btc_usdc(), 10, 57000);
+    second.push_price_at_block(&btc_usdc(), 10, 57000);
+    let report = timeout(Duration::from_secs(5), report_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(DivergenceKind::RangeLiquidity, report.kind);
+    assert_eq!(10, report.block_number);
+    assert_eq!(Some(57000), report.tick);
+    assert_eq!(Some("0x2001".to_string()), report.values[1].value);
+
+    // liquidity was requested at the block's hash
+    let request = &first.requests()[0];
+    assert_eq!(
+        report.block_hash.as_deref(),
+        request["params"]["at"].as_str()
+    );
+
+    // diverging ticks
+    first.push_price_at_block(&btc_usdc(), 11, 57001);
+    second.push_price_at_block(&btc_usdc(), 11, 57002);
+    let report = timeout(Duration::from_secs(5), report_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(DivergenceKind::Tick, report.kind);
+    assert_eq!(11, report.block_number);
+    assert_eq!(
+        vec![Some("57001".to_string()), Some("57002".to_string())],
+        report
+            .values
+            .iter()
+            .map(|value| value.value.clone())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_compares_every_block_of_a_burst() {
+    let first = MockNode::start().await;
+    let second = MockNode::start().await;
+
+    let mut nodes = Vec::new();
+    for node in [&first, &second] {
+        let (handle, _) = start_provider_with_endpoint(
+            &NodeEndpoint::from_address(&node.hostname()),
+            Duration::from_secs(60),
+            CancellationToken::new(),
+        )
+        .await;
+        nodes.push((node.hostname(), handle));
+    }
+    let mut report_rx = create_and_start_consistency_checker(
+        nodes,
+        vec![btc_usdc()],
+        ConsistencyOptions {
+            block_timeout: Duration::from_millis(200),
+            liquidity_interval: Duration::from_secs(60),
+        },
+        CancellationToken::new(),
+    );
+    first.wait_for_subscriptions(1).await;
+    second.wait_for_subscriptions(1).await;
+
+    // updates sent back to back are all forwarded, none are reported missing
+    for block in 20..30 {
+        first.push_price_at_block(&btc_usdc(), block, 57000);
+    }
+    for block in 20..30 {
+        second.push_price_at_block(&btc_usdc(), block, 57000);
+    }
+    second.push_price_at_block(&btc_usdc(), 30, 57001);
+    first.push_price_at_block(&btc_usdc(), 30, 57002);
+
+    let report = timeout(Duration::from_secs(5), report_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(DivergenceKind::Tick, report.kind);
+    assert_eq!(30, report.block_number);
+
+    // nothing is reported once the earlier blocks time out
+    assert!(timeout(Duration::from_secs(1), report_rx.recv())
+        .await
+        .is_err());
+}
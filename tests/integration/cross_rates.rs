@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::cross_rates::{
+    create_and_start_cross_rate_monitor, CrossRateOptions, CrossRates, RateSource,
+};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use super::{btc_usdc, eth_usdc, mock_node::MockNode, start_provider};
+
+#[tokio::test]
+async fn test_reports_reference_price_beyond_threshold() {
+    let node = MockNode::start().await;
+    let (handle, _) = start_provider(&node).await;
+    let cross_rates = CrossRates::new();
+    let mut report_rx = create_and_start_cross_rate_monitor(
+        handle,
+        vec![btc_usdc(), eth_usdc()],
+        CrossRateOptions {
+            threshold_bps: 50.0,
+            reference_prices: vec![("BTC-ETH".parse().unwrap(), 9.0)],
+        },
+        cross_rates.clone(),
+        CancellationToken::new(),
+    );
+    node.wait_for_subscriptions(2).await;
+
+    // BTC around 30000 and ETH around 3000 USDC imply 10 ETH per BTC, 10% above the reference
+    node.push_price(&btc_usdc(), 57040, "0x0");
+    node.push_price(&eth_usdc(), -196_257, "0x0");
+    let report = timeout(Duration::from_secs(5), report_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(RateSource::Reference, report.source);
+    assert_eq!("BTC-ETH", report.asset_pair.to_string());
+    assert!((report.implied_rate - 10.0).abs() < 0.01);
+    assert!((report.deviation_bps + 1000.0).abs() < 10.0);
+
+    let btc_eth = cross_rates.rate("BTC", "ETH").unwrap();
+    assert!((btc_eth - 10.0).abs() < 0.01, "rate {}", btc_eth);
+    assert_eq!(3, cross_rates.rates().len());
+
+    // an inconsistency is reported once, not on every update
+    node.push_price(&btc_usdc(), 57041, "0x0");
+    assert!(timeout(Duration::from_millis(500), report_rx.recv())
+        .await
+        .is_err());
+}
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::{
+    health::HealthMonitor,
+    metrics,
+    model::price_update::PriceUpdate,
+    pool_info_provider::{
+        node_connection::{NodeConnector, RedundancyMode},
+        pool_info_provider::PoolInfoProvider,
+        pool_info_provider_handle::PoolInfoProviderHandle,
+    },
+    transport::websocket_transport::NodeEndpoint,
+};
+use tokio::{sync::watch, task::JoinHandle, time::timeout};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    btc_usdc, eth_usdc,
+    mock_node::{sample_liquidity, MockNode},
+};
+
+/// Run a `PoolInfoProvider` connected to every node in `nodes`
+fn start_redundant_provider(
+    nodes: &[&MockNode],
+    redundancy: RedundancyMode,
+    stale_after: Duration,
+) -> (PoolInfoProviderHandle, JoinHandle<()>) {
+    let connectors = nodes
+        .iter()
+        .map(|node| NodeConnector::websocket(NodeEndpoint::from_address(&node.hostname())))
+        .collect();
+    let mut pool_info_provider =
+        PoolInfoProvider::with_nodes(connectors, redundancy, HealthMonitor::new(stale_after));
+    let handle = pool_info_provider.get_handle();
+
+    let task = tokio::spawn(async move {
+        pool_info_provider.run(CancellationToken::new()).await;
+    });
+
+    (handle, task)
+}
+
+/// Wait for the next price update
+async fn next_update(rx: &mut watch::Receiver<Option<PriceUpdate>>) -> PriceUpdate {
+    timeout(Duration::from_secs(5), rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    rx.borrow_and_update().clone().unwrap()
+}
+
+#[tokio::test]
+async fn test_fails_over_to_standby_on_disconnect() {
+    let primary = MockNode::start().await;
+    let standby = MockNode::start().await;
+    standby.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_redundant_provider(
+        &[&primary, &standby],
+        RedundancyMode::Failover,
+        Duration::from_secs(60),
+    );
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    primary.wait_for_subscriptions(1).await;
+    standby.wait_for_subscriptions(1).await;
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+
+    // the standby is subscribed but its updates aren't used while the primary is up
+    standby.push_price(&btc_usdc(), 1, "0x1");
+    primary.push_price(&btc_usdc(), 2, "0x2");
+    assert_eq!(2, next_update(&mut price_update_rx).await.tick);
+
+    primary.disconnect_websockets();
+    timeout(Duration::from_secs(5), async {
+        // updates pushed before the disconnect is noticed are dropped
+        loop {
+            standby.push_price(&btc_usdc(), 3, "0x3");
+            if timeout(Duration::from_millis(100), price_update_rx.changed())
+                .await
+                .is_ok()
+                && price_update_rx.borrow_and_update().as_ref().unwrap().tick == 3
+            {
+                break;
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    // REST requests go to the new active node
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_some());
+    assert_eq!(1, standby.requests().len());
+    assert!(primary.requests().is_empty());
+
+    // the primary is reconnected and resubscribed as a standby
+    primary.wait_for_subscriptions(2).await;
+}
+
+#[tokio::test]
+async fn test_fails_over_from_silent_active_node() {
+    let primary = MockNode::start().await;
+    let standby = MockNode::start().await;
+    let (handle, _task) = start_redundant_provider(
+        &[&primary, &standby],
+        RedundancyMode::Failover,
+        Duration::from_millis(200),
+    );
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    primary.wait_for_subscriptions(1).await;
+    standby.wait_for_subscriptions(1).await;
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+
+    // the primary stays connected but never sends an update
+    timeout(Duration::from_secs(5), async {
+        loop {
+            standby.push_price(&btc_usdc(), 3, "0x3");
+            if timeout(Duration::from_millis(100), price_update_rx.changed())
+                .await
+                .is_ok()
+                && price_update_rx.borrow_and_update().as_ref().unwrap().tick == 3
+            {
+                break;
+            }
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_active_active_deduplicates_by_block_and_tick() {
+    let first = MockNode::start().await;
+    let second = MockNode::start().await;
+    let (handle, _task) = start_redundant_provider(
+        &[&first, &second],
+        RedundancyMode::ActiveActive,
+        Duration::from_secs(60),
+    );
+
+    handle.subscribe_pool_price_updates(&eth_usdc());
+    first.wait_for_subscriptions(1).await;
+    second.wait_for_subscriptions(1).await;
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&eth_usdc())
+        .await
+        .unwrap();
+
+    first.push_price_at_block(&eth_usdc(), 10, 100);
+    let update = next_update(&mut price_update_rx).await;
+    assert_eq!((Some(10), 100), (update.block_number, update.tick));
+
+    // whichever node is first to deliver a block's update is used
+    second.push_price_at_block(&eth_usdc(), 10, 100);
+    second.push_price_at_block(&eth_usdc(), 11, 101);
+    let update = next_update(&mut price_update_rx).await;
+    assert_eq!((Some(11), 101), (update.block_number, update.tick));
+
+    first.push_price_at_block(&eth_usdc(), 11, 101);
+    timeout(Duration::from_secs(5), async {
+        while !metrics::gather()
+            .contains(r#"feedhandler_duplicate_price_updates_total{asset_pair="ETH-USDC"} 2"#)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(!price_update_rx.has_changed().unwrap());
+}
@@ -0,0 +1,559 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chainflip_feedhandler_rs::model::asset_pair::AssetPair;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, Notify},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+/// How the mock node replies to a REST request
+#[derive(Clone, Debug)]
+pub enum Reply {
+    /// JSON-RPC response with `result`
+    Result(Value),
+    /// JSON-RPC error response
+    Error { code: i64, message: String },
+    /// Bare HTTP status with an empty body
+    HttpStatus(u16),
+    /// Close the connection without responding
+    Hangup,
+}
+
+/// A scripted reply to a REST request, optionally delayed
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    pub delay: Duration,
+    pub reply: Reply,
+}
+
+impl MockResponse {
+    pub fn result(result: Value) -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            reply: Reply::Result(result),
+        }
+    }
+
+    pub fn error(code: i64, message: &str) -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            reply: Reply::Error {
+                code,
+                message: message.to_string(),
+            },
+        }
+    }
+
+    pub fn http_status(status: u16) -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            reply: Reply::HttpStatus(status),
+        }
+    }
+
+    pub fn hangup() -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            reply: Reply::Hangup,
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// Commands broadcast to every open websocket connection
+#[derive(Clone, Debug)]
+enum WsCommand {
+    /// Send a pool price update to connections subscribed to the pair
+    PushPrice {
+        asset_pair: AssetPair,
+        result: Value,
+    },
+    /// Send a raw text frame
+    PushRaw(String),
+    /// Close the connection
+    Disconnect,
+}
+
+#[derive(Default)]
+struct MockNodeState {
+    /// Scripted responses, consumed in order, keyed by JSON-RPC method
+    scripted: Mutex<HashMap<String, VecDeque<MockResponse>>>,
+    /// `cf_pool_liquidity` results returned when nothing is scripted
+    liquidity: Mutex<HashMap<AssetPair, Value>>,
+    /// `cf_pool_info` results returned when nothing is scripted
+    pool_info: Mutex<HashMap<AssetPair, Value>>,
+    /// Every REST request received
+    requests: Mutex<Vec<Value>>,
+    /// `Authorization` header of every connection, websocket and HTTP
+    authorizations: Mutex<Vec<Option<String>>>,
+    /// Asset pairs subscribed to over websockets, in subscription order
+    subscriptions: Mutex<Vec<AssetPair>>,
+    /// Subscription ids unsubscribed from over websockets, in unsubscription order
+    unsubscriptions: Mutex<Vec<String>>,
+    /// Number of websockets closed by the client with a close frame
+    closes: Mutex<usize>,
+    /// Notified on every subscription, unsubscription, close and REST request
+    activity: Notify,
+}
+
+/// An in-process fake Chainflip node serving websocket and HTTP JSON-RPC on the same localhost port,
+/// like a real node does.
+pub struct MockNode {
+    addr: SocketAddr,
+    state: Arc<MockNodeState>,
+    ws_commands: broadcast::Sender<WsCommand>,
+    accept_task: JoinHandle<()>,
+}
+
+impl MockNode {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(MockNodeState::default());
+        let (ws_commands, _) = broadcast::channel(1024);
+
+        let accept_task = {
+            let state = state.clone();
+            let ws_commands = ws_commands.clone();
+
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let state = state.clone();
+                    let ws_commands = ws_commands.subscribe();
+
+                    tokio::spawn(async move {
+                        let head = peek_request_head(&stream).await;
+                        state
+                            .authorizations
+                            .lock()
+                            .unwrap()
+                            .push(header_value(&head, "authorization"));
+
+                        if head.to_lowercase().contains("upgrade: websocket") {
+                            serve_websocket(stream, state, ws_commands).await;
+                        } else {
+                            serve_http(stream, state).await;
+                        }
+                    });
+                }
+            })
+        };
+
+        MockNode {
+            addr,
+            state,
+            ws_commands,
+            accept_task,
+        }
+    }
+
+    /// Address to hand to the provider, ie. "127.0.0.1:1234"
+    pub fn hostname(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Set the `cf_pool_liquidity` result returned for `asset_pair`
+    pub fn set_liquidity(&self, asset_pair: &AssetPair, liquidity: Value) {
+        self.state
+            .liquidity
+            .lock()
+            .unwrap()
+            .insert(asset_pair.clone(), liquidity);
+    }
+
+    /// Set the `cf_pool_info` result returned for `asset_pair`
+    pub fn set_pool_info(&self, asset_pair: &AssetPair, pool_info: Value) {
+        self.state
+            .pool_info
+            .lock()
+            .unwrap()
+            .insert(asset_pair.clone(), pool_info);
+    }
+
+    /// Queue a response to the next request for `method`, taking priority over defaults
+    pub fn script(&self, method: &str, response: MockResponse) {
+        self.state
+            .scripted
+            .lock()
+            .unwrap()
+            .entry(method.to_string())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Push a `cf_subscribe_pool_price` update for `asset_pair` to subscribed websockets
+    pub fn push_price(&self, asset_pair: &AssetPair, tick: i32, sqrt_price: &str) {
+        self.push_result(
+            asset_pair,
+            json!({
+                "price": "0x1",
+                "sqrt_price": sqrt_price,
+                "tick": tick,
+            }),
+        );
+    }
+
+    /// Push a `cf_subscribe_pool_price` update observed at block `block_number`
+    pub fn push_price_at_block(&self, asset_pair: &AssetPair, block_number: u64, tick: i32) {
+        self.push_result(
+            asset_pair,
+            json!({
+                "price": "0x1",
+                "sqrt_price": "0x10",
+                "tick": tick,
+                "block_number": block_number,
+                "block_hash": format!("0x{:064x}", block_number),
+            }),
+        );
+    }
+
+    fn push_result(&self, asset_pair: &AssetPair, result: Value) {
+        let _ = self.ws_commands.send(WsCommand::PushPrice {
+            asset_pair: asset_pair.clone(),
+            result,
+        });
+    }
+
+    /// Push an arbitrary text frame to every websocket
+    pub fn push_raw(&self, frame: &str) {
+        let _ = self.ws_commands.send(WsCommand::PushRaw(frame.to_string()));
+    }
+
+    /// Close every open websocket
+    pub fn disconnect_websockets(&self) {
+        let _ = self.ws_commands.send(WsCommand::Disconnect);
+    }
+
+    /// `Authorization` header of every connection made so far, websocket and HTTP
+    pub fn authorizations(&self) -> Vec<Option<String>> {
+        self.state.authorizations.lock().unwrap().clone()
+    }
+
+    /// REST requests received so far
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Wait until at least `count` websocket subscriptions have been made
+    pub async fn wait_for_subscriptions(&self, count: usize) -> Vec<AssetPair> {
+        self.wait_for(|state| {
+            let subscriptions = state.subscriptions.lock().unwrap();
+            (subscriptions.len() >= count).then(|| subscriptions.clone())
+        })
+        .await
+    }
+
+    /// Wait until at least `count` websocket unsubscriptions have been made
+    pub async fn wait_for_unsubscriptions(&self, count: usize) -> Vec<String> {
+        self.wait_for(|state| {
+            let unsubscriptions = state.unsubscriptions.lock().unwrap();
+            (unsubscriptions.len() >= count).then(|| unsubscriptions.clone())
+        })
+        .await
+    }
+
+    /// Wait until at least `count` websockets have been closed by the client
+    pub async fn wait_for_closes(&self, count: usize) -> usize {
+        self.wait_for(|state| {
+            let closes = *state.closes.lock().unwrap();
+            (closes >= count).then_some(closes)
+        })
+        .await
+    }
+
+    /// Wait until at least `count` REST requests have been received
+    pub async fn wait_for_requests(&self, count: usize) -> Vec<Value> {
+        self.wait_for(|state| {
+            let requests = state.requests.lock().unwrap();
+            (requests.len() >= count).then(|| requests.clone())
+        })
+        .await
+    }
+
+    async fn wait_for<T>(&self, condition: impl Fn(&MockNodeState) -> Option<T>) -> T {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let notified = self.state.activity.notified();
+                if let Some(result) = condition(&self.state) {
+                    return result;
+                }
+                notified.await;
+            }
+        })
+        .await
+        .expect("timed out waiting for mock node activity")
+    }
+}
+
+impl Drop for MockNode {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        let _ = self.ws_commands.send(WsCommand::Disconnect);
+    }
+}
+
+/// Peek at the request head, used to tell websocket upgrades from plain HTTP requests
+async fn peek_request_head(stream: &TcpStream) -> String {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match stream.peek(&mut buf).await {
+            Ok(n) => n,
+            Err(_) => return String::new(),
+        };
+
+        let head = String::from_utf8_lossy(&buf[..n]);
+        if head.contains("\r\n\r\n") || n == buf.len() {
+            return head.into_owned();
+        }
+
+        sleep(Duration::from_millis(1)).await;
+    }
+}
+
+/// Value of the header `name` (lowercase) in a request head
+fn header_value(head: &str, name: &str) -> Option<String> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim().to_lowercase() == name).then(|| value.trim().to_string())
+    })
+}
+
+fn asset_pair_from_params(params: &Value, from_key: &str, to_key: &str) -> Option<AssetPair> {
+    Some(AssetPair::new(
+        params.get(from_key)?.as_str()?.to_string(),
+        params.get(to_key)?.as_str()?.to_string(),
+    ))
+}
+
+async fn serve_websocket(
+    stream: TcpStream,
+    state: Arc<MockNodeState>,
+    mut ws_commands: broadcast::Receiver<WsCommand>,
+) {
+    let Ok(ws_stream) = accept_async(stream).await else {
+        return;
+    };
+    let (mut ws_write, mut ws_read) = ws_stream.split();
+
+    // subscription id per subscribed asset pair on this connection
+    let mut subscriptions: HashMap<AssetPair, String> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            frame = ws_read.next() => {
+                let frame = match frame {
+                    Some(Ok(Message::Text(frame))) => frame,
+                    Some(Ok(Message::Close(_))) => {
+                        *state.closes.lock().unwrap() += 1;
+                        state.activity.notify_waiters();
+
+                        return;
+                    },
+                    _ => return,
+                };
+                let Ok(request) = serde_json::from_str::<Value>(&frame) else {
+                    continue;
+                };
+
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                let response = match request.get("method").and_then(|m| m.as_str()) {
+                    Some("cf_subscribe_pool_price") => {
+                        match asset_pair_from_params(&request["params"], "from_asset", "to_asset") {
+                            Some(asset_pair) => {
+                                let subscription_id = format!("sub-{}", asset_pair);
+                                subscriptions.insert(asset_pair.clone(), subscription_id.clone());
+                                state.subscriptions.lock().unwrap().push(asset_pair);
+                                state.activity.notify_waiters();
+
+                                json!({"jsonrpc": "2.0", "id": id, "result": subscription_id})
+                            },
+                            None => json!({"jsonrpc": "2.0", "id": id, "error": {"code": -32602, "message": "Invalid params"}}),
+                        }
+                    },
+                    Some("cf_unsubscribe_pool_price") => {
+                        let subscription_id = request["params"][0].as_str().unwrap_or_default().to_string();
+                        let subscribed = subscriptions.values().any(|id| id == &subscription_id);
+                        subscriptions.retain(|_, id| id != &subscription_id);
+                        state.unsubscriptions.lock().unwrap().push(subscription_id);
+                        state.activity.notify_waiters();
+
+                        json!({"jsonrpc": "2.0", "id": id, "result": subscribed})
+                    },
+                    _ => json!({"jsonrpc": "2.0", "id": id, "error": {"code": -32601, "message": "Method not found"}}),
+                };
+
+                if ws_write.send(Message::Text(response.to_string())).await.is_err() {
+                    return;
+                }
+            },
+            command = ws_commands.recv() => {
+                let frame = match command {
+                    Ok(WsCommand::PushPrice { asset_pair, result }) => {
+                        let Some(subscription_id) = subscriptions.get(&asset_pair) else {
+                            continue;
+                        };
+
+                        json!({
+                            "jsonrpc": "2.0",
+                            "method": "cf_subscribe_pool_price",
+                            "params": {"subscription": subscription_id, "result": result},
+                        }).to_string()
+                    },
+                    Ok(WsCommand::PushRaw(frame)) => frame,
+                    Ok(WsCommand::Disconnect) | Err(_) => {
+                        let _ = ws_write.close().await;
+
+                        return;
+                    },
+                };
+
+                if ws_write.send(Message::Text(frame)).await.is_err() {
+                    return;
+                }
+            },
+        }
+    }
+}
+
+/// Read a single HTTP request and return its body
+async fn read_http_body(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_lowercase();
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|len| len.trim().parse().ok())
+        .unwrap_or(0);
+
+    while buf.len() < header_end + content_length {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    Some(buf[header_end..header_end + content_length].to_vec())
+}
+
+async fn serve_http(mut stream: TcpStream, state: Arc<MockNodeState>) {
+    let Some(body) = read_http_body(&mut stream).await else {
+        return;
+    };
+    let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let method = request["method"].as_str().unwrap_or_default().to_string();
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+
+    let scripted = state
+        .scripted
+        .lock()
+        .unwrap()
+        .get_mut(&method)
+        .and_then(|responses| responses.pop_front());
+
+    let response = scripted.unwrap_or_else(|| match method.as_str() {
+        "cf_pool_liquidity" => {
+            let liquidity = asset_pair_from_params(&request["params"], "base_asset", "quote_asset")
+                .and_then(|asset_pair| state.liquidity.lock().unwrap().get(&asset_pair).cloned());
+
+            match liquidity {
+                Some(liquidity) => MockResponse::result(liquidity),
+                None => MockResponse::error(-32603, "Pool not found"),
+            }
+        }
+        "cf_pool_info" => {
+            let pool_info = asset_pair_from_params(&request["params"], "base_asset", "quote_asset")
+                .and_then(|asset_pair| state.pool_info.lock().unwrap().get(&asset_pair).cloned());
+
+            match pool_info {
+                Some(pool_info) => MockResponse::result(pool_info),
+                None => MockResponse::error(-32603, "Pool not found"),
+            }
+        }
+        _ => MockResponse::error(-32601, "Method not found"),
+    });
+
+    state.requests.lock().unwrap().push(request);
+    state.activity.notify_waiters();
+
+    sleep(response.delay).await;
+
+    let (status, body) = match response.reply {
+        Reply::Result(result) => (
+            200,
+            json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string(),
+        ),
+        Reply::Error { code, message } => (
+            200,
+            json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+                .to_string(),
+        ),
+        Reply::HttpStatus(status) => (status, String::new()),
+        Reply::Hangup => return,
+    };
+
+    let http_response = format!(
+        "HTTP/1.1 {} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(http_response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// A `cf_pool_liquidity` result with one ask, one bid and two range order bands
+pub fn sample_liquidity() -> Value {
+    json!({
+        "limit_orders": {
+            "asks": [{"tick": 57050, "amount": "0x100"}],
+            "bids": [{"tick": 57030, "amount": "0x200"}],
+        },
+        "range_orders": [
+            {"tick": 56000, "liquidity": "0x1000"},
+            {"tick": 57000, "liquidity": "0x2000"},
+            {"tick": 58000, "liquidity": "0x0"},
+        ],
+    })
+}
+
+/// A `cf_pool_info` result charging range orders 0.05% and limit orders nothing
+pub fn sample_pool_info() -> Value {
+    json!({
+        "limit_order_fee_hundredth_pips": 0,
+        "range_order_fee_hundredth_pips": 500,
+        "range_order_total_fees_earned": {"base": "0x0", "quote": "0x0"},
+        "limit_order_total_fees_earned": {"base": "0x0", "quote": "0x0"},
+        "range_total_swap_inputs": {"base": "0x0", "quote": "0x0"},
+        "limit_total_swap_inputs": {"base": "0x0", "quote": "0x0"},
+    })
+}
@@ -0,0 +1,402 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::{
+    metrics,
+    model::book_validation::ValidationPolicy,
+    orderbook_builder::{create_and_start_order_book_builder, OrderBookBuilder, TriggerMode},
+};
+use primitive_types::U256;
+use serde_json::json;
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    btc_usdc,
+    mock_node::{sample_liquidity, sample_pool_info, MockNode, MockResponse},
+    start_provider,
+};
+
+#[tokio::test]
+async fn test_builds_books_on_price_updates() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // a long poll interval so only the initial tick and price changes trigger builds
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_secs(3600),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+        CancellationToken::new(),
+    );
+
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(btc_usdc(), ob.asset_pair);
+    assert_eq!(57040, ob.tick);
+    assert_eq!(U256::from(1337), ob.sqrt_price_x96);
+    assert_eq!(2, ob.range_orders.len());
+    assert_eq!(1, ob.limit_asks.len());
+    assert_eq!(1, ob.limit_bids.len());
+
+    // each price change triggers a rebuild against freshly fetched liquidity
+    node.push_price(&btc_usdc(), 57041, "0x53a");
+    let ob = timeout(Duration::from_secs(5), async {
+        loop {
+            let ob = book_rx.recv().await.unwrap();
+            if ob.tick == 57041 {
+                break ob;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(U256::from(1338), ob.sqrt_price_x96);
+    assert!(node.requests().len() >= 2);
+}
+
+#[tokio::test]
+async fn test_retries_failed_liquidity_request() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    node.script(
+        "cf_pool_liquidity",
+        MockResponse::error(-32603, "Internal error"),
+    );
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_secs(3600),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+        CancellationToken::new(),
+    );
+
+    // the first build is skipped, the builder keeps running and the next price change builds
+    timeout(Duration::from_secs(5), async {
+        while !metrics::gather()
+            .contains(r#"feedhandler_builds_without_liquidity_total{asset_pair="BTC-USDC"}"#)
+        {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    node.push_price(&btc_usdc(), 57041, "0x53a");
+
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(57041, ob.tick);
+}
+
+#[tokio::test]
+async fn test_builds_books_on_poll_interval() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_millis(50),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+        CancellationToken::new(),
+    );
+
+    for _ in 0..3 {
+        let ob = timeout(Duration::from_secs(5), book_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(57040, ob.tick);
+    }
+    assert!(node.wait_for_requests(3).await.len() >= 3);
+}
+
+#[tokio::test]
+async fn test_slow_liquidity_delays_book() {
+    let node = MockNode::start().await;
+    node.script(
+        "cf_pool_liquidity",
+        MockResponse::result(sample_liquidity()).with_delay(Duration::from_millis(300)),
+    );
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let start = tokio::time::Instant::now();
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_secs(3600),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+        CancellationToken::new(),
+    );
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    // the slow request is visible in the book's latency stamps
+    let latency = ob.latency.unwrap();
+    assert!(latency.liquidity_request_duration() >= Duration::from_millis(300));
+    assert!(latency.age_at_publish >= latency.liquidity_request_duration());
+    assert!(latency.price_received_at.monotonic <= latency.liquidity_requested_at.monotonic);
+    assert!(latency.liquidity_received_at.monotonic <= latency.built_at.monotonic);
+}
+
+#[tokio::test]
+async fn test_builder_stops_on_shutdown() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+
+    let shutdown = CancellationToken::new();
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_millis(50),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+        shutdown.clone(),
+    );
+    timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // books already built are still delivered, then the channel closes
+    shutdown.cancel();
+    timeout(Duration::from_secs(5), async {
+        while book_rx.recv().await.is_some() {}
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_builder_waiting_for_prices_stops_on_shutdown() {
+    let node = MockNode::start().await;
+    let (handle, _task) = start_provider(&node).await;
+
+    // never subscribed, so the builder waits for a price stream
+    let shutdown = CancellationToken::new();
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle,
+        Duration::from_millis(50),
+        TriggerMode::IntervalAndPriceChange,
+        Duration::ZERO,
+        shutdown.clone(),
+    );
+
+    shutdown.cancel();
+    assert!(timeout(Duration::from_secs(1), book_rx.recv())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_min_rebuild_interval_coalesces_price_changes() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_secs(3600),
+        TriggerMode::PriceChange,
+        Duration::from_millis(300),
+        CancellationToken::new(),
+    );
+    let first = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(57040, first.tick);
+    let first_built_at = tokio::time::Instant::now();
+
+    // a burst of price changes is built once, off the latest price
+    for tick in 57041..57046 {
+        node.push_price(&btc_usdc(), tick, "0x539");
+    }
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(first_built_at.elapsed() >= Duration::from_millis(250));
+    assert_eq!(57045, ob.tick);
+    assert!(timeout(Duration::from_millis(500), book_rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_applies_validation_policy() {
+    let node = MockNode::start().await;
+    // unsorted range orders and a bid above the ask
+    node.set_liquidity(
+        &btc_usdc(),
+        json!({
+            "limit_orders": {
+                "asks": [{"tick": 57050, "amount": "0x100"}],
+                "bids": [{"tick": 57060, "amount": "0x200"}, {"tick": 57030, "amount": "0x200"}],
+            },
+            "range_orders": [
+                {"tick": 57000, "liquidity": "0x2000"},
+                {"tick": 56000, "liquidity": "0x1000"},
+                {"tick": 58000, "liquidity": "0x0"},
+            ],
+        }),
+    );
+    let (handle, _task) = start_provider(&node).await;
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x0");
+
+    let start = |policy: ValidationPolicy| {
+        let (book_tx, book_rx) = mpsc::unbounded_channel();
+        let builder = OrderBookBuilder::new(
+            btc_usdc(),
+            handle.clone(),
+            Duration::from_secs(3600),
+            TriggerMode::IntervalAndPriceChange,
+            Duration::ZERO,
+            book_tx,
+        )
+        .with_validation(policy);
+        tokio::spawn(async move { builder.run(CancellationToken::new()).await });
+
+        book_rx
+    };
+
+    let mut book_rx = start(ValidationPolicy::Repair);
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(2, ob.issues.len());
+    assert_eq!(56000, ob.range_orders[0].start_tick);
+    assert_eq!(57000, ob.range_orders[1].start_tick);
+    assert_eq!(
+        vec![57030],
+        ob.limit_bids.iter().map(|o| o.tick).collect::<Vec<_>>()
+    );
+
+    let mut book_rx = start(ValidationPolicy::Drop);
+    assert!(timeout(Duration::from_millis(500), book_rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_attaches_pool_info() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    node.set_pool_info(&btc_usdc(), sample_pool_info());
+    // a slow fetch doesn't hold up books
+    node.script(
+        "cf_pool_info",
+        MockResponse::result(sample_pool_info()).with_delay(Duration::from_secs(1)),
+    );
+    let (handle, _task) = start_provider(&node).await;
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    node.push_price(&btc_usdc(), 57040, "0x539");
+
+    let mut book_rx = create_and_start_order_book_builder(
+        &btc_usdc(),
+        handle.clone(),
+        Duration::from_secs(3600),
+        TriggerMode::PriceChange,
+        Duration::ZERO,
+        CancellationToken::new(),
+    );
+    let ob = timeout(Duration::from_millis(500), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(ob.pool_info.is_none());
+
+    // attached to books once fetched, which is only once rather than on every build
+    sleep(Duration::from_millis(1500)).await;
+    node.push_price(&btc_usdc(), 57041, "0x53a");
+    let ob = timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(500, ob.pool_info.unwrap().range_order_fee_hundredth_pips);
+    node.push_price(&btc_usdc(), 57042, "0x53b");
+    timeout(Duration::from_secs(5), book_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let pool_info_requests = node
+        .requests()
+        .iter()
+        .filter(|request| request["method"] == "cf_pool_info")
+        .count();
+    assert_eq!(1, pool_info_requests);
+}
@@ -0,0 +1,422 @@
+use std::time::Duration;
+
+use chainflip_feedhandler_rs::{
+    metrics, model::asset_pair::AssetPair, transport::websocket_transport::NodeEndpoint,
+};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    btc_usdc, eth_usdc,
+    mock_node::{sample_liquidity, sample_pool_info, MockNode, MockResponse},
+    start_provider, start_provider_with_endpoint, start_provider_with_stale_after,
+};
+
+#[tokio::test]
+async fn test_subscribe_and_stream_price_updates() {
+    let node = MockNode::start().await;
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    handle.subscribe_pool_price_updates(&eth_usdc());
+    assert_eq!(
+        vec![btc_usdc(), eth_usdc()],
+        node.wait_for_subscriptions(2).await
+    );
+
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+    assert!(price_update_rx.borrow().is_none());
+
+    node.push_price(&btc_usdc(), 57040, "0x10");
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let price_update = price_update_rx.borrow_and_update().clone().unwrap();
+    assert_eq!(btc_usdc(), price_update.asset_pair);
+    assert_eq!(57040, price_update.tick);
+    assert_eq!("0x10", price_update.sqrt_price);
+
+    let latest = handle.get_latest_pool_price(&btc_usdc()).await.unwrap();
+    assert_eq!(57040, latest.tick);
+
+    // no update has been pushed for ETH
+    assert!(handle.get_latest_pool_price(&eth_usdc()).await.is_none());
+}
+
+#[tokio::test]
+async fn test_unsubscribed_pool_has_no_price_stream() {
+    let node = MockNode::start().await;
+    let (handle, _task) = start_provider(&node).await;
+
+    assert!(handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .is_none());
+    assert!(handle.get_latest_pool_price(&btc_usdc()).await.is_none());
+}
+
+#[tokio::test]
+async fn test_unsubscribe_before_subscribed() {
+    let node = MockNode::start().await;
+    let (handle, _task) = start_provider(&node).await;
+
+    // the subscription is made after the pool was unsubscribed, it is unsubscribed once known
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    handle.unsubscribe_pool_price_updates(&btc_usdc());
+    assert_eq!(vec!["sub-BTC-USDC"], node.wait_for_unsubscriptions(1).await);
+    assert_eq!(vec![btc_usdc()], node.wait_for_subscriptions(1).await);
+}
+
+#[tokio::test]
+async fn test_subscriptions_are_shared() {
+    let node = MockNode::start().await;
+    let (handle, _task) = start_provider(&node).await;
+
+    // subscribed once on the node, unsubscribed once both clients are done with it
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    assert_eq!(vec![btc_usdc()], node.wait_for_subscriptions(1).await);
+    handle.unsubscribe_pool_price_updates(&btc_usdc());
+    node.push_price(&btc_usdc(), 42, "0x1");
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+    timeout(
+        Duration::from_secs(5),
+        price_update_rx.wait_for(|p| p.is_some()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    handle.unsubscribe_pool_price_updates(&btc_usdc());
+    assert_eq!(vec!["sub-BTC-USDC"], node.wait_for_unsubscriptions(1).await);
+}
+
+#[tokio::test]
+async fn test_unknown_and_malformed_frames_are_ignored() {
+    let node = MockNode::start().await;
+    let (handle, task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+
+    node.push_raw("not json");
+    node.push_raw(r#"{"jsonrpc":"2.0","id":"unknown","result":"sub-x"}"#);
+    node.push_raw(
+        r#"{"jsonrpc":"2.0","method":"cf_subscribe_pool_price","params":{"subscription":"sub-x","result":{"price":"0x1","sqrt_price":"0x1","tick":1}}}"#,
+    );
+    node.push_price(&btc_usdc(), 42, "0x1");
+
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(42, price_update_rx.borrow().as_ref().unwrap().tick);
+    assert!(!task.is_finished());
+}
+
+#[tokio::test]
+async fn test_get_pool_liquidity() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    let liquidity = handle.get_pool_liquidity(&btc_usdc()).await.unwrap();
+    assert_eq!(3, liquidity.result.range_orders.len());
+    assert_eq!(1, liquidity.result.limit_orders.asks.len());
+    assert_eq!(1, liquidity.result.limit_orders.bids.len());
+
+    let requests = node.requests();
+    assert_eq!(1, requests.len());
+    assert_eq!("cf_pool_liquidity", requests[0]["method"]);
+    assert_eq!("BTC", requests[0]["params"]["base_asset"]);
+    assert_eq!("USDC", requests[0]["params"]["quote_asset"]);
+}
+
+#[tokio::test]
+async fn test_get_pool_info() {
+    let node = MockNode::start().await;
+    node.set_pool_info(&btc_usdc(), sample_pool_info());
+    let (handle, _task) = start_provider(&node).await;
+
+    let pool_info = handle.get_pool_info(&btc_usdc()).await.unwrap();
+    assert_eq!(0, pool_info.limit_order_fee_hundredth_pips);
+    assert_eq!(500, pool_info.range_order_fee_hundredth_pips);
+
+    let requests = node.requests();
+    assert_eq!("cf_pool_info", requests[0]["method"]);
+    assert_eq!("BTC", requests[0]["params"]["base_asset"]);
+    assert_eq!("USDC", requests[0]["params"]["quote_asset"]);
+
+    // unknown pool
+    assert!(handle.get_pool_info(&eth_usdc()).await.is_none());
+}
+
+#[tokio::test]
+async fn test_get_pool_liquidity_failures() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    node.script("cf_pool_liquidity", MockResponse::error(-32603, "boom"));
+    node.script("cf_pool_liquidity", MockResponse::http_status(500));
+    node.script("cf_pool_liquidity", MockResponse::hangup());
+    let (handle, task) = start_provider(&node).await;
+
+    // each failure is reported as no liquidity without taking the provider down
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_none());
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_none());
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_none());
+    assert!(!task.is_finished());
+
+    // unknown pool
+    assert!(handle.get_pool_liquidity(&eth_usdc()).await.is_none());
+
+    // once the script is exhausted the default liquidity is served again
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_some());
+
+    let metrics = metrics::gather();
+    assert!(metrics.contains(
+        r#"feedhandler_liquidity_request_errors_total{asset_pair="BTC-USDC",kind="parse"}"#
+    ));
+    assert!(metrics.contains(
+        r#"feedhandler_liquidity_request_errors_total{asset_pair="BTC-USDC",kind="transport"}"#
+    ));
+}
+
+#[tokio::test]
+async fn test_delayed_liquidity_response() {
+    let node = MockNode::start().await;
+    node.script(
+        "cf_pool_liquidity",
+        MockResponse::result(sample_liquidity()).with_delay(Duration::from_millis(200)),
+    );
+    let (handle, _task) = start_provider(&node).await;
+
+    let start = tokio::time::Instant::now();
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_some());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_concurrent_liquidity_requests_are_coalesced() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    node.script(
+        "cf_pool_liquidity",
+        MockResponse::result(sample_liquidity()).with_delay(Duration::from_millis(300)),
+    );
+    let (handle, _task) = start_provider(&node).await;
+
+    let asset_pair = btc_usdc();
+    let (first, second, third, at_block) = tokio::join!(
+        handle.get_pool_liquidity(&asset_pair),
+        handle.get_pool_liquidity(&asset_pair),
+        handle.get_pool_liquidity(&asset_pair),
+        handle.get_pool_liquidity_at(&asset_pair, "0xabc"),
+    );
+    assert!(first.is_some() && second.is_some() && third.is_some() && at_block.is_some());
+
+    // one request for the latest block is shared, a request at another block is made separately
+    let requests = node.requests();
+    assert_eq!(2, requests.len());
+    assert_eq!(
+        1,
+        requests
+            .iter()
+            .filter(|request| request["params"]["at"] == "0xabc")
+            .count()
+    );
+    assert!(metrics::gather()
+        .contains(r#"feedhandler_coalesced_liquidity_requests_total{asset_pair="BTC-USDC"} 2"#));
+
+    // requests made once the shared request is answered go to the node again
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_some());
+    assert_eq!(3, node.requests().len());
+}
+
+#[tokio::test]
+async fn test_auth_headers_sent_to_node() {
+    let node = MockNode::start().await;
+    node.set_liquidity(&btc_usdc(), sample_liquidity());
+    let endpoint = NodeEndpoint {
+        ws_url: format!("ws://{}/ws", node.hostname()),
+        http_url: format!("http://{}/rpc", node.hostname()),
+        bearer_token: Some("secret".to_string()),
+        ..NodeEndpoint::from_address(&node.hostname())
+    };
+    let (handle, _task) =
+        start_provider_with_endpoint(&endpoint, Duration::from_secs(60), CancellationToken::new())
+            .await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    assert!(handle.get_pool_liquidity(&btc_usdc()).await.is_some());
+
+    // the websocket handshake and the REST request both carry the token
+    let authorizations = node.authorizations();
+    assert_eq!(2, authorizations.len());
+    assert!(authorizations
+        .iter()
+        .all(|a| a.as_deref() == Some("Bearer secret")));
+}
+
+#[tokio::test]
+async fn test_websocket_disconnect_stops_provider() {
+    let node = MockNode::start().await;
+    let (handle, task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+
+    node.disconnect_websockets();
+    timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_pool_goes_stale_without_updates() {
+    let node = MockNode::start().await;
+    let (handle, _task) = start_provider_with_stale_after(&node, Duration::from_millis(200)).await;
+
+    handle.subscribe_pool_price_updates(&btc_usdc());
+    node.wait_for_subscriptions(1).await;
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&btc_usdc())
+        .await
+        .unwrap();
+
+    node.push_price(&btc_usdc(), 57040, "0x10");
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!price_update_rx.borrow_and_update().as_ref().unwrap().stale);
+    assert!(handle
+        .health_monitor()
+        .report()
+        .pools
+        .iter()
+        .all(|p| !p.stale));
+
+    // watchers are notified once the pool goes stale
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(price_update_rx.borrow_and_update().as_ref().unwrap().stale);
+    assert!(
+        handle
+            .get_latest_pool_price(&btc_usdc())
+            .await
+            .unwrap()
+            .stale
+    );
+    assert!(!handle.health_monitor().report().ready);
+
+    // a fresh update clears the flag
+    node.push_price(&btc_usdc(), 57041, "0x10");
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!price_update_rx.borrow().as_ref().unwrap().stale);
+}
+
+#[tokio::test]
+async fn test_shutdown_unsubscribes_and_closes_connection() {
+    let node = MockNode::start().await;
+    let shutdown = CancellationToken::new();
+    let (handle, task) = start_provider_with_endpoint(
+        &NodeEndpoint::from_address(&node.hostname()),
+        Duration::from_secs(60),
+        shutdown.clone(),
+    )
+    .await;
+
+    // a price update for each pool makes sure both subscriptions have been confirmed
+    for (count, asset_pair) in [btc_usdc(), eth_usdc()].iter().enumerate() {
+        handle.subscribe_pool_price_updates(asset_pair);
+        node.wait_for_subscriptions(count + 1).await;
+        let mut price_update_rx = handle
+            .get_streaming_pool_price_updates(asset_pair)
+            .await
+            .unwrap();
+        node.push_price(asset_pair, 1, "0x1");
+        timeout(Duration::from_secs(5), price_update_rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    shutdown.cancel();
+    timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut unsubscriptions = node.wait_for_unsubscriptions(2).await;
+    unsubscriptions.sort();
+    assert_eq!(vec!["sub-BTC-USDC", "sub-ETH-USDC"], unsubscriptions);
+    assert_eq!(1, node.wait_for_closes(1).await);
+
+    // requests made after shutdown go unanswered
+    assert!(handle.get_latest_pool_price(&btc_usdc()).await.is_none());
+}
+
+#[tokio::test]
+async fn test_liquidity_cached_until_next_block() {
+    let node = MockNode::start().await;
+    // a pool no other test uses, so the cache metrics only count this test's lookups
+    let dot_usdc = AssetPair::new("DOT".to_string(), "USDC".to_string());
+    node.set_liquidity(&dot_usdc, sample_liquidity());
+    let (handle, _task) = start_provider(&node).await;
+
+    handle.subscribe_pool_price_updates(&dot_usdc);
+    node.wait_for_subscriptions(1).await;
+    let mut price_update_rx = handle
+        .get_streaming_pool_price_updates(&dot_usdc)
+        .await
+        .unwrap();
+    node.push_price_at_block(&dot_usdc, 10, 100);
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the latest block's liquidity is requested once
+    assert!(handle.get_pool_liquidity(&dot_usdc).await.is_some());
+    assert!(handle.get_pool_liquidity(&dot_usdc).await.is_some());
+    let requests = node.requests();
+    assert_eq!(1, requests.len());
+    assert_eq!(format!("0x{:064x}", 10), requests[0]["params"]["at"]);
+    let gathered = metrics::gather();
+    assert!(gathered.contains(
+        r#"feedhandler_liquidity_cache_lookups_total{asset_pair="DOT-USDC",result="hit"} 1"#
+    ));
+    assert!(gathered.contains(
+        r#"feedhandler_liquidity_cache_lookups_total{asset_pair="DOT-USDC",result="miss"} 1"#
+    ));
+
+    // a new block needs its own liquidity
+    node.push_price_at_block(&dot_usdc, 11, 101);
+    timeout(Duration::from_secs(5), price_update_rx.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(handle.get_pool_liquidity(&dot_usdc).await.is_some());
+    let requests = node.requests();
+    assert_eq!(2, requests.len());
+    assert_eq!(format!("0x{:064x}", 11), requests[1]["params"]["at"]);
+}
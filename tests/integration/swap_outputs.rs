@@ -0,0 +1,113 @@
+use chainflip_feedhandler_rs::{
+    model::{
+        asset_pair::AssetPair,
+        liquidity::{Liquidity, Result},
+        order_book::OrderBook,
+        swap::{simulate_swap, SwapFees},
+    },
+    util::{asset_decimals, hex_string_to_u256, u256_to_f64},
+};
+use serde::Deserialize;
+
+/// Swap outputs computed with exact integer maths by `generate_swap_outputs.py`
+const SWAP_OUTPUTS: &str = include_str!("../fixtures/swap_outputs.json");
+
+#[derive(Deserialize)]
+struct SwapOutputs {
+    cases: Vec<SwapCase>,
+}
+
+#[derive(Deserialize)]
+struct SwapCase {
+    name: String,
+    asset_pair: String,
+    tick: i32,
+    sqrt_price: String,
+    #[serde(flatten)]
+    liquidity: Result,
+    from_asset: String,
+    amount_in: String,
+    limit_order_fee_hundredth_pips: u32,
+    range_order_fee_hundredth_pips: u32,
+    amount_out: String,
+    unfilled: String,
+}
+
+impl SwapCase {
+    fn asset_pair(&self) -> AssetPair {
+        self.asset_pair.parse().unwrap()
+    }
+
+    fn book(&self) -> OrderBook {
+        let liquidity = Liquidity {
+            id: "1".to_string(),
+            jsonrpc: "2.0".to_string(),
+            result: self.liquidity.clone(),
+        };
+
+        OrderBook::new(
+            &self.asset_pair(),
+            liquidity,
+            hex_string_to_u256(&self.sqrt_price),
+            self.tick,
+        )
+    }
+
+    fn fees(&self) -> SwapFees {
+        SwapFees {
+            limit_order_hundredth_pips: self.limit_order_fee_hundredth_pips,
+            range_order_hundredth_pips: self.range_order_fee_hundredth_pips,
+        }
+    }
+
+    /// Smallest units per whole unit of the input and output assets
+    fn units(&self) -> (f64, f64) {
+        let unit = |asset: &str| 10_f64.powi(asset_decimals(asset).unwrap() as i32);
+        let asset_pair = self.asset_pair();
+        let to_asset = if self.from_asset == asset_pair.from {
+            &asset_pair.to
+        } else {
+            &asset_pair.from
+        };
+
+        (unit(&self.from_asset), unit(to_asset))
+    }
+}
+
+/// The simulator works in `f64`, its error on these cases is within 5e-11 of the exact amount
+fn assert_amount(case: &str, expected: &str, actual: f64) {
+    let expected = u256_to_f64(hex_string_to_u256(expected));
+    let tolerance = (expected * 1e-10).max(1.0);
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{}: expected {}, got {}",
+        case,
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn test_swaps_match_exact_integer_maths() {
+    let outputs: SwapOutputs = serde_json::from_str(SWAP_OUTPUTS).unwrap();
+    assert!(!outputs.cases.is_empty());
+
+    for case in &outputs.cases {
+        let (in_unit, out_unit) = case.units();
+        let amount_in = u256_to_f64(hex_string_to_u256(&case.amount_in)) / in_unit;
+        let leg = simulate_swap(&case.book(), &case.from_asset, amount_in, case.fees()).unwrap();
+
+        assert_amount(&case.name, &case.amount_out, leg.amount_out * out_unit);
+        assert_amount(&case.name, &case.unfilled, leg.unfilled * in_unit);
+
+        // and the same from the pool quoted the other way round
+        let leg = simulate_swap(
+            &case.book().inverse(),
+            &case.from_asset,
+            amount_in,
+            case.fees(),
+        )
+        .unwrap();
+        assert_amount(&case.name, &case.amount_out, leg.amount_out * out_unit);
+    }
+}